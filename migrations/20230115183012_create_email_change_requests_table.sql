@@ -0,0 +1,9 @@
+-- Create Email Change Requests Table
+CREATE TABLE email_change_requests(
+    email_change_token TEXT NOT NULL,
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    new_email TEXT NOT NULL,
+    requested_at timestamptz NOT NULL,
+    PRIMARY KEY (email_change_token)
+);
@@ -0,0 +1,11 @@
+-- Subscriptions removed as duplicates of `kept_id` when emails became
+-- case-insensitive, so that they can be looked into
+CREATE TABLE removed_duplicate_subscriptions(
+    id uuid NOT NULL PRIMARY KEY,
+    email TEXT NOT NULL,
+    name TEXT NOT NULL,
+    subscribed_at timestamptz NOT NULL,
+    status TEXT NOT NULL,
+    kept_id uuid NOT NULL,
+    removed_at timestamptz NOT NULL DEFAULT now()
+);
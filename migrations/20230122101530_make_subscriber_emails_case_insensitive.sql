@@ -0,0 +1,6 @@
+-- Existing emails have been brought to the form `SubscriberEmail::parse` gives
+-- them by now, one subscription for each: see `normalize_subscriber_emails`
+-- in `src/migrations.rs`.
+
+-- `Foo@example.com` and `foo@example.com` are the same subscriber
+CREATE UNIQUE INDEX subscriptions_email_lower_idx ON subscriptions (lower(email));
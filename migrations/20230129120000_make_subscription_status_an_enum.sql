@@ -0,0 +1,40 @@
+-- Turn the free-text `status` column into an enum
+CREATE TYPE subscription_status AS ENUM (
+    'pending',
+    'confirmed',
+    'unsubscribed',
+    'bounced',
+    'complained',
+    'deleted'
+);
+ALTER TABLE subscriptions
+    ALTER COLUMN status TYPE subscription_status
+    USING (
+        CASE status
+            WHEN 'pending_confirmation' THEN 'pending'
+            ELSE status
+        END
+    )::subscription_status;
+
+-- When the subscriber last entered these states
+ALTER TABLE subscriptions ADD COLUMN confirmed_at timestamptz NULL;
+ALTER TABLE subscriptions ADD COLUMN unsubscribed_at timestamptz NULL;
+
+-- Every status change, the initial `pending` included
+CREATE TABLE subscription_status_history(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    from_status subscription_status NULL,
+    to_status subscription_status NOT NULL,
+    changed_at timestamptz NOT NULL
+);
+CREATE INDEX subscription_status_history_subscriber_id_idx
+    ON subscription_status_history (subscriber_id, changed_at);
+
+-- Existing subscribers: when they confirmed isn't known, so assume they did
+-- right away, and their history starts at their current status
+UPDATE subscriptions SET confirmed_at = subscribed_at WHERE status = 'confirmed';
+INSERT INTO subscription_status_history (id, subscriber_id, from_status, to_status, changed_at)
+SELECT md5('initial status of ' || id::text)::uuid, id, NULL, status, subscribed_at
+FROM subscriptions;
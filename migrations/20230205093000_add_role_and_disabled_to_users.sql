@@ -0,0 +1,6 @@
+CREATE TYPE user_role AS ENUM ('owner', 'editor', 'analyst', 'support');
+-- Existing users could do everything so far: they become owners
+ALTER TABLE users ADD COLUMN role user_role NOT NULL DEFAULT 'owner';
+ALTER TABLE users ALTER COLUMN role DROP DEFAULT;
+-- Disabled users can't log in, their sessions are rejected
+ALTER TABLE users ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT false;
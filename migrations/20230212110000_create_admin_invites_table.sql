@@ -0,0 +1,15 @@
+CREATE TABLE admin_invites(
+    invite_id uuid NOT NULL,
+    PRIMARY KEY (invite_id),
+    email TEXT NOT NULL,
+    role user_role NOT NULL,
+    -- SHA-256 of the token sent by email, which is never stored
+    invite_token_hash TEXT NOT NULL UNIQUE,
+    invited_by uuid NOT NULL REFERENCES users (user_id),
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    -- At most one of these is set
+    accepted_at timestamptz NULL,
+    accepted_by uuid NULL REFERENCES users (user_id),
+    revoked_at timestamptz NULL
+);
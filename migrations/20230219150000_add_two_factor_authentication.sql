@@ -0,0 +1,22 @@
+-- Base32 TOTP secret, set once the user has confirmed enrollment with a code
+ALTER TABLE users ADD COLUMN totp_secret TEXT NULL;
+-- Last time step a code was accepted for, so codes can't be replayed
+ALTER TABLE users ADD COLUMN totp_last_used_step BIGINT NULL;
+
+CREATE TABLE two_factor_recovery_codes(
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    -- SHA-256 of the code, which is only shown to the user once
+    code_hash TEXT NOT NULL,
+    PRIMARY KEY (user_id, code_hash),
+    created_at timestamptz NOT NULL,
+    used_at timestamptz NULL
+);
+
+-- Installation-wide security settings, kept in a single row
+CREATE TABLE security_settings(
+    id BOOLEAN NOT NULL DEFAULT TRUE,
+    PRIMARY KEY (id),
+    CHECK (id),
+    require_two_factor BOOLEAN NOT NULL DEFAULT FALSE
+);
+INSERT INTO security_settings (id) VALUES (TRUE);
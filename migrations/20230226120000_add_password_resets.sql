@@ -0,0 +1,19 @@
+-- Where password reset links are sent, known for users who joined through an invite
+ALTER TABLE users ADD COLUMN email TEXT NULL;
+UPDATE users SET email = admin_invites.email
+FROM admin_invites
+WHERE admin_invites.accepted_by = users.user_id;
+
+-- Sessions started before this point in time are no longer accepted
+ALTER TABLE users ADD COLUMN sessions_revoked_at timestamptz NULL;
+
+CREATE TABLE password_resets(
+    reset_id uuid NOT NULL,
+    PRIMARY KEY (reset_id),
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    -- HMAC of the token sent by email, keyed with the application secret
+    reset_token_signature TEXT NOT NULL UNIQUE,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    used_at timestamptz NULL
+);
@@ -0,0 +1,14 @@
+-- One row per logged-in session, so that users can see and end them.
+-- Replaces the single per-user revocation timestamp.
+CREATE TABLE user_sessions(
+    session_id uuid NOT NULL,
+    PRIMARY KEY (session_id),
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL,
+    last_seen_at timestamptz NOT NULL,
+    ip TEXT NOT NULL,
+    user_agent TEXT NOT NULL,
+    revoked_at timestamptz NULL
+);
+CREATE INDEX user_sessions_user_id_idx ON user_sessions (user_id);
+ALTER TABLE users DROP COLUMN sessions_revoked_at;
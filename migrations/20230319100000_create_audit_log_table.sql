@@ -0,0 +1,29 @@
+-- Who did what, from where. Rows are only ever added.
+CREATE TABLE audit_log(
+    event_id uuid NOT NULL,
+    PRIMARY KEY (event_id),
+    occurred_at timestamptz NOT NULL,
+    -- NULL for actions of anonymous visitors, e.g. subscribing or a failed login
+    actor_id uuid NULL REFERENCES users (user_id),
+    action TEXT NOT NULL,
+    target TEXT NULL,
+    ip TEXT NOT NULL,
+    user_agent TEXT NOT NULL,
+    request_id uuid NOT NULL,
+    -- `{"field": {"from": ..., "to": ...}}` for what the action changed
+    diff jsonb NOT NULL
+);
+CREATE INDEX audit_log_occurred_at_idx ON audit_log (occurred_at);
+
+CREATE FUNCTION reject_audit_log_changes() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'The audit log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_is_append_only
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION reject_audit_log_changes();
+CREATE TRIGGER audit_log_cannot_be_truncated
+    BEFORE TRUNCATE ON audit_log
+    FOR EACH STATEMENT EXECUTE FUNCTION reject_audit_log_changes();
@@ -0,0 +1,13 @@
+-- Owners can make a user sign in through emailed links only
+ALTER TABLE users ADD COLUMN password_login_disabled BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE magic_links(
+    link_id uuid NOT NULL,
+    PRIMARY KEY (link_id),
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    -- HMAC of the token sent by email, keyed with the application secret
+    token_signature TEXT NOT NULL UNIQUE,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    used_at timestamptz NULL
+);
@@ -0,0 +1,2 @@
+-- The `sub` claim of the user at the single sign-on provider, once they have used it
+ALTER TABLE users ADD COLUMN oidc_subject TEXT NULL UNIQUE;
@@ -0,0 +1,17 @@
+-- WebAuthn credentials, usable as a second factor or to log in without a password
+CREATE TABLE passkeys(
+    passkey_id uuid NOT NULL,
+    PRIMARY KEY (passkey_id),
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- As the browser reports it, base64url-encoded
+    credential_id TEXT NOT NULL UNIQUE,
+    -- COSE key, with the algorithm it signs with
+    public_key BYTEA NOT NULL,
+    algorithm INTEGER NOT NULL,
+    -- Counter reported by the authenticator, to detect cloned keys
+    sign_count BIGINT NOT NULL,
+    created_at timestamptz NOT NULL,
+    last_used_at timestamptz NULL
+);
+CREATE INDEX passkeys_user_id_idx ON passkeys (user_id);
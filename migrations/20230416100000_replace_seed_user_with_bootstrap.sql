@@ -0,0 +1,27 @@
+-- Set for users who have to pick a new password before doing anything else
+ALTER TABLE users ADD COLUMN password_change_required BOOLEAN NOT NULL DEFAULT false;
+
+-- One-time links to create the first owner, logged at startup while there are
+-- no users. Only a hash of the token is stored.
+CREATE TABLE setup_tokens(
+    token_hash TEXT NOT NULL,
+    PRIMARY KEY (token_hash),
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL
+);
+
+-- `20230102194529_seed_user.sql` gave every installation an `admin` user whose
+-- password is public. Where it has never been used, it goes: the first owner is
+-- now created at startup instead. Its activity would be referenced elsewhere,
+-- and API tokens (perhaps older than the audit log) would go with it.
+DELETE FROM users u
+WHERE u.user_id = 'ddf8994f-d522-4659-8d02-c1d479057be6'
+    AND u.password_hash = '$argon2id$v=19$m=15000,t=2,p=1$OEx/rcq+3ts//WUDzGNl2g$Am8UFBA4w5NJEmAtquGvBmAlu92q/VQcaoL5AyJPfc8'
+    AND NOT EXISTS (SELECT 1 FROM user_sessions s WHERE s.user_id = u.user_id)
+    AND NOT EXISTS (SELECT 1 FROM audit_log a WHERE a.actor_id = u.user_id)
+    AND NOT EXISTS (SELECT 1 FROM admin_invites i WHERE i.invited_by = u.user_id)
+    AND NOT EXISTS (SELECT 1 FROM api_tokens t WHERE t.user_id = u.user_id);
+
+-- Elsewhere it is still there, and still has to change its password
+UPDATE users SET password_change_required = true
+WHERE password_hash = '$argon2id$v=19$m=15000,t=2,p=1$OEx/rcq+3ts//WUDzGNl2g$Am8UFBA4w5NJEmAtquGvBmAlu92q/VQcaoL5AyJPfc8';
@@ -0,0 +1,306 @@
+use crate::utils::{client_ip, user_agent};
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+use sqlx::{PgExecutor, PgPool};
+use std::future::{ready, Ready};
+use tracing_actix_web::RequestId;
+use uuid::Uuid;
+
+/// What an audit log entry records, as stored in its `action` column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    LoginSucceeded,
+    LoginFailed,
+    LoggedOut,
+    PasswordChanged,
+    PasswordResetRequested,
+    PasswordReset,
+    MagicLinkRequested,
+    UserCreated,
+    UserCreatedBySso,
+    OwnerBootstrapped,
+    NewsletterPublished,
+    SubscriberAdded,
+    SubscriberConfirmed,
+    SubscriberEmailChanged,
+    SubscriberStatusChanged,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 15] = [
+        AuditAction::LoginSucceeded,
+        AuditAction::LoginFailed,
+        AuditAction::LoggedOut,
+        AuditAction::PasswordChanged,
+        AuditAction::PasswordResetRequested,
+        AuditAction::PasswordReset,
+        AuditAction::MagicLinkRequested,
+        AuditAction::UserCreated,
+        AuditAction::UserCreatedBySso,
+        AuditAction::OwnerBootstrapped,
+        AuditAction::NewsletterPublished,
+        AuditAction::SubscriberAdded,
+        AuditAction::SubscriberConfirmed,
+        AuditAction::SubscriberEmailChanged,
+        AuditAction::SubscriberStatusChanged,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::LoginSucceeded => "login_succeeded",
+            AuditAction::LoginFailed => "login_failed",
+            AuditAction::LoggedOut => "logged_out",
+            AuditAction::PasswordChanged => "password_changed",
+            AuditAction::PasswordResetRequested => "password_reset_requested",
+            AuditAction::PasswordReset => "password_reset",
+            AuditAction::MagicLinkRequested => "magic_link_requested",
+            AuditAction::UserCreated => "user_created",
+            AuditAction::UserCreatedBySso => "user_created_by_sso",
+            AuditAction::OwnerBootstrapped => "owner_bootstrapped",
+            AuditAction::NewsletterPublished => "newsletter_published",
+            AuditAction::SubscriberAdded => "subscriber_added",
+            AuditAction::SubscriberConfirmed => "subscriber_confirmed",
+            AuditAction::SubscriberEmailChanged => "subscriber_email_changed",
+            AuditAction::SubscriberStatusChanged => "subscriber_status_changed",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|action| action.as_str() == s)
+    }
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+/// Where a request came from, recorded with every audit log entry it leads to.
+#[derive(Debug, Clone)]
+pub struct RequestOrigin {
+    pub ip: String,
+    pub user_agent: String,
+    pub request_id: Uuid,
+}
+
+impl RequestOrigin {
+    pub fn of(request: &HttpRequest) -> Self {
+        // Set by `TracingLogger`, so that entries can be matched with the logs of the request
+        let request_id = request
+            .extensions()
+            .get::<RequestId>()
+            .map(|id| **id)
+            .unwrap_or_else(Uuid::new_v4);
+        Self {
+            ip: client_ip(request),
+            user_agent: user_agent(request).to_string(),
+            request_id,
+        }
+    }
+
+    /// For what the application does on its own, e.g. at startup.
+    pub fn internal() -> Self {
+        Self {
+            ip: "internal".into(),
+            user_agent: "zero2prod".into(),
+            request_id: Uuid::new_v4(),
+        }
+    }
+}
+
+impl FromRequest for RequestOrigin {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(Self::of(req)))
+    }
+}
+
+/// An entry for the audit log, e.g.
+/// `AuditEntry::new(AuditAction::SubscriberConfirmed).target(id).change("status", "pending", "confirmed")`.
+pub struct AuditEntry {
+    action: AuditAction,
+    actor_id: Option<Uuid>,
+    target: Option<String>,
+    diff: Map<String, Value>,
+}
+
+impl AuditEntry {
+    pub fn new(action: AuditAction) -> Self {
+        Self {
+            action,
+            actor_id: None,
+            target: None,
+            diff: Map::new(),
+        }
+    }
+
+    /// The admin user who took the action. Left out for anonymous visitors.
+    pub fn actor(mut self, user_id: Uuid) -> Self {
+        self.actor_id = Some(user_id);
+        self
+    }
+
+    /// What the action was taken on, e.g. a subscriber id or the username of a failed login.
+    pub fn target(mut self, target: impl std::fmt::Display) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    /// Record that `field` went from `from` to `to`. `from` is `None` for something new.
+    pub fn change(mut self, field: &str, from: impl serde::Serialize, to: impl serde::Serialize) -> Self {
+        self.diff.insert(field.to_string(), json!({ "from": from, "to": to }));
+        self
+    }
+
+    /// Append the entry to the audit log. Pass the transaction of the change
+    /// itself, if there is one, so that both are kept or lost together.
+    #[tracing::instrument(name = "Record an audit log entry", skip(self, executor, origin), fields(action = %self.action))]
+    pub async fn record<'c>(self, executor: impl PgExecutor<'c>, origin: &RequestOrigin) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO audit_log (event_id, occurred_at, actor_id, action, target, ip, user_agent, request_id, diff)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+            Uuid::new_v4(),
+            Utc::now(),
+            self.actor_id,
+            self.action.as_str(),
+            self.target,
+            origin.ip,
+            origin.user_agent,
+            origin.request_id,
+            Value::Object(self.diff),
+        )
+        .execute(executor)
+        .await
+        .context("Failed to record an audit log entry.")?;
+        Ok(())
+    }
+}
+
+/// An entry as shown on the audit log page and in its export.
+pub struct AuditLogEntry {
+    pub occurred_at: DateTime<Utc>,
+    // The username, `None` for anonymous visitors
+    pub actor: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub ip: String,
+    pub user_agent: String,
+    pub request_id: Uuid,
+    pub diff: Value,
+}
+
+/// Which entries to list. Every filter left out matches all entries.
+#[derive(Debug, Default)]
+pub struct AuditLogFilter {
+    pub action: Option<AuditAction>,
+    pub actor: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// The entries matching `filter`, most recent first, at most `limit` of them if given.
+#[tracing::instrument(name = "Get audit log entries", skip(pool))]
+pub async fn get_audit_log(
+    pool: &PgPool,
+    filter: &AuditLogFilter,
+    limit: Option<i64>,
+) -> Result<Vec<AuditLogEntry>, anyhow::Error> {
+    let entries = sqlx::query_as!(
+        AuditLogEntry,
+        r#"
+        SELECT a.occurred_at, u.username AS "actor?", a.action, a.target, a.ip, a.user_agent, a.request_id, a.diff
+        FROM audit_log a
+        LEFT JOIN users u ON u.user_id = a.actor_id
+        WHERE ($1::text IS NULL OR a.action = $1)
+            AND ($2::text IS NULL OR u.username = $2)
+            AND ($3::timestamptz IS NULL OR a.occurred_at >= $3)
+            AND ($4::timestamptz IS NULL OR a.occurred_at < $4)
+        ORDER BY a.occurred_at DESC
+        LIMIT $5
+        "#,
+        filter.action.map(|action| action.as_str()),
+        filter.actor,
+        filter.since,
+        filter.until,
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve audit log entries.")?;
+    Ok(entries)
+}
+
+/// `entries` as CSV, one line per entry after a header line.
+pub fn to_csv(entries: &[AuditLogEntry]) -> String {
+    let mut csv = String::from("occurred_at,actor,action,target,ip,user_agent,request_id,diff\r\n");
+    for entry in entries {
+        let fields = [
+            entry.occurred_at.to_rfc3339(),
+            entry.actor.clone().unwrap_or_default(),
+            entry.action.clone(),
+            entry.target.clone().unwrap_or_default(),
+            entry.ip.clone(),
+            entry.user_agent.clone(),
+            entry.request_id.to_string(),
+            entry.diff.to_string(),
+        ];
+        let line: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&line.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+// Quoted, and defused for spreadsheets: targets and user agents come from the
+// outside world, and a cell starting with `=` would be run as a formula.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn actions_round_trip_through_their_name() {
+        for action in AuditAction::ALL {
+            assert_eq!(AuditAction::parse(action.as_str()), Some(action));
+        }
+        assert_eq!(AuditAction::parse("dropped_tables"), None);
+    }
+
+    #[test]
+    fn changes_are_recorded_as_from_and_to() {
+        let entry = AuditEntry::new(AuditAction::SubscriberEmailChanged)
+            .change("email", "old@example.com", "new@example.com")
+            .change("name", None::<String>, "Ursula");
+
+        assert_eq!(
+            Value::Object(entry.diff),
+            json!({
+                "email": { "from": "old@example.com", "to": "new@example.com" },
+                "name": { "from": null, "to": "Ursula" },
+            })
+        );
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_and_defused() {
+        assert_eq!(csv_field("Mozilla/5.0"), r#""Mozilla/5.0""#);
+        assert_eq!(csv_field(r#"say "hi", twice"#), r#""say ""hi"", twice""#);
+        assert_eq!(csv_field("=HYPERLINK(\"http://evil\")"), r#""'=HYPERLINK(""http://evil"")""#);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::authentication::{AuthError, Permission};
+use actix_web::http::header::HeaderMap;
+use anyhow::Context;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// Makes leaked tokens easy to recognise, for us and for secret scanners
+const TOKEN_PREFIX: &str = "z2p_";
+
+/// What an API token is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiScope {
+    PublishNewsletters,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 1] = [ApiScope::PublishNewsletters];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::PublishNewsletters => "newsletter:publish",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|scope| scope.as_str() == s)
+    }
+
+    /// What the token's owner must be allowed to do, for the scope to be granted and used.
+    pub fn permission(&self) -> Permission {
+        match self {
+            ApiScope::PublishNewsletters => Permission::PublishNewsletters,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The user an API token belongs to, along with what the token allows.
+#[derive(Debug)]
+pub struct ApiTokenOwner {
+    pub user_id: Uuid,
+    pub scopes: Vec<ApiScope>,
+}
+
+pub fn generate_api_token() -> Secret<String> {
+    let random: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(40)
+        .collect();
+    Secret::new(format!("{}{}", TOKEN_PREFIX, random))
+}
+
+/// API tokens are random enough for a plain SHA-256 to be a safe way to store them.
+pub fn hash_api_token(token: &Secret<String>) -> String {
+    format!("{:x}", Sha256::digest(token.expose_secret().as_bytes()))
+}
+
+/// The token of an `Authorization: Bearer` header, if that is how the request authenticates.
+pub fn bearer_token(headers: &HeaderMap) -> Option<Secret<String>> {
+    headers
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| Secret::new(token.trim().to_string()))
+}
+
+/// Look up who an API token belongs to and record that it has been used.
+/// Revoked and expired tokens, and those of disabled users, are rejected.
+#[tracing::instrument(name = "Validate an API token", skip(token, pool), fields(token_id = tracing::field::Empty))]
+pub async fn validate_api_token(token: &Secret<String>, pool: &PgPool) -> Result<ApiTokenOwner, AuthError> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_tokens SET last_used_at = now()
+        FROM users
+        WHERE api_tokens.token_hash = $1
+            AND api_tokens.revoked_at IS NULL
+            AND (api_tokens.expires_at IS NULL OR api_tokens.expires_at > now())
+            AND users.user_id = api_tokens.user_id
+            AND NOT users.disabled
+        RETURNING api_tokens.token_id, api_tokens.user_id, api_tokens.scopes
+        "#,
+        hash_api_token(token),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to validate an API token.")
+    .map_err(AuthError::UnexpectedError)?
+    .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown, revoked or expired API token.")))?;
+
+    tracing::Span::current().record("token_id", &tracing::field::display(&row.token_id));
+    Ok(ApiTokenOwner {
+        user_id: row.user_id,
+        // Scopes we no longer know about grant nothing
+        scopes: row.scopes.iter().filter_map(|s| ApiScope::parse(s)).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bearer_token, generate_api_token, hash_api_token, ApiScope};
+    use actix_web::http::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+    use secrecy::ExposeSecret;
+
+    #[test]
+    fn scopes_round_trip() {
+        for scope in ApiScope::ALL {
+            assert_eq!(ApiScope::parse(scope.as_str()), Some(scope));
+        }
+        assert_eq!(ApiScope::parse("newsletter:delete"), None);
+    }
+
+    #[test]
+    fn tokens_are_prefixed_and_unique() {
+        let (a, b) = (generate_api_token(), generate_api_token());
+        assert!(a.expose_secret().starts_with("z2p_"));
+        assert_ne!(hash_api_token(&a), hash_api_token(&b));
+    }
+
+    #[test]
+    fn only_bearer_headers_carry_a_token() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Basic dXNlcjpwYXNz"));
+        assert!(bearer_token(&headers).is_none());
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer z2p_abc"));
+        assert_eq!(bearer_token(&headers).unwrap().expose_secret(), "z2p_abc");
+    }
+}
@@ -1,5 +1,5 @@
-use actix_web_lab::middleware::Next;
-use actix_web::body::MessageBody;
+use actix_web_lab::middleware::{from_fn, MiddlewareFn, Next};
+use actix_web::body::{BoxBody, EitherBody, MessageBody};
 use actix_web::dev::{ServiceRequest, ServiceResponse};
 use actix_web::FromRequest;
 use crate::session_state::TypedSession;
@@ -7,7 +7,17 @@ use actix_web::error::InternalError;
 use crate::utils::{e500, see_other};
 use uuid::Uuid;
 use std::ops::Deref;
-use actix_web::HttpMessage;
+use actix_web::{web, HttpMessage, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use crate::configuration::SessionSettings;
+use std::future::Future;
+use std::pin::Pin;
+use crate::authentication::{
+    get_enabled_user_role, password_change_required, touch_session, two_factor_enabled, two_factor_required, Permission,
+    Role,
+};
 
 #[derive(Copy, Clone, Debug)]
 pub struct UserId(Uuid);
@@ -43,6 +53,134 @@ pub async fn reject_anonymous_users(mut req: ServiceRequest, next: Next<impl Mes
             Err(InternalError::from_response(e, response).into())
         }
     }
+}
+
+// Look up the role of the user `reject_anonymous_users` let through and make it
+// available to handlers as `web::ReqData<Role>`. Disabled users and sessions
+// that were revoked or timed out are logged out.
+pub async fn load_user_role<B: MessageBody>(mut req: ServiceRequest, next: Next<B>) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let user_id = req
+        .extensions()
+        .get::<UserId>()
+        .copied()
+        .context("`load_user_role` must run after `reject_anonymous_users`")
+        .map_err(e500)?;
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .context("The database pool is missing from the application data")
+        .map_err(e500)?
+        .clone();
+    let settings = req
+        .app_data::<web::Data<SessionSettings>>()
+        .context("The session settings are missing from the application data")
+        .map_err(e500)?
+        .clone();
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+
+    // Sessions from before the registry have no id and are treated as expired
+    let active = match session.get_session_id().map_err(e500)? {
+        Some(session_id) => touch_session(&pool, &settings, *user_id, session_id).await.map_err(e500)?,
+        None => false,
+    };
+    if !active {
+        session.log_out();
+        FlashMessage::error("Your session has expired, please log in again.").send();
+        return Ok(req.into_response(see_other("/login")).map_into_right_body());
+    }
+    match get_enabled_user_role(&pool, *user_id).await.map_err(e500)? {
+        Some(role) => {
+            req.extensions_mut().insert(role);
+            Ok(next.call(req).await?.map_into_left_body())
+        }
+        None => {
+            session.log_out();
+            FlashMessage::error("Your account has been disabled.").send();
+            // A response rather than an error, so that the flash message is kept
+            Ok(req.into_response(see_other("/login")).map_into_right_body())
+        }
+    }
+}
+
+// Send users without two-factor authentication to the enrollment page when an
+// owner requires it. Adding a passkey instead is allowed too. Relies on `reject_anonymous_users` running first.
+pub async fn enforce_two_factor_enrollment(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let path = req.path();
+    if path.starts_with("/admin/two_factor") || path.starts_with("/admin/passkeys") || path == "/admin/logout" {
+        return next.call(req).await;
+    }
+    let user_id = req
+        .extensions()
+        .get::<UserId>()
+        .copied()
+        .context("`enforce_two_factor_enrollment` must run after `reject_anonymous_users`")
+        .map_err(e500)?;
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .context("The database pool is missing from the application data")
+        .map_err(e500)?
+        .clone();
 
-    todo!()
-}
\ No newline at end of file
+    if two_factor_required(&pool).await.map_err(e500)?
+        && !two_factor_enabled(&pool, *user_id).await.map_err(e500)?
+    {
+        let e = anyhow::anyhow!("The user has not enabled two-factor authentication");
+        return Err(InternalError::from_response(e, see_other("/admin/two_factor")).into());
+    }
+    next.call(req).await
+}
+
+// Keep users who still have a well-known password on the password page until
+// they change it. Relies on `reject_anonymous_users` running first.
+pub async fn enforce_password_change(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let path = req.path();
+    if path == "/admin/password" || path == "/admin/logout" {
+        return next.call(req).await;
+    }
+    let user_id = req
+        .extensions()
+        .get::<UserId>()
+        .copied()
+        .context("`enforce_password_change` must run after `reject_anonymous_users`")
+        .map_err(e500)?;
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .context("The database pool is missing from the application data")
+        .map_err(e500)?
+        .clone();
+
+    if password_change_required(&pool, *user_id).await.map_err(e500)? {
+        let e = anyhow::anyhow!("The user must change their password");
+        return Err(InternalError::from_response(e, see_other("/admin/password")).into());
+    }
+    next.call(req).await
+}
+
+type PermissionCheck = Pin<Box<dyn Future<Output = Result<ServiceResponse<BoxBody>, actix_web::Error>>>>;
+
+/// Route middleware letting through users whose role grants `permission`.
+/// Relies on `load_user_role` running first.
+pub fn require_permission(
+    permission: Permission,
+) -> MiddlewareFn<impl Fn(ServiceRequest, Next<BoxBody>) -> PermissionCheck> {
+    from_fn(move |req: ServiceRequest, next: Next<BoxBody>| -> PermissionCheck {
+        Box::pin(check_permission(permission, req, next))
+    })
+}
+
+async fn check_permission(
+    permission: Permission,
+    req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let role = req.extensions().get::<Role>().copied();
+    match role {
+        Some(role) if role.can(permission) => next.call(req).await,
+        _ => {
+            let e = anyhow::anyhow!("The user's role does not grant {:?}", permission);
+            Err(InternalError::from_response(e, HttpResponse::Forbidden().finish()).into())
+        }
+    }
+}
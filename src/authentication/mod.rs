@@ -1,5 +1,37 @@
+mod api_tokens;
 mod middleware;
+mod oidc;
+mod passkeys;
 mod password;
-pub use middleware::reject_anonymous_users;
+mod password_policy;
+mod roles;
+mod sessions;
+mod totp;
+mod two_factor;
+mod users;
+pub use api_tokens::{
+    bearer_token, generate_api_token, hash_api_token, validate_api_token, ApiScope, ApiTokenOwner,
+};
+pub use middleware::{enforce_password_change, enforce_two_factor_enrollment, load_user_role, reject_anonymous_users, require_permission};
 pub use middleware::UserId;
-pub use password::{change_password, validate_credentials, AuthError, Credentials};
\ No newline at end of file
+pub use oidc::{OidcClient, OidcIdentity, PendingOidcLogin};
+pub use passkeys::{
+    find_passkey, generate_passkey_challenge, get_passkey_credential_ids, has_passkeys, record_passkey_use,
+    store_passkey, AssertionResponse, NewPasskey, RegistrationResponse, RelyingParty, StoredPasskey,
+};
+pub use password::{
+    basic_authentication, change_password, hash_password, password_change_required, password_login_enabled,
+    validate_credentials, AuthError, Credentials,
+};
+pub use password_policy::{check_password_policy, PasswordPolicyError, MAX_PASSWORD_LENGTH, MIN_PASSWORD_LENGTH};
+pub use roles::{get_enabled_user_role, Permission, Role};
+pub use sessions::{get_active_sessions, revoke_session, revoke_user_sessions, start_session, touch_session, ActiveSession};
+pub use totp::{
+    generate_recovery_codes, generate_totp_secret, hash_recovery_code, qr_code_svg, totp_code,
+    totp_provisioning_uri, verify_totp_code,
+};
+pub use two_factor::{
+    disable_two_factor, enable_two_factor, set_two_factor_required, totp_enabled, two_factor_enabled,
+    two_factor_required, verify_second_factor,
+};
+pub use users::{create_admin_user, CreateUserError, NewAdminUser};
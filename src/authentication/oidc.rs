@@ -0,0 +1,311 @@
+use crate::authentication::Role;
+use crate::configuration::OidcSettings;
+use anyhow::Context;
+use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::ExposeSecret;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use url::Url;
+
+// Only signatures made with the provider's private key: an `HS256` token
+// would otherwise be checked against its public key, known to everyone.
+const ACCEPTED_ALGORITHMS: [Algorithm; 6] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::ES256,
+    Algorithm::ES384,
+];
+
+// For each call to the provider
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Logs users in through the OpenID Connect provider of `OidcSettings`.
+pub struct OidcClient {
+    settings: OidcSettings,
+    redirect_url: String,
+    http_client: reqwest::Client,
+}
+
+/// Kept in the session between sending the user to the provider and their return.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PendingOidcLogin {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+/// Who the provider says the user is.
+#[derive(Debug)]
+pub struct OidcIdentity {
+    pub subject: String,
+    // Only if the provider has verified it
+    pub email: Option<String>,
+    pub preferred_username: Option<String>,
+    pub groups: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(serde::Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(serde::Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    preferred_username: Option<String>,
+    #[serde(flatten)]
+    other: HashMap<String, Value>,
+}
+
+impl OidcClient {
+    pub fn new(settings: OidcSettings, base_url: &str) -> Self {
+        Self {
+            settings,
+            redirect_url: format!("{}/login/oidc/callback", base_url),
+            http_client: reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build().unwrap(),
+        }
+    }
+
+    pub fn display_name(&self) -> &str {
+        &self.settings.display_name
+    }
+
+    pub fn create_users(&self) -> bool {
+        self.settings.create_users
+    }
+
+    pub fn link_by_email(&self) -> bool {
+        self.settings.link_by_email
+    }
+
+    pub fn sync_roles(&self) -> bool {
+        self.settings.sync_roles
+    }
+
+    /// Where to send the user to log in, along with what to check on their return.
+    #[tracing::instrument(name = "Start an OIDC login", skip(self))]
+    pub async fn authorization_url(&self) -> Result<(Url, PendingOidcLogin), anyhow::Error> {
+        let metadata = self.provider_metadata().await?;
+        let pending = PendingOidcLogin {
+            state: random_string(32),
+            nonce: random_string(32),
+            code_verifier: random_string(64),
+        };
+        let mut url = Url::parse(&metadata.authorization_endpoint).context("Invalid authorization endpoint.")?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.settings.client_id)
+            .append_pair("redirect_uri", &self.redirect_url)
+            .append_pair("scope", &self.settings.scopes.join(" "))
+            .append_pair("state", &pending.state)
+            .append_pair("nonce", &pending.nonce)
+            .append_pair("code_challenge", &code_challenge(&pending.code_verifier))
+            .append_pair("code_challenge_method", "S256");
+        Ok((url, pending))
+    }
+
+    /// Trade the code the provider sent the user back with for their verified identity.
+    #[tracing::instrument(name = "Finish an OIDC login", skip(self, code, pending))]
+    pub async fn exchange_code(&self, code: &str, pending: &PendingOidcLogin) -> Result<OidcIdentity, anyhow::Error> {
+        let metadata = self.provider_metadata().await?;
+        let response: TokenResponse = self
+            .http_client
+            .post(&metadata.token_endpoint)
+            .basic_auth(&self.settings.client_id, Some(self.settings.client_secret.expose_secret()))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.redirect_url),
+                ("code_verifier", &pending.code_verifier),
+            ])
+            .send()
+            .await
+            .context("Failed to call the token endpoint.")?
+            .error_for_status()
+            .context("The token endpoint refused the code.")?
+            .json()
+            .await
+            .context("Invalid token endpoint response.")?;
+
+        let keys: JwkSet = self
+            .http_client
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .context("Failed to retrieve the provider's keys.")?
+            .error_for_status()
+            .context("Failed to retrieve the provider's keys.")?
+            .json()
+            .await
+            .context("Invalid provider keys.")?;
+        let claims = self.verify_id_token(&response.id_token, &keys)?;
+        if claims.nonce.as_deref() != Some(pending.nonce.as_str()) {
+            anyhow::bail!("The ID token was not issued for this login.");
+        }
+        Ok(OidcIdentity {
+            groups: groups(claims.other.get(&self.settings.groups_claim)),
+            email: claims.email.filter(|_| claims.email_verified),
+            preferred_username: claims.preferred_username,
+            subject: claims.sub,
+        })
+    }
+
+    /// The role for someone in `groups`, if they are to have access at all.
+    pub fn role_for(&self, groups: &[String]) -> Option<Role> {
+        self.settings
+            .role_mappings
+            .iter()
+            .find(|mapping| groups.contains(&mapping.group))
+            .map(|mapping| mapping.role)
+            .or(self.settings.default_role)
+    }
+
+    fn verify_id_token(&self, id_token: &str, keys: &JwkSet) -> Result<IdTokenClaims, anyhow::Error> {
+        let header = decode_header(id_token).context("Invalid ID token.")?;
+        if !ACCEPTED_ALGORITHMS.contains(&header.alg) {
+            anyhow::bail!("The ID token is signed with {:?}, which is not accepted.", header.alg);
+        }
+        let key = match &header.kid {
+            Some(kid) => keys.find(kid),
+            None if keys.keys.len() == 1 => keys.keys.first(),
+            None => None,
+        }
+        .context("The ID token is signed with an unknown key.")?;
+        if let AlgorithmParameters::OctetKey(_) = key.algorithm {
+            anyhow::bail!("The provider published a symmetric key.");
+        }
+        let key = DecodingKey::from_jwk(key).context("Invalid provider key.")?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.settings.client_id]);
+        validation.set_issuer(&[&self.settings.issuer_url]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let token = decode::<IdTokenClaims>(id_token, &key, &validation).context("The ID token is not valid.")?;
+        Ok(token.claims)
+    }
+
+    // Read on every login, so that key rotations at the provider need no restart
+    async fn provider_metadata(&self) -> Result<ProviderMetadata, anyhow::Error> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.settings.issuer_url.trim_end_matches('/')
+        );
+        let metadata: ProviderMetadata = self
+            .http_client
+            .get(&url)
+            .send()
+            .await
+            .context("Failed to retrieve the provider configuration.")?
+            .error_for_status()
+            .context("Failed to retrieve the provider configuration.")?
+            .json()
+            .await
+            .context("Invalid provider configuration.")?;
+        if metadata.issuer != self.settings.issuer_url {
+            anyhow::bail!("The provider configuration is for another issuer: {}", metadata.issuer);
+        }
+        Ok(metadata)
+    }
+}
+
+fn random_string(length: usize) -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(length)
+        .collect()
+}
+
+// PKCE `S256`: the provider only hands out tokens to whoever knows the verifier
+fn code_challenge(code_verifier: &str) -> String {
+    base64::encode_config(Sha256::digest(code_verifier.as_bytes()), base64::URL_SAFE_NO_PAD)
+}
+
+// Providers give either a list of groups or, for a single one, a string
+fn groups(claim: Option<&Value>) -> Vec<String> {
+    match claim {
+        Some(Value::Array(groups)) => groups.iter().filter_map(|g| g.as_str().map(str::to_string)).collect(),
+        Some(Value::String(group)) => vec![group.clone()],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::OidcRoleMapping;
+    use serde_json::json;
+
+    fn client(role_mappings: Vec<OidcRoleMapping>, default_role: Option<Role>) -> OidcClient {
+        let settings = OidcSettings {
+            issuer_url: "https://idp.example.com".into(),
+            client_id: "newsletter".into(),
+            client_secret: secrecy::Secret::new("secret".into()),
+            display_name: "Example".into(),
+            scopes: vec!["openid".into()],
+            groups_claim: "groups".into(),
+            role_mappings,
+            default_role,
+            create_users: false,
+            link_by_email: false,
+            sync_roles: false,
+        };
+        OidcClient::new(settings, "http://localhost")
+    }
+
+    #[test]
+    fn the_code_challenge_matches_the_rfc_example() {
+        // RFC 7636, appendix B
+        assert_eq!(
+            code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn the_first_matching_group_decides_the_role() {
+        let client = client(
+            vec![
+                OidcRoleMapping { group: "admins".into(), role: Role::Owner },
+                OidcRoleMapping { group: "staff".into(), role: Role::Editor },
+            ],
+            None,
+        );
+
+        assert_eq!(client.role_for(&["staff".into(), "admins".into()]), Some(Role::Owner));
+        assert_eq!(client.role_for(&["staff".into()]), Some(Role::Editor));
+        assert_eq!(client.role_for(&["visitors".into()]), None);
+    }
+
+    #[test]
+    fn users_in_no_mapped_group_get_the_default_role() {
+        let client = client(vec![], Some(Role::Analyst));
+
+        assert_eq!(client.role_for(&[]), Some(Role::Analyst));
+    }
+
+    #[test]
+    fn groups_can_be_a_list_or_a_single_string() {
+        assert_eq!(groups(Some(&json!(["a", "b", 3]))), vec!["a", "b"]);
+        assert_eq!(groups(Some(&json!("a"))), vec!["a"]);
+        assert!(groups(None).is_empty());
+    }
+}
@@ -0,0 +1,493 @@
+use anyhow::Context;
+use chrono::Utc;
+use rand::{thread_rng, Rng};
+use ring::signature::{RsaPublicKeyComponents, UnparsedPublicKey, ECDSA_P256_SHA256_ASN1, RSA_PKCS1_2048_8192_SHA256};
+use serde_cbor::Value;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use url::Url;
+use uuid::Uuid;
+
+// COSE algorithm identifiers we offer to browsers, in order of preference
+const ES256: i32 = -7;
+const RS256: i32 = -257;
+
+// Bits of the authenticator data flags
+const USER_PRESENT: u8 = 0x01;
+const USER_VERIFIED: u8 = 0x04;
+const ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+// How long browsers let the user answer, in milliseconds
+const CEREMONY_TIMEOUT_MS: u32 = 300_000;
+
+/// Who passkeys are registered with: the host the application is served from.
+/// Browsers only hand a passkey to pages of the origin it was registered on.
+#[derive(Debug, Clone)]
+pub struct RelyingParty {
+    id: String,
+    origin: String,
+}
+
+/// What the browser returns when a passkey is created, base64url-encoded.
+#[derive(serde::Deserialize)]
+pub struct RegistrationResponse {
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+/// What the browser returns when a passkey is used, base64url-encoded.
+#[derive(serde::Deserialize)]
+pub struct AssertionResponse {
+    pub credential_id: String,
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+}
+
+/// A passkey the browser has just created, checked and ready to be stored.
+pub struct NewPasskey {
+    pub credential_id: String,
+    pub public_key: Vec<u8>,
+    pub algorithm: i32,
+    pub sign_count: u32,
+}
+
+/// A registered passkey, as needed to check that it signed a login.
+pub struct StoredPasskey {
+    pub passkey_id: Uuid,
+    pub user_id: Uuid,
+    pub public_key: Vec<u8>,
+    pub algorithm: i32,
+    pub sign_count: i64,
+}
+
+#[derive(serde::Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    // Only when a passkey is created: its id and its COSE public key
+    attested_credential: Option<(&'a [u8], Value)>,
+}
+
+impl RelyingParty {
+    pub fn from_base_url(base_url: &str) -> Result<Self, anyhow::Error> {
+        let url = Url::parse(base_url).context("Invalid base URL.")?;
+        let id = url.host_str().context("The base URL has no host.")?.to_string();
+        Ok(Self {
+            id,
+            origin: url.origin().ascii_serialization(),
+        })
+    }
+
+    /// Options for `navigator.credentials.create()`, leaving out the passkeys
+    /// the user already has so that an authenticator is not registered twice.
+    pub fn registration_options(
+        &self,
+        challenge: &str,
+        user_id: Uuid,
+        username: &str,
+        existing_credential_ids: &[String],
+    ) -> serde_json::Value {
+        json!({
+            "challenge": challenge,
+            "rp": { "id": self.id, "name": self.id },
+            "user": {
+                "id": base64::encode_config(user_id.as_bytes(), base64::URL_SAFE_NO_PAD),
+                "name": username,
+                "displayName": username,
+            },
+            "pubKeyCredParams": [
+                { "type": "public-key", "alg": ES256 },
+                { "type": "public-key", "alg": RS256 },
+            ],
+            "excludeCredentials": credential_descriptors(existing_credential_ids),
+            "authenticatorSelection": { "residentKey": "preferred", "userVerification": "preferred" },
+            "attestation": "none",
+            "timeout": CEREMONY_TIMEOUT_MS,
+        })
+    }
+
+    /// Options for `navigator.credentials.get()`. Without allowed credentials
+    /// the browser offers every passkey it has for us, for a passwordless login.
+    pub fn login_options(
+        &self,
+        challenge: &str,
+        allowed_credential_ids: &[String],
+        require_user_verification: bool,
+    ) -> serde_json::Value {
+        json!({
+            "challenge": challenge,
+            "rpId": self.id,
+            "allowCredentials": credential_descriptors(allowed_credential_ids),
+            "userVerification": if require_user_verification { "required" } else { "preferred" },
+            "timeout": CEREMONY_TIMEOUT_MS,
+        })
+    }
+
+    /// Check a newly created passkey. We ask for no attestation, so whatever
+    /// statement the authenticator adds about its make is ignored.
+    pub fn verify_registration(
+        &self,
+        challenge: &str,
+        response: &RegistrationResponse,
+    ) -> Result<NewPasskey, anyhow::Error> {
+        self.check_client_data(&decode(&response.client_data_json)?, "webauthn.create", challenge)?;
+        let attestation: Value =
+            serde_cbor::from_slice(&decode(&response.attestation_object)?).context("Invalid attestation object.")?;
+        let auth_data = match map_entry(&attestation, Value::Text("authData".into())) {
+            Some(Value::Bytes(auth_data)) => auth_data,
+            _ => anyhow::bail!("The attestation object has no authenticator data."),
+        };
+        let auth_data = parse_authenticator_data(auth_data)?;
+        self.check_authenticator_data(&auth_data, false)?;
+        let (credential_id, public_key) = auth_data
+            .attested_credential
+            .context("The authenticator did not return the new credential.")?;
+        let algorithm = cose_key_algorithm(&public_key)?;
+        Ok(NewPasskey {
+            credential_id: base64::encode_config(credential_id, base64::URL_SAFE_NO_PAD),
+            public_key: serde_cbor::to_vec(&public_key).context("Failed to encode a public key.")?,
+            algorithm,
+            sign_count: auth_data.sign_count,
+        })
+    }
+
+    /// Check that `passkey` signed our challenge, and return its new signature counter.
+    pub fn verify_assertion(
+        &self,
+        challenge: &str,
+        response: &AssertionResponse,
+        passkey: &StoredPasskey,
+        require_user_verification: bool,
+    ) -> Result<u32, anyhow::Error> {
+        let client_data_json = decode(&response.client_data_json)?;
+        self.check_client_data(&client_data_json, "webauthn.get", challenge)?;
+        let auth_data_bytes = decode(&response.authenticator_data)?;
+        let auth_data = parse_authenticator_data(&auth_data_bytes)?;
+        self.check_authenticator_data(&auth_data, require_user_verification)?;
+
+        let mut signed = auth_data_bytes.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data_json));
+        verify_signature(passkey, &signed, &decode(&response.signature)?)?;
+        if !sign_count_advanced(passkey.sign_count, auth_data.sign_count) {
+            anyhow::bail!("The signature counter went backwards, the passkey may have been cloned.");
+        }
+        Ok(auth_data.sign_count)
+    }
+
+    fn check_client_data(&self, client_data_json: &[u8], kind: &str, challenge: &str) -> Result<(), anyhow::Error> {
+        let client_data: ClientData = serde_json::from_slice(client_data_json).context("Invalid client data.")?;
+        if client_data.kind != kind {
+            anyhow::bail!("Expected a {} response, got {}.", kind, client_data.kind);
+        }
+        if client_data.challenge != challenge {
+            anyhow::bail!("The response is not for our challenge.");
+        }
+        if client_data.origin != self.origin {
+            anyhow::bail!("The response comes from another origin: {}", client_data.origin);
+        }
+        Ok(())
+    }
+
+    fn check_authenticator_data(
+        &self,
+        auth_data: &AuthenticatorData,
+        require_user_verification: bool,
+    ) -> Result<(), anyhow::Error> {
+        if auth_data.rp_id_hash != Sha256::digest(self.id.as_bytes()).as_slice() {
+            anyhow::bail!("The passkey is for another site.");
+        }
+        if auth_data.flags & USER_PRESENT == 0 {
+            anyhow::bail!("The user was not present.");
+        }
+        if require_user_verification && auth_data.flags & USER_VERIFIED == 0 {
+            anyhow::bail!("The authenticator did not verify the user.");
+        }
+        Ok(())
+    }
+}
+
+/// Random, base64url-encoded challenge for one registration or login.
+pub fn generate_passkey_challenge() -> String {
+    let bytes: [u8; 32] = thread_rng().gen();
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+// Authenticators that keep no counter always report 0. Otherwise a counter that
+// does not move forward means two copies of the key are in use.
+fn sign_count_advanced(stored: i64, reported: u32) -> bool {
+    (stored == 0 && reported == 0) || i64::from(reported) > stored
+}
+
+fn credential_descriptors(credential_ids: &[String]) -> Vec<serde_json::Value> {
+    credential_ids
+        .iter()
+        .map(|id| json!({ "type": "public-key", "id": id }))
+        .collect()
+}
+
+fn decode(field: &str) -> Result<Vec<u8>, anyhow::Error> {
+    base64::decode_config(field, base64::URL_SAFE_NO_PAD).context("Invalid base64url encoding.")
+}
+
+fn map_entry(map: &Value, key: Value) -> Option<&Value> {
+    match map {
+        Value::Map(map) => map.get(&key),
+        _ => None,
+    }
+}
+
+fn cose_bytes(key: &Value, label: i128) -> Result<&[u8], anyhow::Error> {
+    match map_entry(key, Value::Integer(label)) {
+        Some(Value::Bytes(bytes)) => Ok(bytes),
+        _ => anyhow::bail!("The public key has no {} parameter.", label),
+    }
+}
+
+fn cose_integer(key: &Value, label: i128) -> Result<i128, anyhow::Error> {
+    match map_entry(key, Value::Integer(label)) {
+        Some(Value::Integer(value)) => Ok(*value),
+        _ => anyhow::bail!("The public key has no {} parameter.", label),
+    }
+}
+
+// Only the algorithms we offered, with the parameters `verify_signature` needs
+fn cose_key_algorithm(key: &Value) -> Result<i32, anyhow::Error> {
+    match (cose_integer(key, 3)?, cose_integer(key, 1)?) {
+        (alg, 2) if alg == ES256.into() => {
+            if cose_integer(key, -1)? != 1 || cose_bytes(key, -2)?.len() != 32 || cose_bytes(key, -3)?.len() != 32 {
+                anyhow::bail!("Only P-256 keys are supported for ES256.");
+            }
+            Ok(ES256)
+        }
+        (alg, 3) if alg == RS256.into() => {
+            cose_bytes(key, -1)?;
+            cose_bytes(key, -2)?;
+            Ok(RS256)
+        }
+        (alg, kty) => anyhow::bail!("Unsupported public key: algorithm {}, key type {}.", alg, kty),
+    }
+}
+
+fn verify_signature(passkey: &StoredPasskey, message: &[u8], signature: &[u8]) -> Result<(), anyhow::Error> {
+    let key: Value = serde_cbor::from_slice(&passkey.public_key).context("Invalid stored public key.")?;
+    let verified = match passkey.algorithm {
+        ES256 => {
+            // Uncompressed point: 0x04 || x || y
+            let mut point = vec![0x04];
+            point.extend_from_slice(cose_bytes(&key, -2)?);
+            point.extend_from_slice(cose_bytes(&key, -3)?);
+            UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, point).verify(message, signature)
+        }
+        RS256 => RsaPublicKeyComponents { n: cose_bytes(&key, -1)?, e: cose_bytes(&key, -2)? }.verify(
+            &RSA_PKCS1_2048_8192_SHA256,
+            message,
+            signature,
+        ),
+        algorithm => anyhow::bail!("Unsupported algorithm: {}", algorithm),
+    };
+    verified.map_err(|_| anyhow::anyhow!("Invalid signature."))
+}
+
+// rpIdHash (32) | flags (1) | signCount (4) | [aaguid (16) | idLength (2) | id | COSE key]
+fn parse_authenticator_data(bytes: &[u8]) -> Result<AuthenticatorData<'_>, anyhow::Error> {
+    if bytes.len() < 37 {
+        anyhow::bail!("The authenticator data is too short.");
+    }
+    let flags = bytes[32];
+    let mut sign_count = [0; 4];
+    sign_count.copy_from_slice(&bytes[33..37]);
+    let attested_credential = if flags & ATTESTED_CREDENTIAL_DATA != 0 {
+        let rest = &bytes[37..];
+        if rest.len() < 18 {
+            anyhow::bail!("The attested credential data is too short.");
+        }
+        let id_length = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+        let rest = &rest[18..];
+        if rest.len() < id_length {
+            anyhow::bail!("The credential id is truncated.");
+        }
+        let (credential_id, rest) = rest.split_at(id_length);
+        // Extensions may follow the key, so read a single CBOR item
+        let public_key = serde_cbor::Deserializer::from_slice(rest)
+            .into_iter::<Value>()
+            .next()
+            .context("The credential has no public key.")?
+            .context("Invalid credential public key.")?;
+        Some((credential_id, public_key))
+    } else {
+        None
+    };
+    Ok(AuthenticatorData {
+        rp_id_hash: &bytes[..32],
+        flags,
+        sign_count: u32::from_be_bytes(sign_count),
+        attested_credential,
+    })
+}
+
+/// Whether the user has registered at least one passkey.
+#[tracing::instrument(name = "Check whether a user has passkeys", skip(pool))]
+pub async fn has_passkeys(pool: &PgPool, user_id: Uuid) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT EXISTS (SELECT 1 FROM passkeys WHERE user_id = $1) AS "exists!""#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform a query to check whether a user has passkeys.")?;
+    Ok(row.exists)
+}
+
+#[tracing::instrument(name = "Get the passkey credential ids of a user", skip(pool))]
+pub async fn get_passkey_credential_ids(pool: &PgPool, user_id: Uuid) -> Result<Vec<String>, anyhow::Error> {
+    let rows = sqlx::query!("SELECT credential_id FROM passkeys WHERE user_id = $1", user_id)
+        .fetch_all(pool)
+        .await
+        .context("Failed to perform a query to retrieve passkeys.")?;
+    Ok(rows.into_iter().map(|row| row.credential_id).collect())
+}
+
+/// Returns `false` if the passkey is already registered, to this user or another.
+#[tracing::instrument(name = "Store a passkey", skip(pool, passkey))]
+pub async fn store_passkey(
+    pool: &PgPool,
+    user_id: Uuid,
+    name: &str,
+    passkey: &NewPasskey,
+) -> Result<bool, anyhow::Error> {
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO passkeys (passkey_id, user_id, name, credential_id, public_key, algorithm, sign_count, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT (credential_id) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        name,
+        passkey.credential_id,
+        passkey.public_key,
+        passkey.algorithm,
+        i64::from(passkey.sign_count),
+        Utc::now(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a passkey.")?
+    .rows_affected();
+    Ok(inserted == 1)
+}
+
+/// The passkey with this credential id, unless its user has been disabled.
+#[tracing::instrument(name = "Find a passkey", skip(pool))]
+pub async fn find_passkey(pool: &PgPool, credential_id: &str) -> Result<Option<StoredPasskey>, anyhow::Error> {
+    let passkey = sqlx::query_as!(
+        StoredPasskey,
+        r#"
+        SELECT p.passkey_id, p.user_id, p.public_key, p.algorithm, p.sign_count
+        FROM passkeys p JOIN users u ON u.user_id = p.user_id
+        WHERE p.credential_id = $1 AND NOT u.disabled
+        "#,
+        credential_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve a passkey.")?;
+    Ok(passkey)
+}
+
+/// Move the signature counter forward. Returns `false` if another login with
+/// the same passkey got there first.
+#[tracing::instrument(name = "Record the use of a passkey", skip(pool, passkey), fields(passkey_id = %passkey.passkey_id))]
+pub async fn record_passkey_use(pool: &PgPool, passkey: &StoredPasskey, sign_count: u32) -> Result<bool, anyhow::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE passkeys SET sign_count = $1, last_used_at = now()
+        WHERE passkey_id = $2 AND sign_count = $3
+        "#,
+        i64::from(sign_count),
+        passkey.passkey_id,
+        passkey.sign_count,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the signature counter of a passkey.")?
+    .rows_affected();
+    Ok(updated == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authenticator_data(rp_id: &str, flags: u8, sign_count: u32) -> Vec<u8> {
+        let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend_from_slice(&sign_count.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn the_relying_party_is_the_host_of_the_base_url() {
+        let rp = RelyingParty::from_base_url("https://news.example.com:8443/").unwrap();
+
+        assert_eq!(rp.id, "news.example.com");
+        assert_eq!(rp.origin, "https://news.example.com:8443");
+    }
+
+    #[test]
+    fn authenticator_data_is_parsed() {
+        let data = authenticator_data("example.com", USER_PRESENT | USER_VERIFIED, 42);
+
+        let parsed = parse_authenticator_data(&data).unwrap();
+
+        assert_eq!(parsed.flags, USER_PRESENT | USER_VERIFIED);
+        assert_eq!(parsed.sign_count, 42);
+        assert!(parsed.attested_credential.is_none());
+    }
+
+    #[test]
+    fn truncated_authenticator_data_is_rejected() {
+        let data = authenticator_data("example.com", USER_PRESENT | ATTESTED_CREDENTIAL_DATA, 0);
+
+        assert!(parse_authenticator_data(&data[..36]).is_err());
+        assert!(parse_authenticator_data(&data).is_err());
+    }
+
+    #[test]
+    fn user_verification_is_only_checked_when_required() {
+        let rp = RelyingParty::from_base_url("https://example.com").unwrap();
+        let data = authenticator_data("example.com", USER_PRESENT, 0);
+        let parsed = parse_authenticator_data(&data).unwrap();
+
+        assert!(rp.check_authenticator_data(&parsed, false).is_ok());
+        assert!(rp.check_authenticator_data(&parsed, true).is_err());
+    }
+
+    #[test]
+    fn passkeys_for_another_site_are_rejected() {
+        let rp = RelyingParty::from_base_url("https://example.com").unwrap();
+        let data = authenticator_data("evil.example", USER_PRESENT, 0);
+        let parsed = parse_authenticator_data(&data).unwrap();
+
+        assert!(rp.check_authenticator_data(&parsed, false).is_err());
+    }
+
+    #[test]
+    fn the_signature_counter_must_move_forward() {
+        assert!(sign_count_advanced(0, 0));
+        assert!(sign_count_advanced(0, 1));
+        assert!(sign_count_advanced(5, 6));
+        assert!(!sign_count_advanced(5, 5));
+        assert!(!sign_count_advanced(5, 0));
+    }
+}
@@ -1,6 +1,9 @@
 use anyhow::Context;
 use secrecy::{Secret, ExposeSecret};
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use crate::configuration::PasswordHashingSettings;
+use crate::telemetry::spawn_blocking_with_tracing;
 use actix_web::http::header::{HeaderMap};
 use sqlx::PgPool;
 
@@ -17,6 +20,7 @@ pub struct Credentials {
     pub password: Secret<String>,
 }
 
+// Disabled users are treated as unknown ones.
 // We extracted the db-querying logic in its own function with its own span.
 #[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
 async fn get_stored_credentials(username: &str, pool: &PgPool) -> Result<Option<(uuid::Uuid, Secret<String>)>, anyhow::Error> {
@@ -24,7 +28,7 @@ async fn get_stored_credentials(username: &str, pool: &PgPool) -> Result<Option<
         r#"
         SELECT user_id, password_hash
         FROM users
-        WHERE username = $1
+        WHERE username = $1 AND NOT disabled
         "#,
         username,
     )
@@ -36,40 +40,98 @@ async fn get_stored_credentials(username: &str, pool: &PgPool) -> Result<Option<
 }
 
 
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
-pub async fn validate_credentials(credentials: Credentials, pool: &PgPool) -> Result<uuid::Uuid, AuthError> {
-    let row: Option<_> = sqlx::query!(
-        r#"
-        SELECT user_id, password_hash FROM USERS 
-        WHERE username = $1
-        "#,
-        credentials.username
-    )
-    .fetch_optional(pool)
+#[tracing::instrument(name = "Validate credentials", skip(credentials, hashing, pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    hashing: &PasswordHashingSettings,
+    pool: &PgPool,
+) -> Result<uuid::Uuid, AuthError> {
+    let params = hashing
+        .params()
+        .context("Invalid password hashing parameters.")
+        .map_err(AuthError::UnexpectedError)?;
+    let mut user_id = None;
+    // Unknown usernames are checked against a dummy hash with the same parameters
+    // as real ones: both outcomes then take the same time, so response times
+    // don't reveal which usernames exist.
+    let mut expected_password_hash = Secret::new(format!(
+        "$argon2id$v=19$m={},t={},p={}$\
+        gZiV/M1gPc22ElAH/Jh1Hw$\
+        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+        params.m_cost(),
+        params.t_cost(),
+        params.p_cost(),
+    ));
+
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool)
+            .await
+            .map_err(AuthError::UnexpectedError)?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    let password = credentials.password.clone();
+    let verify_params = params.clone();
+    let needs_rehash = spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, credentials.password, &verify_params)
+    })
     .await
-    .context("Failed to perform query to retrieve user credentials")
-    .map_err(AuthError::UnexpectedError)?;
-    
-    let (expected_password_hash, user_id) = match row {
-        Some(row) => (row.password_hash, row.user_id),
-        None => {
-            return Err(AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username")));
+    .context("Failed to spawn blocking task.")
+    .map_err(AuthError::UnexpectedError)??;
+
+    // Only reached with the dummy hash if someone guessed its password
+    let user_id = user_id
+        .ok_or_else(|| anyhow::anyhow!("Unknown username."))
+        .map_err(AuthError::InvalidCredentials)?;
+
+    // The password is known to be right: take the chance to upgrade its hash.
+    // This is best effort, the login goes ahead either way.
+    if needs_rehash {
+        if let Err(e) = upgrade_password_hash(user_id, password, hashing, pool).await {
+            tracing::warn!(error.cause_chain = ?e, "Failed to upgrade a password hash");
         }
-    };
+    }
+
+    Ok(user_id)
+}
 
-    let expected_password_hash = PasswordHash::new(&expected_password_hash)
+/// Check `password_candidate` against `expected_password_hash`.
+/// On success, tells whether the hash should be recomputed with `params`.
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate, params)
+)]
+fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+    params: &Params,
+) -> Result<bool, AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
         .context("Failed to parse hash in PHC string format.")
         .map_err(AuthError::UnexpectedError)?;
 
-    tracing::info_span!("Verify password hash")
-        .in_scope(|| {
-            Argon2::default()
-                .verify_password(credentials.password.expose_secret().as_bytes(), &expected_password_hash)
-        })
+    // The parameters stored in the hash are used for verification
+    Argon2::default()
+        .verify_password(password_candidate.expose_secret().as_bytes(), &expected_password_hash)
         .context("Invalid password")
         .map_err(AuthError::InvalidCredentials)?;
 
-    return Ok(user_id);
+    Ok(needs_rehash(&expected_password_hash, params))
+}
+
+// Whether `hash` was computed with another algorithm or other parameters than ours.
+fn needs_rehash(hash: &PasswordHash, params: &Params) -> bool {
+    let hash_params = match Params::try_from(hash) {
+        Ok(hash_params) => hash_params,
+        Err(_) => return true,
+    };
+    hash.algorithm != Algorithm::Argon2id.ident()
+        || hash.version != Some(Version::V0x13.into())
+        || hash_params.m_cost() != params.m_cost()
+        || hash_params.t_cost() != params.t_cost()
+        || hash_params.p_cost() != params.p_cost()
 }
 
 pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
@@ -107,23 +169,124 @@ pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::
 }
 
 
-#[tracing::instrument(name = "Change password", skip(password, pool))]
+/// Whether the user may log in with their password. Owners can turn it off,
+/// leaving sign-in links as the only way in.
+#[tracing::instrument(name = "Check whether password login is enabled", skip(pool))]
+pub async fn password_login_enabled(pool: &PgPool, user_id: uuid::Uuid) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        "SELECT password_login_disabled FROM users WHERE user_id = $1",
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to check whether password login is enabled.")?;
+    Ok(matches!(row, Some(row) if !row.password_login_disabled))
+}
+
+
+/// Whether the user must pick a new password before doing anything else,
+/// as they still have the one everybody knows.
+#[tracing::instrument(name = "Check whether a password change is required", skip(pool))]
+pub async fn password_change_required(pool: &PgPool, user_id: uuid::Uuid) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        "SELECT password_change_required FROM users WHERE user_id = $1",
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to check whether a password change is required.")?;
+    Ok(matches!(row, Some(row) if row.password_change_required))
+}
+
+
+#[tracing::instrument(name = "Change password", skip(password, hashing, pool))]
 pub async fn change_password(
     user_id: uuid::Uuid,
     password: Secret<String>,
+    hashing: &PasswordHashingSettings,
     pool: &PgPool,
 ) -> Result<(), anyhow::Error> {
+    let password_hash = hash_password(password, hashing).await?;
     sqlx::query!(
         r#"
         UPDATE users
-        SET password_hash = $1
+        SET password_hash = $1, password_change_required = false
         WHERE user_id = $2
         "#,
-        password.expose_secret(),
+        password_hash.expose_secret(),
         user_id
     )
     .execute(pool)
     .await
     .context("Failed to change user's password in the database.")?;
     return Ok(());
-}
\ No newline at end of file
+}
+
+// Same password, new hash: unlike `change_password`, a required change stays required.
+#[tracing::instrument(name = "Upgrade password hash", skip(password, hashing, pool))]
+async fn upgrade_password_hash(
+    user_id: uuid::Uuid,
+    password: Secret<String>,
+    hashing: &PasswordHashingSettings,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let password_hash = hash_password(password, hashing).await?;
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+        password_hash.expose_secret(),
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to upgrade a password hash in the database.")?;
+    Ok(())
+}
+
+/// Hash `password` for storage, on the blocking thread pool.
+pub async fn hash_password(
+    password: Secret<String>,
+    hashing: &PasswordHashingSettings,
+) -> Result<Secret<String>, anyhow::Error> {
+    let params = hashing.params().context("Invalid password hashing parameters.")?;
+    spawn_blocking_with_tracing(move || compute_password_hash(password, params))
+        .await?
+        .context("Failed to hash password")
+}
+
+// Argon2id with the configured parameters and a random salt, in PHC string format.
+fn compute_password_hash(password: Secret<String>, params: Params) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.expose_secret().as_bytes(), &salt)?
+        .to_string();
+    Ok(Secret::new(password_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::needs_rehash;
+    use argon2::{Params, PasswordHash};
+
+    const HASH: &str = "$argon2id$v=19$m=15000,t=2,p=1$\
+        gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno";
+
+    #[test]
+    fn hashes_with_the_current_parameters_are_kept() {
+        let hash = PasswordHash::new(HASH).unwrap();
+        assert!(!needs_rehash(&hash, &Params::new(15000, 2, 1, None).unwrap()));
+    }
+
+    #[test]
+    fn hashes_with_other_parameters_are_upgraded() {
+        let hash = PasswordHash::new(HASH).unwrap();
+        assert!(needs_rehash(&hash, &Params::new(19456, 2, 1, None).unwrap()));
+        assert!(needs_rehash(&hash, &Params::new(15000, 3, 1, None).unwrap()));
+    }
+
+    #[test]
+    fn argon2i_hashes_are_upgraded() {
+        let argon2i_hash = HASH.replacen("argon2id", "argon2i", 1);
+        let hash = PasswordHash::new(&argon2i_hash).unwrap();
+        assert!(needs_rehash(&hash, &Params::new(15000, 2, 1, None).unwrap()));
+    }
+}
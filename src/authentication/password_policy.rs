@@ -0,0 +1,73 @@
+use secrecy::{ExposeSecret, Secret};
+
+pub const MIN_PASSWORD_LENGTH: usize = 12;
+pub const MAX_PASSWORD_LENGTH: usize = 128;
+
+// Well-known passwords from public breach lists, one per line, lower-cased
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum PasswordPolicyError {
+    #[error("The new password must be at least {} characters long.", MIN_PASSWORD_LENGTH)]
+    TooShort,
+    #[error("The new password must be at most {} characters long.", MAX_PASSWORD_LENGTH)]
+    TooLong,
+    #[error("The new password is too common - please choose a less predictable one.")]
+    TooCommon,
+}
+
+/// Check that `password` is acceptable as a new password.
+pub fn check_password_policy(password: &Secret<String>) -> Result<(), PasswordPolicyError> {
+    let password = password.expose_secret();
+    // Length in characters, not bytes, so that non-ASCII passwords aren't penalised
+    let length = password.chars().count();
+    if length < MIN_PASSWORD_LENGTH {
+        return Err(PasswordPolicyError::TooShort);
+    }
+    if length > MAX_PASSWORD_LENGTH {
+        return Err(PasswordPolicyError::TooLong);
+    }
+    let lowercase = password.to_lowercase();
+    if COMMON_PASSWORDS.lines().any(|common| common == lowercase) {
+        return Err(PasswordPolicyError::TooCommon);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_password_policy, PasswordPolicyError};
+    use claim::assert_ok;
+    use secrecy::Secret;
+
+    fn check(password: &str) -> Result<(), PasswordPolicyError> {
+        check_password_policy(&Secret::new(password.to_string()))
+    }
+
+    #[test]
+    fn passwords_below_the_minimum_length_are_rejected() {
+        assert_eq!(check("a-short-one"), Err(PasswordPolicyError::TooShort));
+    }
+
+    #[test]
+    fn passwords_above_the_maximum_length_are_rejected() {
+        assert_eq!(check(&"a".repeat(129)), Err(PasswordPolicyError::TooLong));
+    }
+
+    #[test]
+    fn length_is_counted_in_characters() {
+        assert_ok!(check("ñandú-ñandú-"));
+    }
+
+    #[test]
+    fn common_passwords_are_rejected_regardless_of_case() {
+        assert_eq!(check("Password1234"), Err(PasswordPolicyError::TooCommon));
+    }
+
+    #[test]
+    fn the_common_password_list_is_lower_cased() {
+        for common in super::COMMON_PASSWORDS.lines() {
+            assert_eq!(common, common.to_lowercase());
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// What an admin user is allowed to do, mirroring the `user_role` Postgres enum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, serde::Deserialize)]
+#[sqlx(type_name = "user_role", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Everything, including managing other users.
+    Owner,
+    /// Drafts and publishes newsletter issues.
+    Editor,
+    /// Read-only access to reports.
+    Analyst,
+    /// Manages subscribers.
+    Support,
+}
+
+/// Actions that only some roles can take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ManageUsers,
+    PublishNewsletters,
+    ViewReports,
+    ManageSubscribers,
+    ViewAuditLog,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Owner, Role::Editor, Role::Analyst, Role::Support];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Editor => "editor",
+            Role::Analyst => "analyst",
+            Role::Support => "support",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|role| role.as_str() == s)
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        matches!(
+            (self, permission),
+            (Role::Owner, _)
+                | (Role::Editor, Permission::PublishNewsletters)
+                | (Role::Analyst, Permission::ViewReports)
+                | (Role::Support, Permission::ManageSubscribers)
+        )
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+/// The role of `user_id`, unless the user is disabled or doesn't exist.
+#[tracing::instrument(name = "Get the role of an enabled user", skip(pool))]
+pub async fn get_enabled_user_role(pool: &PgPool, user_id: Uuid) -> Result<Option<Role>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT role AS "role: Role" FROM users WHERE user_id = $1 AND NOT disabled"#,
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve the role of a user.")?;
+    Ok(row.map(|r| r.role))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Permission, Role};
+
+    #[test]
+    fn owners_can_do_everything() {
+        for permission in [
+            Permission::ManageUsers,
+            Permission::PublishNewsletters,
+            Permission::ViewReports,
+            Permission::ManageSubscribers,
+            Permission::ViewAuditLog,
+        ] {
+            assert!(Role::Owner.can(permission));
+        }
+    }
+
+    #[test]
+    fn only_owners_can_manage_users() {
+        for role in [Role::Editor, Role::Analyst, Role::Support] {
+            assert!(!role.can(Permission::ManageUsers));
+            assert!(!role.can(Permission::ViewAuditLog));
+        }
+    }
+
+    #[test]
+    fn analysts_cannot_publish() {
+        assert!(!Role::Analyst.can(Permission::PublishNewsletters));
+        assert!(Role::Editor.can(Permission::PublishNewsletters));
+    }
+}
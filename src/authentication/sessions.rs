@@ -0,0 +1,161 @@
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use crate::configuration::SessionSettings;
+use crate::session_state::TypedSession;
+use crate::utils::{client_ip, user_agent};
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+// A session as listed on the sessions page
+pub struct ActiveSession {
+    pub session_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub ip: String,
+    pub user_agent: String,
+}
+
+/// Log `user_id` in: record the session in the registry and store its id in the cookie session.
+/// Dead sessions of the user are cleaned up on the way, and the login goes into the audit log.
+#[tracing::instrument(name = "Start a session", skip(pool, settings, session, request))]
+pub async fn start_session(
+    pool: &PgPool,
+    settings: &SessionSettings,
+    session: &TypedSession,
+    user_id: Uuid,
+    request: &HttpRequest,
+) -> Result<(), anyhow::Error> {
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        DELETE FROM user_sessions
+        WHERE user_id = $1 AND (revoked_at IS NOT NULL OR last_seen_at < $2 OR created_at < $3)
+        "#,
+        user_id,
+        now - settings.idle_timeout(),
+        now - settings.absolute_timeout(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete expired sessions.")?;
+
+    let session_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO user_sessions (session_id, user_id, created_at, last_seen_at, ip, user_agent)
+        VALUES ($1, $2, $3, $3, $4, $5)
+        "#,
+        session_id,
+        user_id,
+        now,
+        client_ip(request),
+        user_agent(request),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record a new session.")?;
+    AuditEntry::new(AuditAction::LoginSucceeded)
+        .actor(user_id)
+        .record(pool, &RequestOrigin::of(request))
+        .await?;
+
+    session.renew();
+    session.insert_user_id(user_id, session_id)?;
+    Ok(())
+}
+
+/// Record activity on a session and tell whether it is still active, i.e.
+/// neither revoked nor past one of the timeouts.
+#[tracing::instrument(name = "Touch a session", skip(pool, settings))]
+pub async fn touch_session(
+    pool: &PgPool,
+    settings: &SessionSettings,
+    user_id: Uuid,
+    session_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let now = Utc::now();
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_sessions
+        SET last_seen_at = $1
+        WHERE session_id = $2 AND user_id = $3
+            AND revoked_at IS NULL AND last_seen_at >= $4 AND created_at >= $5
+        "#,
+        now,
+        session_id,
+        user_id,
+        now - settings.idle_timeout(),
+        now - settings.absolute_timeout(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record activity on a session.")?;
+    Ok(result.rows_affected() == 1)
+}
+
+#[tracing::instrument(name = "Get the active sessions of a user", skip(pool, settings))]
+pub async fn get_active_sessions(
+    pool: &PgPool,
+    settings: &SessionSettings,
+    user_id: Uuid,
+) -> Result<Vec<ActiveSession>, anyhow::Error> {
+    let now = Utc::now();
+    let sessions = sqlx::query_as!(
+        ActiveSession,
+        r#"
+        SELECT session_id, created_at, last_seen_at, ip, user_agent
+        FROM user_sessions
+        WHERE user_id = $1 AND revoked_at IS NULL AND last_seen_at >= $2 AND created_at >= $3
+        ORDER BY last_seen_at DESC
+        "#,
+        user_id,
+        now - settings.idle_timeout(),
+        now - settings.absolute_timeout(),
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve active sessions.")?;
+    Ok(sessions)
+}
+
+/// Revoke one session of `user_id`. Returns `false` if there was no such active session.
+#[tracing::instrument(name = "Revoke a session", skip(pool))]
+pub async fn revoke_session(pool: &PgPool, user_id: Uuid, session_id: Uuid) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_sessions
+        SET revoked_at = now()
+        WHERE session_id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        session_id,
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke a session.")?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Log `user_id` out everywhere, except from the session `keep` if given.
+#[tracing::instrument(name = "Revoke all sessions of a user", skip(executor))]
+pub async fn revoke_user_sessions<'c>(
+    executor: impl PgExecutor<'c>,
+    user_id: Uuid,
+    keep: Option<Uuid>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE user_sessions
+        SET revoked_at = now()
+        WHERE user_id = $1 AND revoked_at IS NULL AND session_id IS DISTINCT FROM $2
+        "#,
+        user_id,
+        keep,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to revoke the sessions of a user.")?;
+    Ok(())
+}
@@ -0,0 +1,173 @@
+//! Time-based one-time passwords (RFC 6238), as used by authenticator apps:
+//! HMAC-SHA1, 30 second steps and 6 digit codes.
+use hmac::{Hmac, Mac};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng, RngCore};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+// Steps accepted on either side of the current one, to make up for clock drift
+const ALLOWED_DRIFT_STEPS: u64 = 1;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A fresh 160 bit secret, base32-encoded as authenticator apps expect it.
+pub fn generate_totp_secret() -> String {
+    let mut secret = [0u8; 20];
+    thread_rng().fill_bytes(&mut secret);
+    base32_encode(&secret)
+}
+
+/// The code for `secret` at `unix_time`, or `None` if `secret` is not valid base32.
+pub fn totp_code(secret: &str, unix_time: u64) -> Option<String> {
+    let secret = base32_decode(secret)?;
+    Some(format!("{:0width$}", hotp(&secret, unix_time / STEP_SECONDS), width = DIGITS as usize))
+}
+
+/// Check `code` against `secret` around `unix_time`.
+/// Returns the step it matched, which must be greater than `last_used_step`
+/// so that a code can't be replayed.
+pub fn verify_totp_code(secret: &str, code: &str, unix_time: u64, last_used_step: Option<u64>) -> Option<u64> {
+    let secret = base32_decode(secret)?;
+    let code: u32 = code.trim().parse().ok()?;
+    let current_step = unix_time / STEP_SECONDS;
+    (current_step.saturating_sub(ALLOWED_DRIFT_STEPS)..=current_step + ALLOWED_DRIFT_STEPS)
+        .filter(|step| !matches!(last_used_step, Some(last) if *step <= last))
+        .find(|step| hotp(&secret, *step) == code)
+}
+
+/// The `otpauth://` URI authenticator apps import, usually through a QR code.
+pub fn totp_provisioning_uri(secret: &str, issuer: &str, username: &str) -> String {
+    let encode = |s: &str| url::form_urlencoded::byte_serialize(s.as_bytes()).collect::<String>();
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        encode(issuer),
+        encode(username),
+        secret,
+        encode(issuer),
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+/// `uri` as an inline SVG QR code.
+pub fn qr_code_svg(uri: &str) -> Result<String, anyhow::Error> {
+    let code = QrCode::new(uri.as_bytes())?;
+    Ok(code.render::<svg::Color>().min_dimensions(200, 200).build())
+}
+
+/// Single-use codes to log in with when the authenticator is lost.
+/// They are random enough for a plain SHA-256 to be a safe way to store them.
+pub fn generate_recovery_codes(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|_| {
+            let code: String = thread_rng()
+                .sample_iter(&Alphanumeric)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .take(10)
+                .collect();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+pub fn hash_recovery_code(code: &str) -> String {
+    format!("{:x}", Sha256::digest(code.trim().to_lowercase().as_bytes()))
+}
+
+// RFC 4226: HMAC-SHA1 of the counter, dynamically truncated to `DIGITS` digits.
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([digest[offset], digest[offset + 1], digest[offset + 2], digest[offset + 3]])
+        & 0x7fff_ffff;
+    binary % 10u32.pow(DIGITS)
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for c in encoded.trim_end_matches('=').chars().filter(|c| !c.is_whitespace()) {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The SHA1 secret from RFC 6238's test vectors
+    fn rfc_secret() -> String {
+        base32_encode(b"12345678901234567890")
+    }
+
+    #[test]
+    fn codes_match_the_rfc_6238_test_vectors() {
+        // The RFC lists 8 digit codes, we keep their last 6
+        assert_eq!(totp_code(&rfc_secret(), 59).unwrap(), "287082");
+        assert_eq!(totp_code(&rfc_secret(), 1111111109).unwrap(), "081804");
+        assert_eq!(totp_code(&rfc_secret(), 2000000000).unwrap(), "279037");
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        assert_eq!(rfc_secret(), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(base32_decode(&rfc_secret()).unwrap(), b"12345678901234567890");
+    }
+
+    #[test]
+    fn codes_from_the_previous_and_next_steps_are_accepted() {
+        let secret = rfc_secret();
+        let previous = totp_code(&secret, 1111111109 - 30).unwrap();
+        let next = totp_code(&secret, 1111111109 + 30).unwrap();
+        assert!(verify_totp_code(&secret, &previous, 1111111109, None).is_some());
+        assert!(verify_totp_code(&secret, &next, 1111111109, None).is_some());
+        let too_old = totp_code(&secret, 1111111109 - 90).unwrap();
+        assert!(verify_totp_code(&secret, &too_old, 1111111109, None).is_none());
+    }
+
+    #[test]
+    fn used_codes_cannot_be_replayed() {
+        let secret = rfc_secret();
+        let code = totp_code(&secret, 1111111109).unwrap();
+        let step = verify_totp_code(&secret, &code, 1111111109, None).unwrap();
+        assert!(verify_totp_code(&secret, &code, 1111111109, Some(step)).is_none());
+    }
+
+    #[test]
+    fn recovery_codes_are_hashed_case_insensitively() {
+        let code = &generate_recovery_codes(1)[0];
+        assert_eq!(hash_recovery_code(code), hash_recovery_code(&code.to_uppercase()));
+    }
+}
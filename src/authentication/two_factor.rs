@@ -0,0 +1,158 @@
+use crate::authentication::{generate_recovery_codes, has_passkeys, hash_recovery_code, verify_totp_code};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const RECOVERY_CODES_COUNT: usize = 10;
+
+/// Whether logging in takes a second step: a code from an authenticator app or a passkey.
+#[tracing::instrument(name = "Check whether a user has enabled two-factor authentication", skip(pool))]
+pub async fn two_factor_enabled(pool: &PgPool, user_id: Uuid) -> Result<bool, anyhow::Error> {
+    Ok(totp_enabled(pool, user_id).await? || has_passkeys(pool, user_id).await?)
+}
+
+#[tracing::instrument(name = "Check whether a user has set up an authenticator app", skip(pool))]
+pub async fn totp_enabled(pool: &PgPool, user_id: Uuid) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT totp_secret IS NOT NULL AS "enabled!" FROM users WHERE user_id = $1"#,
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to check whether two-factor authentication is enabled.")?;
+    Ok(matches!(row, Some(row) if row.enabled))
+}
+
+/// Check a code from the user's authenticator app or one of their unused recovery codes.
+/// Either way the code is consumed and won't be accepted again.
+#[tracing::instrument(name = "Verify a second factor", skip(pool, code))]
+pub async fn verify_second_factor(pool: &PgPool, user_id: Uuid, code: &str) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool.begin().await.context("Failed to acquire a Postgres connection from the pool")?;
+    let row = sqlx::query!(
+        "SELECT totp_secret, totp_last_used_step FROM users WHERE user_id = $1 FOR UPDATE",
+        user_id,
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to retrieve the TOTP secret of a user.")?;
+    let (secret, last_used_step) = match row {
+        Some(row) => match row.totp_secret {
+            Some(secret) => (secret, row.totp_last_used_step),
+            None => return Ok(false),
+        },
+        None => return Ok(false),
+    };
+
+    let now = Utc::now().timestamp() as u64;
+    if let Some(step) = verify_totp_code(&secret, code, now, last_used_step.map(|s| s as u64)) {
+        sqlx::query!(
+            "UPDATE users SET totp_last_used_step = $1 WHERE user_id = $2",
+            step as i64,
+            user_id,
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to record the last used TOTP step.")?;
+        transaction.commit().await.context("Failed to commit SQL transaction.")?;
+        return Ok(true);
+    }
+
+    let used = sqlx::query!(
+        r#"
+        UPDATE two_factor_recovery_codes
+        SET used_at = $1
+        WHERE user_id = $2 AND code_hash = $3 AND used_at IS NULL
+        "#,
+        Utc::now(),
+        user_id,
+        hash_recovery_code(code),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to use a recovery code.")?
+    .rows_affected();
+    transaction.commit().await.context("Failed to commit SQL transaction.")?;
+    if used == 1 {
+        tracing::info!("A recovery code has been used");
+    }
+    Ok(used == 1)
+}
+
+/// Turn on two-factor authentication for a user who confirmed `secret` with a code
+/// from time step `used_step`. Returns fresh recovery codes, which are only stored hashed.
+#[tracing::instrument(name = "Enable two-factor authentication", skip(pool, secret))]
+pub async fn enable_two_factor(
+    pool: &PgPool,
+    user_id: Uuid,
+    secret: &str,
+    used_step: u64,
+) -> Result<Vec<String>, anyhow::Error> {
+    let recovery_codes = generate_recovery_codes(RECOVERY_CODES_COUNT);
+    let mut transaction = pool.begin().await.context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        "UPDATE users SET totp_secret = $1, totp_last_used_step = $2 WHERE user_id = $3",
+        secret,
+        used_step as i64,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store the TOTP secret of a user.")?;
+    sqlx::query!("DELETE FROM two_factor_recovery_codes WHERE user_id = $1", user_id)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete old recovery codes.")?;
+    for code in &recovery_codes {
+        sqlx::query!(
+            r#"
+            INSERT INTO two_factor_recovery_codes (user_id, code_hash, created_at)
+            VALUES ($1, $2, $3)
+            "#,
+            user_id,
+            hash_recovery_code(code),
+            Utc::now(),
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to store a recovery code.")?;
+    }
+    transaction.commit().await.context("Failed to commit SQL transaction.")?;
+    Ok(recovery_codes)
+}
+
+#[tracing::instrument(name = "Disable two-factor authentication", skip(pool))]
+pub async fn disable_two_factor(pool: &PgPool, user_id: Uuid) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await.context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        "UPDATE users SET totp_secret = NULL, totp_last_used_step = NULL WHERE user_id = $1",
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to remove the TOTP secret of a user.")?;
+    sqlx::query!("DELETE FROM two_factor_recovery_codes WHERE user_id = $1", user_id)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete recovery codes.")?;
+    transaction.commit().await.context("Failed to commit SQL transaction.")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Check whether two-factor authentication is required", skip(pool))]
+pub async fn two_factor_required(pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!("SELECT require_two_factor FROM security_settings")
+        .fetch_one(pool)
+        .await
+        .context("Failed to retrieve the security settings.")?;
+    Ok(row.require_two_factor)
+}
+
+#[tracing::instrument(name = "Change whether two-factor authentication is required", skip(pool))]
+pub async fn set_two_factor_required(pool: &PgPool, required: bool) -> Result<(), anyhow::Error> {
+    sqlx::query!("UPDATE security_settings SET require_two_factor = $1", required)
+        .execute(pool)
+        .await
+        .context("Failed to update the security settings.")?;
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use super::{check_password_policy, hash_password, PasswordPolicyError, Role};
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use crate::configuration::PasswordHashingSettings;
+use crate::domain::{SubscriberEmail, SubscriberEmailError};
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub struct NewAdminUser {
+    pub username: String,
+    // Where password reset links go, optional
+    pub email: Option<String>,
+    pub password: Secret<String>,
+    pub role: Role,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CreateUserError {
+    #[error("The username cannot be empty.")]
+    EmptyUsername,
+    #[error(transparent)]
+    InvalidEmail(#[from] SubscriberEmailError),
+    #[error(transparent)]
+    WeakPassword(#[from] PasswordPolicyError),
+    #[error("The username {0} is already taken.")]
+    UsernameTaken(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// Create an admin user, as owners do on the users page or from the command
+/// line. `actor` is the owner doing it, if any.
+#[tracing::instrument(
+    name = "Create an admin user",
+    skip(pool, hashing, new_user, origin),
+    fields(username = %new_user.username, role = %new_user.role)
+)]
+pub async fn create_admin_user(
+    pool: &PgPool,
+    hashing: &PasswordHashingSettings,
+    new_user: NewAdminUser,
+    actor: Option<Uuid>,
+    origin: &RequestOrigin,
+) -> Result<Uuid, CreateUserError> {
+    let NewAdminUser { username, email, password, role } = new_user;
+    let username = username.trim();
+    if username.is_empty() {
+        return Err(CreateUserError::EmptyUsername);
+    }
+    let email = email
+        .filter(|email| !email.trim().is_empty())
+        .map(SubscriberEmail::parse)
+        .transpose()?;
+    check_password_policy(&password)?;
+    let password_hash = hash_password(password, hashing).await?;
+
+    let user_id = Uuid::new_v4();
+    let mut transaction = pool.begin().await.context("Failed to acquire a Postgres connection from the pool")?;
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, email, password_hash, role)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (username) DO NOTHING
+        "#,
+        user_id,
+        username,
+        email.as_ref().map(|e| e.as_ref()),
+        password_hash.expose_secret(),
+        role as Role,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to insert a new admin user.")?
+    .rows_affected();
+    if inserted == 0 {
+        return Err(CreateUserError::UsernameTaken(username.to_string()));
+    }
+    let mut entry = AuditEntry::new(AuditAction::UserCreated)
+        .target(username)
+        .change("role", None::<&str>, role.as_str());
+    if let Some(actor) = actor {
+        entry = entry.actor(actor);
+    }
+    entry.record(&mut transaction, origin).await?;
+    transaction.commit().await.context("Failed to commit SQL transaction to create a user.")?;
+    Ok(user_id)
+}
@@ -0,0 +1,178 @@
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use crate::authentication::{check_password_policy, hash_password, Role};
+use crate::configuration::{BootstrapSettings, PasswordHashingSettings};
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+// Held while checking that there are no users and creating the first one, so
+// that instances starting together, or two setup forms, cannot both create one.
+const BOOTSTRAP_LOCK_ID: i64 = 0x7a32_705f_626f_6f74;
+
+const SETUP_TOKEN_VALIDITY_HOURS: i64 = 24;
+
+/// Make sure a new installation gets an owner. While there are no users, the
+/// owner from `settings` is created, or a one-time setup link is logged.
+#[tracing::instrument(name = "Bootstrap the first owner", skip(pool, settings, hashing, base_url))]
+pub async fn bootstrap_owner(
+    pool: &PgPool,
+    settings: &BootstrapSettings,
+    hashing: &PasswordHashingSettings,
+    base_url: &str,
+) -> Result<(), anyhow::Error> {
+    let credentials = settings.credentials()?;
+    if !has_no_users(pool).await? {
+        return Ok(());
+    }
+
+    match credentials {
+        Some((username, password)) => {
+            if username.is_empty() {
+                anyhow::bail!("`bootstrap.admin_username` cannot be empty.");
+            }
+            check_password_policy(password).context("`bootstrap.admin_password` is not acceptable")?;
+            let password_hash = hash_password(password.clone(), hashing).await?;
+            if create_first_owner(pool, None, username, &password_hash, &RequestOrigin::internal())
+                .await?
+                .is_some()
+            {
+                tracing::info!(username, "Created the first owner from the bootstrap settings");
+            }
+        }
+        None => {
+            let token = store_setup_token(pool).await?;
+            tracing::warn!(
+                "There are no users yet. Create the first owner within {} hours at {}/setup?setup_token={}",
+                SETUP_TOKEN_VALIDITY_HOURS,
+                base_url,
+                token.expose_secret(),
+            );
+        }
+    }
+    Ok(())
+}
+
+#[tracing::instrument(name = "Check whether there are no users", skip(pool))]
+async fn has_no_users(pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(r#"SELECT NOT EXISTS (SELECT 1 FROM users) AS "empty!""#)
+        .fetch_one(pool)
+        .await
+        .context("Failed to perform a query to check whether there are users.")?;
+    Ok(row.empty)
+}
+
+/// Whether `setup_token` can still create the first owner.
+#[tracing::instrument(name = "Check a setup token", skip(pool, setup_token))]
+pub async fn setup_token_is_valid(pool: &PgPool, setup_token: &str) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM setup_tokens WHERE token_hash = $1 AND expires_at > now())
+            AND NOT EXISTS (SELECT 1 FROM users) AS "valid!"
+        "#,
+        hash_setup_token(setup_token),
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform a query to check a setup token.")?;
+    Ok(row.valid)
+}
+
+/// Create the owner of a new installation. `None` if there are users already,
+/// or if `setup_token` is given and is not valid. Setup tokens are all used up.
+#[tracing::instrument(name = "Create the first owner", skip(pool, setup_token, password_hash, origin))]
+pub async fn create_first_owner(
+    pool: &PgPool,
+    setup_token: Option<&str>,
+    username: &str,
+    password_hash: &Secret<String>,
+    origin: &RequestOrigin,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let mut transaction = pool.begin().await.context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!("SELECT pg_advisory_xact_lock($1)", BOOTSTRAP_LOCK_ID)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to take the bootstrap lock.")?;
+    if let Some(setup_token) = setup_token {
+        if !use_setup_token(&mut transaction, setup_token).await? {
+            return Ok(None);
+        }
+    }
+    let users = sqlx::query!(r#"SELECT count(*) AS "count!" FROM users"#)
+        .fetch_one(&mut transaction)
+        .await
+        .context("Failed to count users.")?;
+    if users.count > 0 {
+        return Ok(None);
+    }
+
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        user_id,
+        username,
+        password_hash.expose_secret(),
+        Role::Owner as Role,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to insert the first owner.")?;
+    sqlx::query!("DELETE FROM setup_tokens")
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete setup tokens.")?;
+    AuditEntry::new(AuditAction::OwnerBootstrapped)
+        .actor(user_id)
+        .target(username)
+        .record(&mut transaction, origin)
+        .await?;
+    transaction.commit().await.context("Failed to commit SQL transaction to create the first owner.")?;
+    Ok(Some(user_id))
+}
+
+// Every instance that starts without users logs a token of its own, and any of them works
+async fn store_setup_token(pool: &PgPool) -> Result<Secret<String>, anyhow::Error> {
+    let token: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(32)
+        .collect();
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO setup_tokens (token_hash, created_at, expires_at)
+        VALUES ($1, $2, $3)
+        "#,
+        hash_setup_token(&token),
+        now,
+        now + Duration::hours(SETUP_TOKEN_VALIDITY_HOURS),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a setup token.")?;
+    Ok(Secret::new(token))
+}
+
+async fn use_setup_token(transaction: &mut Transaction<'_, Postgres>, setup_token: &str) -> Result<bool, anyhow::Error> {
+    let used = sqlx::query!(
+        "DELETE FROM setup_tokens WHERE token_hash = $1 AND expires_at > now()",
+        hash_setup_token(setup_token),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to use a setup token.")?
+    .rows_affected();
+    Ok(used == 1)
+}
+
+// High-entropy tokens need no salt, see `hash_api_token`
+fn hash_setup_token(setup_token: &str) -> String {
+    format!("{:x}", Sha256::digest(setup_token.as_bytes()))
+}
@@ -0,0 +1,316 @@
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use crate::authentication::{
+    change_password, check_password_policy, create_admin_user, revoke_user_sessions, CreateUserError, NewAdminUser,
+    RelyingParty, Role,
+};
+use crate::configuration::{get_configuration, PasswordHashingSettings, Settings};
+use crate::domain::{SubscriberEmail, SubscriptionStatus};
+use crate::migrations::{migrate, pending_migrations};
+use crate::session_keys::SessionKeys;
+use crate::startup::{get_connection_pool, Application};
+use crate::telemetry::{get_subscriber, init_subscriber};
+use crate::worker::run_worker_until_stopped;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use std::io::{BufRead, Write};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// The `zero2prod` binary. Without a subcommand, it runs the web server.
+#[derive(Parser, Debug)]
+#[clap(name = "zero2prod", about = "Newsletter service and its management commands")]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the web server
+    Serve,
+    /// Prune expired sessions and one-time links at regular intervals
+    Worker {
+        #[clap(long, default_value_t = 600)]
+        interval_seconds: u64,
+    },
+    /// Apply pending database migrations
+    Migrate,
+    /// Create an admin user, reading their password from standard input
+    CreateUser {
+        #[clap(long)]
+        username: String,
+        /// One of owner, editor, analyst or support
+        #[clap(long, value_parser = parse_role)]
+        role: Role,
+        /// Where password reset links go
+        #[clap(long)]
+        email: Option<String>,
+    },
+    /// Set a new password for an admin user, reading it from standard input
+    ResetPassword {
+        #[clap(long)]
+        username: String,
+    },
+    /// Print subscribers as tab-separated values
+    ListSubscribers {
+        /// E.g. pending or confirmed; all subscribers if left out
+        #[clap(long, value_parser = parse_subscription_status)]
+        status: Option<SubscriptionStatus>,
+    },
+    /// Send an email through the configured email API
+    SendTestEmail {
+        #[clap(long)]
+        to: String,
+    },
+    /// Inspect the configuration
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Load the configuration and check that it can be used, including the
+    /// connections to Postgres and Redis
+    Check,
+}
+
+fn parse_role(s: &str) -> Result<Role, String> {
+    Role::parse(s).ok_or_else(|| format!("unknown role `{}`", s))
+}
+
+fn parse_subscription_status(s: &str) -> Result<SubscriptionStatus, String> {
+    SubscriptionStatus::parse(s).ok_or_else(|| format!("unknown subscription status `{}`", s))
+}
+
+pub async fn run(cli: Cli) -> Result<(), anyhow::Error> {
+    let command = cli.command.unwrap_or(Command::Serve);
+    // Management commands print their results: keep the logs out of the way
+    match command {
+        Command::Serve | Command::Worker { .. } => {
+            init_subscriber(get_subscriber("zero2prod".into(), "info".into(), std::io::stdout))
+        }
+        _ => init_subscriber(get_subscriber("zero2prod".into(), "warn".into(), std::io::stderr)),
+    }
+
+    let configuration = get_configuration().context("Failed to read configuration.")?;
+    match command {
+        Command::Serve => {
+            let application = Application::build(configuration).await?;
+            application.run_until_stopped().await?;
+        }
+        Command::Worker { interval_seconds } => {
+            run_worker_until_stopped(configuration, Duration::from_secs(interval_seconds)).await?;
+        }
+        Command::Migrate => {
+            let pool = get_connection_pool(&configuration.database);
+            migrate(&pool).await?;
+            println!("The database is up to date.");
+        }
+        Command::CreateUser { username, role, email } => {
+            let pool = get_connection_pool(&configuration.database);
+            let password = read_password()?;
+            create_user(&pool, &configuration.password_hashing, &username, email, role, password).await?;
+            println!("{} has been added as {}.", username.trim(), role);
+        }
+        Command::ResetPassword { username } => {
+            let pool = get_connection_pool(&configuration.database);
+            let password = read_password()?;
+            reset_password(&pool, &configuration.password_hashing, &username, password).await?;
+            println!("The password of {} has been reset.", username);
+        }
+        Command::ListSubscribers { status } => {
+            let pool = get_connection_pool(&configuration.database);
+            let stdout = std::io::stdout();
+            let mut out = stdout.lock();
+            writeln!(out, "email\tname\tstatus\tsubscribed_at")?;
+            for subscriber in list_subscribers(&pool, status).await? {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}",
+                    subscriber.email,
+                    subscriber.name,
+                    subscriber.status,
+                    subscriber.subscribed_at.to_rfc3339(),
+                )?;
+            }
+        }
+        Command::SendTestEmail { to } => {
+            let recipient = SubscriberEmail::parse(to).map_err(|e| anyhow::anyhow!(e))?;
+            let email_client = configuration.email_client.client().context("Invalid sender email address.")?;
+            email_client
+                .send_email(
+                    &recipient,
+                    "zero2prod test email",
+                    "<p>Emails from zero2prod can be delivered.</p>",
+                    "Emails from zero2prod can be delivered.",
+                )
+                .await
+                .context("Failed to send the test email.")?;
+            println!("A test email has been sent to {}.", recipient.as_ref());
+        }
+        Command::Config { command: ConfigCommand::Check } => {
+            check_configuration(&configuration).await?;
+            println!("The configuration is valid.");
+        }
+    }
+    Ok(())
+}
+
+// The first line of standard input, so that passwords stay out of the shell history
+fn read_password() -> Result<Secret<String>, anyhow::Error> {
+    eprint!("Password: ");
+    std::io::stderr().flush()?;
+    let mut password = String::new();
+    {
+        // Piped passwords are read as they are: there is nothing to hide them from
+        let _no_echo = if atty::is(atty::Stream::Stdin) { Some(EchoOff::new()?) } else { None };
+        std::io::stdin()
+            .lock()
+            .read_line(&mut password)
+            .context("Failed to read the password from standard input.")?;
+    }
+    if atty::is(atty::Stream::Stdin) {
+        // The newline typed after the password was not echoed either
+        eprintln!();
+    }
+    Ok(Secret::new(password.trim_end_matches(&['\r', '\n'][..]).to_string()))
+}
+
+/// Turns off the echo of the terminal on standard input until dropped.
+struct EchoOff {
+    #[cfg(unix)]
+    original: libc::termios,
+}
+
+impl EchoOff {
+    #[cfg(unix)]
+    fn new() -> Result<Self, anyhow::Error> {
+        let mut termios = std::mem::MaybeUninit::<libc::termios>::uninit();
+        // SAFETY: `tcgetattr` fills in `termios` when it succeeds
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to read the terminal settings.");
+        }
+        let original = unsafe { termios.assume_init() };
+        let mut no_echo = original;
+        no_echo.c_lflag &= !libc::ECHO;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &no_echo) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to turn off the terminal echo.");
+        }
+        Ok(Self { original })
+    }
+
+    #[cfg(not(unix))]
+    fn new() -> Result<Self, anyhow::Error> {
+        Ok(Self {})
+    }
+}
+
+impl Drop for EchoOff {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+/// Create an admin user, as owners do on the users page.
+#[tracing::instrument(name = "Create an admin user from the command line", skip(pool, hashing, email, password))]
+pub async fn create_user(
+    pool: &PgPool,
+    hashing: &PasswordHashingSettings,
+    username: &str,
+    email: Option<String>,
+    role: Role,
+    password: Secret<String>,
+) -> Result<Uuid, anyhow::Error> {
+    let new_user = NewAdminUser { username: username.to_string(), email, password, role };
+    create_admin_user(pool, hashing, new_user, None, &RequestOrigin::internal())
+        .await
+        .map_err(|e| match e {
+            CreateUserError::UnexpectedError(e) => e,
+            e => anyhow::anyhow!(e.to_string()),
+        })
+}
+
+/// Give an admin user a new password, e.g. when they lost theirs and have no
+/// email address for reset links. Their sessions are logged out.
+#[tracing::instrument(name = "Reset a password from the command line", skip(pool, hashing, password))]
+pub async fn reset_password(
+    pool: &PgPool,
+    hashing: &PasswordHashingSettings,
+    username: &str,
+    password: Secret<String>,
+) -> Result<(), anyhow::Error> {
+    check_password_policy(&password)?;
+    let user_id = sqlx::query!("SELECT user_id FROM users WHERE username = $1", username)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to perform a query to find the user.")?
+        .map(|r| r.user_id)
+        .with_context(|| format!("There is no user called {}.", username))?;
+
+    change_password(user_id, password, hashing, pool).await?;
+    revoke_user_sessions(pool, user_id, None).await?;
+    AuditEntry::new(AuditAction::PasswordReset)
+        .target(user_id)
+        .record(pool, &RequestOrigin::internal())
+        .await?;
+    Ok(())
+}
+
+pub struct SubscriberSummary {
+    pub email: String,
+    pub name: String,
+    pub status: SubscriptionStatus,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "List subscribers", skip(pool))]
+pub async fn list_subscribers(
+    pool: &PgPool,
+    status: Option<SubscriptionStatus>,
+) -> Result<Vec<SubscriberSummary>, anyhow::Error> {
+    let subscribers = sqlx::query_as!(
+        SubscriberSummary,
+        r#"
+        SELECT email, name, status AS "status: SubscriptionStatus", subscribed_at
+        FROM subscriptions
+        WHERE $1::subscription_status IS NULL OR status = $1
+        ORDER BY subscribed_at
+        "#,
+        status as Option<SubscriptionStatus>,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to list subscribers.")?;
+    Ok(subscribers)
+}
+
+/// What `Application::build` would trip over, checked without starting anything.
+pub async fn check_configuration(configuration: &Settings) -> Result<(), anyhow::Error> {
+    configuration
+        .password_hashing
+        .params()
+        .map_err(|e| anyhow::anyhow!("Invalid password hashing parameters: {}", e))?;
+    configuration.email_client.sender().context("Invalid sender email address.")?;
+    configuration.bootstrap.credentials()?;
+    SessionKeys::load(&configuration.session)?;
+    RelyingParty::from_base_url(&configuration.application.base_url)?;
+
+    let pool = get_connection_pool(&configuration.database);
+    let pending = pending_migrations(&pool).await.context("Failed to check the database schema.")?;
+    if !pending.is_empty() && !configuration.database.migrate_on_startup {
+        anyhow::bail!("The database schema is behind: run `zero2prod migrate` first.");
+    }
+    redis::Client::open(configuration.redis_uri.expose_secret().as_str())?
+        .get_tokio_connection()
+        .await
+        .context("Failed to connect to Redis.")?;
+    Ok(())
+}
@@ -1,4 +1,6 @@
-use crate::domain::SubscriberEmail;
+use crate::authentication::Role;
+use crate::domain::{SubscriberEmail, SubscriberEmailError};
+use crate::email_client::EmailClient;
 use config::{Config, ConfigError};
 use secrecy::{ExposeSecret, Secret};
 use serde_aux::field_attributes::deserialize_number_from_string;
@@ -6,6 +8,7 @@ use sqlx::postgres::PgConnectOptions;
 use sqlx::postgres::PgSslMode;
 use sqlx::ConnectOptions;
 use std::env;
+use std::net::IpAddr;
 use url::Url;
 use substring::Substring;
 use serde::Deserialize;
@@ -18,6 +21,17 @@ pub struct Settings {
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
     pub redis_uri: Secret<String>,
+    pub sign_up_protection: SignUpProtectionSettings,
+    pub login_protection: LoginProtectionSettings,
+    pub session: SessionSettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub api: ApiSettings,
+    #[serde(default)]
+    pub magic_link: MagicLinkSettings,
+    // Single sign-on is off unless this is set
+    pub oidc: Option<OidcSettings>,
+    #[serde(default)]
+    pub bootstrap: BootstrapSettings,
 }
 
 #[derive(Deserialize, Debug)]
@@ -36,6 +50,204 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    pub hmac_secret: Secret<String>,
+    // Origins allowed to call `POST /subscriptions` from a browser
+    // E.g. `APP_APPLICATION__CORS_ALLOWED_ORIGINS=https://a.com,https://b.com`
+    #[serde(default)]
+    pub cors_allowed_origins: Vec<String>,
+    // Reverse proxies whose `X-Forwarded-For` header we believe, e.g. our hosting
+    // provider's load balancer. Other requests are attributed to the peer address.
+    // E.g. `APP_APPLICATION__TRUSTED_PROXIES=10.0.0.1,10.0.0.2`
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+#[derive(Deserialize, Debug)]
+#[derive(Clone)]
+pub struct SignUpProtectionSettings {
+    // Attempts allowed in each rate limit window, per client IP and per email address
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts_per_ip: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts_per_email: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub rate_limit_window_seconds: u64,
+    // Silently discard sign-ups that fill in the hidden `website` form field
+    pub honeypot_enabled: bool,
+    // Leading zero bits required in a proof of work, which is not required when unset
+    #[serde(default)]
+    pub proof_of_work_difficulty: Option<u8>,
+    // Disposable email providers, subdomains included, we refuse sign-ups from
+    #[serde(default)]
+    pub blocked_email_domains: Vec<String>,
+}
+
+// The first owner, created at startup while there are no users. Without both
+// fields a one-time setup link is logged instead.
+// E.g. `APP_BOOTSTRAP__ADMIN_USERNAME=alice APP_BOOTSTRAP__ADMIN_PASSWORD=...`
+#[derive(Deserialize, Debug, Default)]
+#[derive(Clone)]
+pub struct BootstrapSettings {
+    pub admin_username: Option<String>,
+    pub admin_password: Option<Secret<String>>,
+}
+
+impl BootstrapSettings {
+    /// The username and password of the first owner, if both are set.
+    pub fn credentials(&self) -> Result<Option<(&str, &Secret<String>)>, anyhow::Error> {
+        match (&self.admin_username, &self.admin_password) {
+            (Some(username), Some(password)) => Ok(Some((username.trim(), password))),
+            (None, None) => Ok(None),
+            _ => anyhow::bail!("`bootstrap.admin_username` and `bootstrap.admin_password` must be set together."),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[derive(Clone)]
+pub struct ApiSettings {
+    // Accept an admin's username and password on the API, besides API tokens.
+    // Off unless enabled, and never for users with a second factor.
+    #[serde(default)]
+    pub basic_auth_enabled: bool,
+}
+
+// Passwordless logins through links sent by email. Off unless enabled: anyone
+// who can read a user's inbox could then log in as them.
+#[derive(Deserialize, Debug, Default)]
+#[derive(Clone)]
+pub struct MagicLinkSettings {
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+// Single sign-on through an OpenID Connect provider, with the authorization code flow and PKCE.
+// The provider must list `{base_url}/login/oidc/callback` as a redirect URI for `client_id`.
+// Users are matched by their subject at the provider, then by verified email address.
+// Their role comes from the first of `role_mappings` whose group they are in, else
+// `default_role`: users with neither are refused. Unknown users are only created
+// when `create_users` is set.
+#[derive(Deserialize, Debug)]
+#[derive(Clone)]
+pub struct OidcSettings {
+    pub issuer_url: String,
+    pub client_id: String,
+    pub client_secret: Secret<String>,
+    // Shown on the login page, e.g. "Log in with Okta"
+    pub display_name: String,
+    #[serde(default = "OidcSettings::default_scopes")]
+    pub scopes: Vec<String>,
+    // The claim listing the groups of the user
+    #[serde(default = "OidcSettings::default_groups_claim")]
+    pub groups_claim: String,
+    #[serde(default)]
+    pub role_mappings: Vec<OidcRoleMapping>,
+    pub default_role: Option<Role>,
+    #[serde(default)]
+    pub create_users: bool,
+    // Link an existing user to the first identity with their verified email address.
+    // Off by default: the provider would vouch for accounts it does not own. Users
+    // with password login or a second factor are never linked this way.
+    #[serde(default)]
+    pub link_by_email: bool,
+    // Bring the role of users in line with their groups on every login, rather
+    // than only when creating them. The last active owner is never demoted.
+    #[serde(default)]
+    pub sync_roles: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[derive(Clone)]
+pub struct OidcRoleMapping {
+    pub group: String,
+    pub role: Role,
+}
+
+impl OidcSettings {
+    fn default_scopes() -> Vec<String> {
+        vec!["openid".into(), "email".into(), "profile".into()]
+    }
+
+    fn default_groups_claim() -> String {
+        "groups".into()
+    }
+}
+
+// Failed logins are counted per username and per client IP within a window.
+// Past `failures_before_delay` every attempt is slowed down a bit more, and
+// past the lockout thresholds attempts are refused for `lockout_seconds`.
+#[derive(Deserialize, Debug)]
+#[derive(Clone)]
+pub struct LoginProtectionSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub failure_window_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub failures_before_delay: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub delay_step_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_delay_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub failures_before_lockout_per_username: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub failures_before_lockout_per_ip: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lockout_seconds: u64,
+    // One-time links (password resets, sign-in links) that can be asked for each
+    // username or email address, and from each client IP, per `failure_window_seconds`
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_link_requests_per_account: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_link_requests_per_ip: u64,
+}
+
+// Admin sessions end after `idle_timeout_minutes` without a request, and
+// `absolute_timeout_hours` after logging in whatever the activity.
+// Session cookies are encrypted with `key`, given inline or through `key_file`
+// (e.g. a mounted secret). After a rotation, keep the old key as `previous_key`
+// for `absolute_timeout_hours` so that existing sessions survive it.
+#[derive(Deserialize, Debug)]
+#[derive(Clone)]
+pub struct SessionSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub idle_timeout_minutes: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub absolute_timeout_hours: u64,
+    pub key: Option<Secret<String>>,
+    pub key_file: Option<String>,
+    pub previous_key: Option<Secret<String>>,
+    pub previous_key_file: Option<String>,
+    // Refuse to start without a key, rather than making up a throwaway one
+    pub require_key: bool,
+}
+
+impl SessionSettings {
+    pub fn idle_timeout(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.idle_timeout_minutes as i64)
+    }
+
+    pub fn absolute_timeout(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.absolute_timeout_hours as i64)
+    }
+}
+
+// Argon2id cost parameters for new password hashes.
+// Existing hashes are upgraded the next time their owner logs in.
+#[derive(Deserialize, Debug)]
+#[derive(Clone)]
+pub struct PasswordHashingSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub memory_cost_kib: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub iterations: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub parallelism: u32,
+}
+
+impl PasswordHashingSettings {
+    pub fn params(&self) -> Result<argon2::Params, argon2::Error> {
+        argon2::Params::new(self.memory_cost_kib, self.iterations, self.parallelism, None)
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -48,18 +260,27 @@ pub struct DatabaseSettings {
     pub host: String,
     pub database_name: String,
     // Determine if we demand the connection to be encrypted or not
-    pub require_ssl: bool
+    pub require_ssl: bool,
+    // Apply pending migrations at startup, rather than refusing to start
+    #[serde(default)]
+    pub migrate_on_startup: bool,
 }
 
 
 impl EmailClientSettings {
-    pub fn sender(&self) -> Result<SubscriberEmail, String> {
+    pub fn sender(&self) -> Result<SubscriberEmail, SubscriberEmailError> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
     
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
+
+    pub fn client(self) -> Result<EmailClient, SubscriberEmailError> {
+        let sender_email = self.sender()?;
+        let timeout = self.timeout();
+        Ok(EmailClient::new(self.base_url, sender_email, self.authorization_token, timeout))
+    }
 }
 
 
@@ -131,7 +352,18 @@ pub fn get_configuration() -> Result<Settings, ConfigError> {
         .add_source(config::File::from(configuration_directory.join(&environment_filename)))
         // Add in settings from environment variables (with a prefix of APP and '__' as separator)
         // E.g. `APP_APPLICATION__PORT=5001 would set `Settings.application.port`
-        .add_source(config::Environment::with_prefix("APP").prefix_separator("_").separator("__"));
+        .add_source(
+            config::Environment::with_prefix("APP")
+                .prefix_separator("_")
+                .separator("__")
+                // List values are comma separated
+                .try_parsing(true)
+                .list_separator(",")
+                .with_list_parse_key("application.cors_allowed_origins")
+                .with_list_parse_key("application.trusted_proxies")
+                .with_list_parse_key("sign_up_protection.blocked_email_domains")
+                .with_list_parse_key("oidc.scopes")
+        );
     
     match environment {
         Environment::Production => {
@@ -141,9 +373,26 @@ pub fn get_configuration() -> Result<Settings, ConfigError> {
         _ => {}
     }
 
-    let settings = builder.build()?;
-    
-    settings.try_deserialize::<Settings>()
+    let settings = builder.build()?.try_deserialize::<Settings>()?;
+    if let Environment::Production = environment {
+        check_hmac_secret(&settings.application.hmac_secret).map_err(ConfigError::Message)?;
+    }
+    Ok(settings)
+}
+
+// The secret in `local.yaml`, which everybody can read
+const LOCAL_HMAC_SECRET: &str = "super-long-and-secret-random-key-needed-to-verify-message-integrity";
+
+// Links and challenges signed with a known or short secret can be forged
+fn check_hmac_secret(hmac_secret: &Secret<String>) -> Result<(), String> {
+    let hmac_secret = hmac_secret.expose_secret();
+    if hmac_secret == LOCAL_HMAC_SECRET {
+        return Err("`application.hmac_secret` is still the local development one.".into());
+    }
+    if hmac_secret.len() < 64 {
+        return Err("`application.hmac_secret` must be at least 64 characters long.".into());
+    }
+    Ok(())
 }
 
 /// The possible runtime environment for our application.
@@ -177,3 +426,20 @@ impl TryFrom<String> for Environment {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{check_hmac_secret, LOCAL_HMAC_SECRET};
+    use secrecy::Secret;
+
+    #[test]
+    fn the_local_hmac_secret_is_refused() {
+        assert!(check_hmac_secret(&Secret::new(LOCAL_HMAC_SECRET.into())).is_err());
+    }
+
+    #[test]
+    fn short_hmac_secrets_are_refused() {
+        assert!(check_hmac_secret(&Secret::new("a".repeat(63))).is_err());
+        assert!(check_hmac_secret(&Secret::new("a".repeat(64))).is_ok());
+    }
+}
@@ -0,0 +1,186 @@
+use crate::session_state::TypedSession;
+use crate::templates::{render_with_status, ErrorPage};
+use crate::utils::e500;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{CONTENT_TYPE, ORIGIN, REFERER};
+use actix_web::http::Method;
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse};
+use actix_web_lab::middleware::Next;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use std::future::Future;
+use std::pin::Pin;
+use url::Url;
+
+// Where our forms put the token. Scripts can send it as a header instead.
+pub const CSRF_FORM_FIELD: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// The CSRF token of the current session, created the first time a page needs it.
+/// Every form posting to `/admin` or `/login` must include `templates/csrf_field.html`,
+/// which reads it from the `csrf_token` field of the page.
+pub struct CsrfToken(String);
+
+impl std::fmt::Display for CsrfToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromRequest for CsrfToken {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let session = TypedSession::from_request(req, payload);
+        Box::pin(async move {
+            let session = session.await?;
+            if let Some(token) = session.get_csrf_token().map_err(e500)? {
+                return Ok(CsrfToken(token));
+            }
+            let token: String = thread_rng()
+                .sample_iter(&Alphanumeric)
+                .map(char::from)
+                .take(32)
+                .collect();
+            session.insert_csrf_token(&token).map_err(e500)?;
+            Ok(CsrfToken(token))
+        })
+    }
+}
+
+// Reject state-changing requests to `/admin` and `/login` that come from another
+// site, or that do not carry the token of the session. Must run inside `SessionMiddleware`.
+pub async fn reject_cross_site_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if !needs_protection(req.method(), req.path()) {
+        return next.call(req).await;
+    }
+    if !same_origin(&req) {
+        return Err(forbidden("The request comes from another site"));
+    }
+
+    let submitted = match req.headers().get(CSRF_HEADER) {
+        Some(token) => token.to_str().ok().map(str::to_string),
+        None => read_form_token(&mut req).await?,
+    };
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+    let expected = session.get_csrf_token().map_err(e500)?;
+    match (expected, submitted) {
+        (Some(expected), Some(submitted)) if tokens_match(&expected, &submitted) => next.call(req).await,
+        (_, None) => Err(forbidden("The request has no CSRF token")),
+        _ => Err(forbidden("The request has an invalid CSRF token")),
+    }
+}
+
+fn needs_protection(method: &Method, path: &str) -> bool {
+    let safe = matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS);
+    let protected = ["/admin", "/login"]
+        .iter()
+        .any(|prefix| path == *prefix || path.starts_with(&format!("{}/", prefix)));
+    protected && !safe
+}
+
+// Browsers tell us where a form was submitted from. Requests without either
+// header only have the token to go by.
+fn same_origin(req: &ServiceRequest) -> bool {
+    let source = match req.headers().get(ORIGIN).or_else(|| req.headers().get(REFERER)) {
+        Some(source) => source,
+        None => return true,
+    };
+    let host = req.connection_info().host().to_string();
+    match source.to_str().ok().and_then(|s| Url::parse(s).ok()) {
+        Some(url) => authority(&url).as_deref() == Some(host.as_str()),
+        // E.g. `Origin: null`, sent by sandboxed frames
+        None => false,
+    }
+}
+
+// `host[:port]`, as in a `Host` header
+fn authority(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    })
+}
+
+// Read the token out of a urlencoded form, then hand the body back for the handler to parse
+async fn read_form_token(req: &mut ServiceRequest) -> Result<Option<String>, actix_web::Error> {
+    let is_form = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.starts_with("application/x-www-form-urlencoded"))
+        .unwrap_or(false);
+    if !is_form {
+        return Ok(None);
+    }
+    let body = req.extract::<web::Bytes>().await?;
+    let token = url::form_urlencoded::parse(&body)
+        .find(|(name, _)| name == CSRF_FORM_FIELD)
+        .map(|(_, value)| value.into_owned());
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(body);
+    req.set_payload(payload.into());
+    Ok(token)
+}
+
+// Without short-circuiting, so that timing does not tell how much of a guess was right
+fn tokens_match(expected: &str, submitted: &str) -> bool {
+    expected.len() == submitted.len()
+        && expected
+            .bytes()
+            .zip(submitted.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn forbidden(reason: &'static str) -> actix_web::Error {
+    let page = ErrorPage::new(
+        "Forbidden",
+        "This form has expired or was submitted from another site. \
+         Please go back, reload the page and try again.",
+    );
+    match render_with_status(HttpResponse::Forbidden(), &page) {
+        Ok(response) => InternalError::from_response(anyhow::anyhow!(reason), response).into(),
+        Err(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_state_changing_requests_to_admin_and_login_are_protected() {
+        assert!(needs_protection(&Method::POST, "/admin/password"));
+        assert!(needs_protection(&Method::POST, "/login"));
+        assert!(needs_protection(&Method::POST, "/login/two_factor"));
+        assert!(!needs_protection(&Method::GET, "/admin/password"));
+        assert!(!needs_protection(&Method::POST, "/subscriptions"));
+        assert!(!needs_protection(&Method::POST, "/administrators"));
+    }
+
+    #[test]
+    fn the_authority_of_a_url_matches_the_host_header() {
+        let authority_of = |url: &str| authority(&Url::parse(url).unwrap());
+        assert_eq!(authority_of("https://example.com/admin").as_deref(), Some("example.com"));
+        assert_eq!(authority_of("http://127.0.0.1:8000").as_deref(), Some("127.0.0.1:8000"));
+    }
+
+    #[test]
+    fn tokens_must_match_exactly() {
+        assert!(tokens_match("abc", "abc"));
+        assert!(!tokens_match("abc", "abd"));
+        assert!(!tokens_match("abc", "ab"));
+        assert!(!tokens_match("abc", ""));
+    }
+}
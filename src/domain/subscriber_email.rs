@@ -3,14 +3,54 @@ use validator::validate_email;
 #[derive(Debug)]
 pub struct SubscriberEmail(String);
 
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SubscriberEmailError {
+    #[error("The subscriber email cannot be empty.")]
+    Empty,
+    #[error("{0} is not a valid subscriber email.")]
+    InvalidFormat(String),
+}
+
+impl SubscriberEmailError {
+    /// A stable, machine-readable identifier for the failed constraint.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SubscriberEmailError::Empty => "empty",
+            SubscriberEmailError::InvalidFormat(_) => "invalid_format",
+        }
+    }
+}
+
 impl SubscriberEmail {
-    pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if validate_email(&s) {
-            Ok(Self(s))
+    /// Validate `s` and bring it to its canonical form: the domain is
+    /// lower-cased and IDN domains are converted to punycode.
+    /// The local part is kept as is.
+    pub fn parse(s: String) -> Result<SubscriberEmail, SubscriberEmailError> {
+        if s.trim().is_empty() {
+            return Err(SubscriberEmailError::Empty);
+        }
+        let normalized = match normalize(&s) {
+            Some(normalized) => normalized,
+            None => return Err(SubscriberEmailError::InvalidFormat(s)),
+        };
+        if validate_email(&normalized) {
+            Ok(Self(normalized))
         } else {
-            Err(format!("{} is not a valid subscriber email.", s))
+            Err(SubscriberEmailError::InvalidFormat(s))
         }
     }
+
+    /// The (normalized) part after the `@`.
+    pub fn domain(&self) -> &str {
+        // `parse` guarantees there is an `@`
+        self.0.rsplit_once('@').map(|(_, domain)| domain).unwrap_or_default()
+    }
+}
+
+fn normalize(s: &str) -> Option<String> {
+    let (local_part, domain) = s.rsplit_once('@')?;
+    let domain = idna::domain_to_ascii(domain).ok()?;
+    Some(format!("{}@{}", local_part, domain))
 }
 
 impl AsRef<str> for SubscriberEmail {
@@ -58,5 +98,16 @@ mod tests {
         claim::assert_ok!(SubscriberEmail::parse(email));
     }
 
-    
+    #[test]
+    fn the_domain_is_lower_cased_but_not_the_local_part(){
+        let email = SubscriberEmail::parse("Ursula@Example.COM".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "Ursula@example.com");
+    }
+
+    #[test]
+    fn idn_domains_are_converted_to_punycode(){
+        let email = SubscriberEmail::parse("ursula@Bücher.example".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "ursula@xn--bcher-kva.example");
+        assert_eq!(email.domain(), "xn--bcher-kva.example");
+    }
 }
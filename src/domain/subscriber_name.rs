@@ -3,11 +3,32 @@ use unicode_segmentation::UnicodeSegmentation;
 #[derive(Debug)]
 pub struct SubscriberName(String);
 
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SubscriberNameError {
+    #[error("The subscriber name cannot be empty.")]
+    Empty,
+    #[error("The subscriber name cannot be longer than 256 characters.")]
+    TooLong,
+    #[error("The subscriber name contains forbidden characters.")]
+    ForbiddenCharacters,
+}
+
+impl SubscriberNameError {
+    /// A stable, machine-readable identifier for the failed constraint.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SubscriberNameError::Empty => "empty",
+            SubscriberNameError::TooLong => "too_long",
+            SubscriberNameError::ForbiddenCharacters => "forbidden_characters",
+        }
+    }
+}
+
 impl SubscriberName {
     /// Returns an instance of `SubscriberName` if the input satisfies all
-    /// our validation constraints on subscriber names.
-    /// It panics otherwise.
-    pub fn parse(s: String) -> Result<SubscriberName, String> {
+    /// our validation constraints on subscriber names, or the first
+    /// constraint it violates otherwise.
+    pub fn parse(s: String) -> Result<SubscriberName, SubscriberNameError> {
         // `.trim()` returns a view over the input `s` without trailing
         // whitespace-like characters.
         // `.is_empty` checks if the view contains any character.
@@ -27,9 +48,13 @@ impl SubscriberName {
         let forbidden_characters = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
         let contains_forbidden_characters = s.chars().any(|c| forbidden_characters.contains(&c));
 
-        // Return `false` if any of our conditions have been violated
-        if is_empty_or_whitespace || is_too_long || contains_forbidden_characters {
-            Err(format!("Invalid subscriber name: {}", s))
+        // Return the first of our conditions that has been violated
+        if is_empty_or_whitespace {
+            Err(SubscriberNameError::Empty)
+        } else if is_too_long {
+            Err(SubscriberNameError::TooLong)
+        } else if contains_forbidden_characters {
+            Err(SubscriberNameError::ForbiddenCharacters)
         } else {
             Ok(Self(s))
         }
@@ -50,7 +75,7 @@ impl AsRef<str> for SubscriberName {
 
 #[cfg(test)]
 mod tests {
-    use crate::domain::SubscriberName;
+    use crate::domain::{SubscriberName, SubscriberNameError};
     use claim::{assert_err, assert_ok};
 
     #[test]
@@ -85,6 +110,16 @@ mod tests {
         }
     }
     
+    #[test]
+    fn each_violated_constraint_has_its_own_error() {
+        assert_eq!(SubscriberName::parse(" ".into()).unwrap_err(), SubscriberNameError::Empty);
+        assert_eq!(SubscriberName::parse("a".repeat(257)).unwrap_err(), SubscriberNameError::TooLong);
+        assert_eq!(
+            SubscriberName::parse("<script>".into()).unwrap_err(),
+            SubscriberNameError::ForbiddenCharacters
+        );
+    }
+
     #[test]
     fn a_valid_name_is_parsed_successfully() {
         let name = "John Doe".to_string();
@@ -0,0 +1,107 @@
+/// Where a subscriber is in their lifecycle, mirroring the
+/// `subscription_status` Postgres enum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "subscription_status", rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    /// Signed up, waiting for the confirmation link to be clicked.
+    Pending,
+    Confirmed,
+    Unsubscribed,
+    /// Our emails can't be delivered to the address.
+    Bounced,
+    /// The subscriber reported our emails as spam.
+    Complained,
+    /// Terminal: the subscriber's data is kept for auditing only.
+    Deleted,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("A subscription cannot go from {from} to {to}.")]
+pub struct IllegalTransition {
+    pub from: SubscriptionStatus,
+    pub to: SubscriptionStatus,
+}
+
+impl SubscriptionStatus {
+    pub const ALL: [SubscriptionStatus; 6] = [
+        SubscriptionStatus::Pending,
+        SubscriptionStatus::Confirmed,
+        SubscriptionStatus::Unsubscribed,
+        SubscriptionStatus::Bounced,
+        SubscriptionStatus::Complained,
+        SubscriptionStatus::Deleted,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionStatus::Pending => "pending",
+            SubscriptionStatus::Confirmed => "confirmed",
+            SubscriptionStatus::Unsubscribed => "unsubscribed",
+            SubscriptionStatus::Bounced => "bounced",
+            SubscriptionStatus::Complained => "complained",
+            SubscriptionStatus::Deleted => "deleted",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|status| status.as_str() == s)
+    }
+
+    pub fn can_transition_to(self, to: SubscriptionStatus) -> bool {
+        use SubscriptionStatus::*;
+        matches!(
+            (self, to),
+            (Pending, Confirmed | Bounced | Complained | Deleted)
+                | (Confirmed, Unsubscribed | Bounced | Complained | Deleted)
+                // Signing up again goes through the confirmation flow
+                | (Unsubscribed, Pending | Deleted)
+                | (Bounced, Deleted)
+                | (Complained, Deleted)
+        )
+    }
+
+    pub fn transition_to(self, to: SubscriptionStatus) -> Result<SubscriptionStatus, IllegalTransition> {
+        if self.can_transition_to(to) {
+            Ok(to)
+        } else {
+            Err(IllegalTransition { from: self, to })
+        }
+    }
+}
+
+impl std::fmt::Display for SubscriptionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionStatus::*;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn pending_subscribers_can_be_confirmed() {
+        assert_ok!(Pending.transition_to(Confirmed));
+    }
+
+    #[test]
+    fn only_confirmed_subscribers_can_unsubscribe() {
+        assert_ok!(Confirmed.transition_to(Unsubscribed));
+        assert_err!(Pending.transition_to(Unsubscribed));
+    }
+
+    #[test]
+    fn deleted_is_terminal() {
+        for to in [Pending, Confirmed, Unsubscribed, Bounced, Complained, Deleted] {
+            assert_err!(Deleted.transition_to(to));
+        }
+    }
+
+    #[test]
+    fn staying_in_the_same_state_is_not_a_transition() {
+        for status in [Pending, Confirmed, Unsubscribed, Bounced, Complained, Deleted] {
+            assert!(!status.can_transition_to(status));
+        }
+    }
+}
@@ -1,10 +1,20 @@
+pub mod audit_log;
 pub mod authentication;
+pub mod bootstrap;
+pub mod cli;
 pub mod configuration;
+pub mod csrf;
 pub mod domain;
 pub mod email_client;
+pub mod login_protection;
+pub mod migrations;
 pub mod routes;
 pub mod startup;
+pub mod session_keys;
 pub mod session_state;
+pub mod sign_up_protection;
 pub mod telemetry;
+pub mod templates;
 pub mod utils;
+pub mod worker;
 
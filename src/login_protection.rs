@@ -0,0 +1,252 @@
+use crate::configuration::LoginProtectionSettings;
+use anyhow::Context;
+use redis::aio::ConnectionManager;
+use std::time::Duration;
+
+const KEY_PREFIX: &str = "login_protection";
+
+/// Slows down and then locks out repeated failed logins, per username and per
+/// client IP, to make brute-forcing `POST /login` impractical. Also limits the
+/// one-time links sent by email, which anyone can ask for.
+///
+/// Counters and lockouts live in Redis so that they hold across all our instances.
+pub struct LoginProtection {
+    redis: ConnectionManager,
+    settings: LoginProtectionSettings,
+}
+
+/// What a lockout applies to.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LockoutKind {
+    Username,
+    Ip,
+}
+
+impl LockoutKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LockoutKind::Username => "username",
+            LockoutKind::Ip => "ip",
+        }
+    }
+}
+
+/// An active lockout, as listed to owners.
+#[derive(Debug)]
+pub struct Lockout {
+    pub kind: LockoutKind,
+    pub value: String,
+    pub remaining_seconds: u64,
+}
+
+impl LoginProtection {
+    pub fn new(redis: ConnectionManager, settings: LoginProtectionSettings) -> Self {
+        Self { redis, settings }
+    }
+
+    /// Whether attempts for `username` or from `ip` are currently refused.
+    pub async fn is_locked_out(&self, username: &str, ip: &str) -> Result<bool, anyhow::Error> {
+        let locked: u64 = redis::cmd("EXISTS")
+            .arg(lockout_key(LockoutKind::Username, &normalize(username)))
+            .arg(lockout_key(LockoutKind::Ip, ip))
+            .query_async(&mut self.redis.clone())
+            .await
+            .context("Failed to check for login lockouts in Redis.")?;
+        Ok(locked > 0)
+    }
+
+    /// How long to hold the next attempt back, growing with the recent failures
+    /// for `username` and from `ip`.
+    pub async fn delay(&self, username: &str, ip: &str) -> Result<Duration, anyhow::Error> {
+        let failures = self
+            .failures(LockoutKind::Username, &normalize(username))
+            .await?
+            .max(self.failures(LockoutKind::Ip, ip).await?);
+        let excess = failures.saturating_sub(self.settings.failures_before_delay);
+        let delay = excess
+            .saturating_mul(self.settings.delay_step_milliseconds)
+            .min(self.settings.max_delay_milliseconds);
+        Ok(Duration::from_millis(delay))
+    }
+
+    /// Count a failed attempt, locking the username or the IP out once they go
+    /// over their threshold.
+    #[tracing::instrument(name = "Record a failed login", skip(self))]
+    pub async fn record_failure(&self, username: &str, ip: &str) -> Result<(), anyhow::Error> {
+        let username = normalize(username);
+        let username_failures = self.count_failure(LockoutKind::Username, &username).await?;
+        if username_failures >= self.settings.failures_before_lockout_per_username {
+            self.lock_out(LockoutKind::Username, &username, username_failures).await?;
+        }
+        let ip_failures = self.count_failure(LockoutKind::Ip, ip).await?;
+        if ip_failures >= self.settings.failures_before_lockout_per_ip {
+            self.lock_out(LockoutKind::Ip, ip, ip_failures).await?;
+        }
+        Ok(())
+    }
+
+    /// Forget past failures for `username` after a successful login.
+    pub async fn record_success(&self, username: &str) -> Result<(), anyhow::Error> {
+        redis::cmd("DEL")
+            .arg(failures_key(LockoutKind::Username, &normalize(username)))
+            .query_async::<_, ()>(&mut self.redis.clone())
+            .await
+            .context("Failed to reset failed logins in Redis.")?;
+        Ok(())
+    }
+
+    /// Count a request for a one-time link to `account` (a username or an email
+    /// address) from `ip`, and tell whether either went over its limit.
+    pub async fn link_request_limit_exceeded(&self, account: &str, ip: &str) -> Result<bool, anyhow::Error> {
+        let account_requests = self
+            .count(&link_requests_key(LockoutKind::Username, &normalize(account)))
+            .await
+            .context("Failed to count a one-time link request in Redis.")?;
+        let ip_requests = self
+            .count(&link_requests_key(LockoutKind::Ip, ip))
+            .await
+            .context("Failed to count a one-time link request in Redis.")?;
+        let exceeded = account_requests > self.settings.max_link_requests_per_account
+            || ip_requests > self.settings.max_link_requests_per_ip;
+        if exceeded {
+            tracing::warn!(security_event = "link_request_limit", account = %account, ip = %ip, "Refused a one-time link request");
+        }
+        Ok(exceeded)
+    }
+
+    /// All active lockouts, usernames first.
+    pub async fn lockouts(&self) -> Result<Vec<Lockout>, anyhow::Error> {
+        let mut lockouts = Vec::new();
+        for kind in [LockoutKind::Username, LockoutKind::Ip] {
+            let prefix = lockout_key(kind, "");
+            for key in self.scan(&format!("{}*", prefix)).await? {
+                let ttl: i64 = redis::cmd("TTL")
+                    .arg(&key)
+                    .query_async(&mut self.redis.clone())
+                    .await
+                    .context("Failed to retrieve the expiry of a login lockout from Redis.")?;
+                // The lockout expired in the meantime
+                if ttl < 0 {
+                    continue;
+                }
+                lockouts.push(Lockout {
+                    kind,
+                    value: key[prefix.len()..].to_string(),
+                    remaining_seconds: ttl as u64,
+                });
+            }
+        }
+        lockouts.sort_by(|a, b| (a.kind == LockoutKind::Ip, &a.value).cmp(&(b.kind == LockoutKind::Ip, &b.value)));
+        Ok(lockouts)
+    }
+
+    /// Lift a lockout and forget the failures that led to it.
+    #[tracing::instrument(name = "Unlock logins", skip(self))]
+    pub async fn unlock(&self, kind: LockoutKind, value: &str) -> Result<(), anyhow::Error> {
+        let value = match kind {
+            LockoutKind::Username => normalize(value),
+            LockoutKind::Ip => value.to_string(),
+        };
+        redis::cmd("DEL")
+            .arg(lockout_key(kind, &value))
+            .arg(failures_key(kind, &value))
+            .query_async::<_, ()>(&mut self.redis.clone())
+            .await
+            .context("Failed to lift a login lockout in Redis.")?;
+        tracing::warn!(security_event = "login_unlocked", kind = kind.as_str(), value = %value, "Logins have been unlocked");
+        Ok(())
+    }
+
+    // `SCAN` rather than `KEYS`, which would block Redis while it goes through every key
+    async fn scan(&self, pattern: &str) -> Result<Vec<String>, anyhow::Error> {
+        let mut keys = Vec::new();
+        let mut cursor = 0u64;
+        loop {
+            let (next_cursor, batch): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(pattern)
+                .arg("COUNT")
+                .arg(100)
+                .query_async(&mut self.redis.clone())
+                .await
+                .context("Failed to list login lockouts in Redis.")?;
+            keys.extend(batch);
+            if next_cursor == 0 {
+                return Ok(keys);
+            }
+            cursor = next_cursor;
+        }
+    }
+
+    async fn failures(&self, kind: LockoutKind, value: &str) -> Result<u64, anyhow::Error> {
+        let failures: Option<u64> = redis::cmd("GET")
+            .arg(failures_key(kind, value))
+            .query_async(&mut self.redis.clone())
+            .await
+            .context("Failed to retrieve failed logins from Redis.")?;
+        Ok(failures.unwrap_or(0))
+    }
+
+    async fn count_failure(&self, kind: LockoutKind, value: &str) -> Result<u64, anyhow::Error> {
+        self.count(&failures_key(kind, value))
+            .await
+            .context("Failed to count a failed login in Redis.")
+    }
+
+    // Fixed window counter: the first hit in a window sets its expiry.
+    async fn count(&self, key: &str) -> Result<u64, redis::RedisError> {
+        let (hits,): (u64,) = redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(key)
+            .arg(0)
+            .arg("EX")
+            .arg(self.settings.failure_window_seconds)
+            .arg("NX")
+            .ignore()
+            .cmd("INCR")
+            .arg(key)
+            .query_async(&mut self.redis.clone())
+            .await?;
+        Ok(hits)
+    }
+
+    async fn lock_out(&self, kind: LockoutKind, value: &str, failures: u64) -> Result<(), anyhow::Error> {
+        redis::cmd("SET")
+            .arg(lockout_key(kind, value))
+            .arg(failures)
+            .arg("EX")
+            .arg(self.settings.lockout_seconds)
+            .query_async::<_, ()>(&mut self.redis.clone())
+            .await
+            .context("Failed to store a login lockout in Redis.")?;
+        tracing::warn!(
+            security_event = "login_lockout",
+            kind = kind.as_str(),
+            value = %value,
+            failures,
+            lockout_seconds = self.settings.lockout_seconds,
+            "Locked out logins after too many failures"
+        );
+        Ok(())
+    }
+}
+
+// Usernames differing only by case share their counters
+fn normalize(username: &str) -> String {
+    username.trim().to_lowercase()
+}
+
+fn failures_key(kind: LockoutKind, value: &str) -> String {
+    format!("{}:failures:{}:{}", KEY_PREFIX, kind.as_str(), value)
+}
+
+fn link_requests_key(kind: LockoutKind, value: &str) -> String {
+    format!("{}:link_requests:{}:{}", KEY_PREFIX, kind.as_str(), value)
+}
+
+fn lockout_key(kind: LockoutKind, value: &str) -> String {
+    format!("{}:lockout:{}:{}", KEY_PREFIX, kind.as_str(), value)
+}
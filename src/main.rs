@@ -1,15 +1,8 @@
-use zero2prod::configuration::get_configuration;
-use zero2prod::startup::Application;
-use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use clap::Parser;
+use zero2prod::cli::{run, Cli};
 
 
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
-    init_subscriber(subscriber);
-
-    let configuration = get_configuration().expect("Failed to read configuration.");
-    let application = Application::build(configuration).await?;
-    application.run_until_stopped().await?;
-    Ok(())
+async fn main() -> anyhow::Result<()> {
+    run(Cli::parse()).await
 }
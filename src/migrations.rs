@@ -0,0 +1,175 @@
+use crate::configuration::DatabaseSettings;
+use crate::domain::SubscriberEmail;
+use anyhow::Context;
+use sqlx::migrate::{Migration, Migrator};
+use sqlx::PgPool;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// The migrations in `migrations/`, built into the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Apply pending migrations, normalizing subscriber emails in Rust on the way
+/// if need be. Instances doing it at the same time wait for each other:
+/// `Migrator::run` holds a Postgres advisory lock while it works.
+#[tracing::instrument(name = "Migrate the database", skip(pool))]
+pub async fn migrate(pool: &PgPool) -> Result<(), anyhow::Error> {
+    if !applied_migrations(pool).await?.contains_key(&CASE_INSENSITIVE_EMAILS) {
+        let before = Migrator {
+            migrations: Cow::Owned(MIGRATOR.iter().filter(|m| m.version < CASE_INSENSITIVE_EMAILS).cloned().collect()),
+            ignore_missing: true,
+        };
+        before.run(pool).await.context("Failed to migrate the database.")?;
+        normalize_subscriber_emails(pool).await?;
+    }
+    MIGRATOR.run(pool).await.context("Failed to migrate the database.")
+}
+
+// The migration that makes subscriber emails unique regardless of case. Postgres
+// can't normalize emails the way `SubscriberEmail::parse` does, so it is done
+// in Rust right before.
+const CASE_INSENSITIVE_EMAILS: i64 = 20230122101530;
+
+// Bring the emails of existing subscribers to the form new ones get, keeping one
+// subscription for each: a confirmed one if any, otherwise the oldest. The others
+// are set aside in `removed_duplicate_subscriptions`. Emails that no longer parse
+// are left alone. Queries are unchecked: they run against the schema of the
+// time, not the one the binary is built for.
+#[tracing::instrument(name = "Normalize subscriber emails", skip(pool))]
+async fn normalize_subscriber_emails(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await.context("Failed to acquire a Postgres connection from the pool")?;
+    // Instances migrating together take turns: whoever comes second has nothing left to do
+    sqlx::query("LOCK TABLE subscriptions IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut transaction)
+        .await
+        .context("Failed to lock the subscriptions table.")?;
+    let subscribers: Vec<(Uuid, String)> =
+        sqlx::query_as("SELECT id, email FROM subscriptions ORDER BY status = 'confirmed' DESC, subscribed_at, id")
+            .fetch_all(&mut transaction)
+            .await
+            .context("Failed to perform a query to retrieve subscribers.")?;
+
+    let mut kept = HashMap::new();
+    let mut duplicates = Vec::new();
+    let mut changed = Vec::new();
+    for (id, email) in subscribers {
+        let normalized = match SubscriberEmail::parse(email.clone()) {
+            Ok(normalized) => normalized.as_ref().to_string(),
+            Err(_) => email.clone(),
+        };
+        match kept.get(&normalized.to_lowercase()) {
+            Some(kept_id) => duplicates.push((id, *kept_id)),
+            None => {
+                kept.insert(normalized.to_lowercase(), id);
+                if normalized != email {
+                    changed.push((id, normalized));
+                }
+            }
+        }
+    }
+
+    // Duplicates go first, or they would clash with the emails they duplicate
+    for (id, kept_id) in &duplicates {
+        sqlx::query(
+            r#"
+            INSERT INTO removed_duplicate_subscriptions (id, email, name, subscribed_at, status, kept_id)
+            SELECT id, email, name, subscribed_at, status::text, $2 FROM subscriptions WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(kept_id)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to set aside a duplicate subscriber.")?;
+        for query in [
+            "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+            "DELETE FROM email_change_requests WHERE subscriber_id = $1",
+            "DELETE FROM subscriptions WHERE id = $1",
+        ] {
+            sqlx::query(query)
+                .bind(id)
+                .execute(&mut transaction)
+                .await
+                .context("Failed to remove a duplicate subscriber.")?;
+        }
+    }
+    for (id, email) in &changed {
+        sqlx::query("UPDATE subscriptions SET email = $1 WHERE id = $2")
+            .bind(email)
+            .bind(id)
+            .execute(&mut transaction)
+            .await
+            .context("Failed to normalize the email of a subscriber.")?;
+    }
+    transaction.commit().await.context("Failed to commit SQL transaction to normalize subscriber emails.")?;
+    if !duplicates.is_empty() {
+        tracing::warn!(
+            count = duplicates.len(),
+            "Set aside duplicate subscribers in removed_duplicate_subscriptions"
+        );
+    }
+    Ok(())
+}
+
+/// Make sure the schema is the one this binary expects before serving anything:
+/// apply pending migrations if `settings` allow it, or fail right away.
+pub async fn prepare_database(pool: &PgPool, settings: &DatabaseSettings) -> Result<(), anyhow::Error> {
+    if settings.migrate_on_startup {
+        return migrate(pool).await;
+    }
+    let pending = pending_migrations(pool).await?;
+    if let Some(first) = pending.first() {
+        anyhow::bail!(
+            "The database schema is behind: {} migration(s) to apply, starting with {}_{}. \
+            Run `zero2prod migrate` or set `database.migrate_on_startup`.",
+            pending.len(),
+            first.version,
+            first.description,
+        );
+    }
+    Ok(())
+}
+
+/// The embedded migrations the database is missing. Fails if one was only
+/// partially applied or was changed after it was.
+#[tracing::instrument(name = "Look for pending migrations", skip(pool))]
+pub async fn pending_migrations(pool: &PgPool) -> Result<Vec<&'static Migration>, anyhow::Error> {
+    let applied = applied_migrations(pool).await?;
+    let mut pending = Vec::new();
+    for migration in MIGRATOR.iter() {
+        match applied.get(&migration.version) {
+            None => pending.push(migration),
+            Some((false, _)) => anyhow::bail!(
+                "Migration {}_{} failed part way and needs fixing by hand.",
+                migration.version,
+                migration.description,
+            ),
+            Some((true, checksum)) if checksum[..] != migration.checksum[..] => anyhow::bail!(
+                "Migration {}_{} was changed after it was applied.",
+                migration.version,
+                migration.description,
+            ),
+            Some(_) => {}
+        }
+    }
+    Ok(pending)
+}
+
+// Whether each applied migration succeeded, and its checksum, by version
+async fn applied_migrations(pool: &PgPool) -> Result<HashMap<i64, (bool, Vec<u8>)>, anyhow::Error> {
+    // Until the first migration, sqlx's bookkeeping table doesn't exist
+    let initialised = sqlx::query!(r#"SELECT to_regclass('_sqlx_migrations') IS NOT NULL AS "initialised!""#)
+        .fetch_one(pool)
+        .await
+        .context("Failed to check whether the database has been migrated.")?
+        .initialised;
+    if !initialised {
+        return Ok(HashMap::new());
+    }
+    let rows = sqlx::query!("SELECT version, success, checksum FROM _sqlx_migrations")
+        .fetch_all(pool)
+        .await
+        .context("Failed to perform a query to retrieve the applied migrations.")?;
+    Ok(rows.into_iter().map(|r| (r.version, (r.success, r.checksum))).collect())
+}
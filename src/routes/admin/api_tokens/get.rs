@@ -0,0 +1,83 @@
+use crate::authentication::{ApiScope, Role, UserId};
+use crate::utils::e500;
+use crate::csrf::CsrfToken;
+use crate::templates::{render, FlashMessages};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use askama::Template;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+struct ApiToken {
+    token_id: Uuid,
+    name: String,
+    scopes: Vec<String>,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    fn status(&self) -> &'static str {
+        if self.revoked_at.is_some() {
+            "revoked"
+        } else if matches!(self.expires_at, Some(expires_at) if expires_at <= Utc::now()) {
+            "expired"
+        } else {
+            "active"
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.status() == "active"
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin/api_tokens.html")]
+struct ApiTokensPage {
+    tokens: Vec<ApiToken>,
+    scopes: Vec<ApiScope>,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+}
+
+pub async fn api_tokens_page(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render(&ApiTokensPage {
+        tokens: get_api_tokens(&pool, **user_id).await.map_err(e500)?,
+        // Only offer what the user's role allows
+        scopes: ApiScope::ALL
+            .iter()
+            .copied()
+            .filter(|scope| role.can(scope.permission()))
+            .collect(),
+        csrf_token,
+        flash_messages,
+    })
+}
+
+#[tracing::instrument(name = "Get the API tokens of a user", skip(pool))]
+async fn get_api_tokens(pool: &PgPool, user_id: Uuid) -> Result<Vec<ApiToken>, anyhow::Error> {
+    let tokens = sqlx::query_as!(
+        ApiToken,
+        r#"
+        SELECT token_id, name, scopes, created_at, expires_at, last_used_at, revoked_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve API tokens.")?;
+    Ok(tokens)
+}
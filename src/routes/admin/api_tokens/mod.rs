@@ -0,0 +1,5 @@
+mod get;
+pub use get::api_tokens_page;
+
+mod post;
+pub use post::{create_api_token, revoke_api_token};
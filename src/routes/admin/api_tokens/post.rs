@@ -0,0 +1,129 @@
+use crate::authentication::{generate_api_token, hash_api_token, ApiScope, Role, UserId};
+use crate::templates::{render, FlashMessages};
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use askama::Template;
+use chrono::{Duration, Utc};
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// Checkboxes repeat the `scope` field, so the form comes in as pairs
+type FormPairs = Vec<(String, String)>;
+
+#[derive(Template)]
+#[template(path = "admin/api_token_created.html")]
+struct ApiTokenCreatedPage<'a> {
+    name: &'a str,
+    token: &'a str,
+    flash_messages: FlashMessages,
+}
+
+#[tracing::instrument(name = "Create an API token", skip(form, pool, role), fields(user_id = %*user_id))]
+pub async fn create_api_token(
+    form: web::Form<FormPairs>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut name = String::new();
+    let mut scopes = Vec::new();
+    let mut expires_in_days = String::new();
+    for (key, value) in form.into_inner() {
+        match key.as_str() {
+            "name" => name = value.trim().to_string(),
+            "scope" => match ApiScope::parse(&value) {
+                Some(scope) if scopes.contains(&scope) => {}
+                Some(scope) => scopes.push(scope),
+                None => {
+                    FlashMessage::error(format!("Unknown scope: {}.", value)).send();
+                    return Ok(see_other("/admin/api_tokens"));
+                }
+            },
+            "expires_in_days" => expires_in_days = value.trim().to_string(),
+            _ => {}
+        }
+    }
+
+    if name.is_empty() {
+        FlashMessage::error("The token needs a name.").send();
+        return Ok(see_other("/admin/api_tokens"));
+    }
+    if scopes.is_empty() {
+        FlashMessage::error("The token needs at least one scope.").send();
+        return Ok(see_other("/admin/api_tokens"));
+    }
+    if let Some(scope) = scopes.iter().find(|scope| !role.can(scope.permission())) {
+        FlashMessage::error(format!("Your role does not allow the {} scope.", scope)).send();
+        return Ok(see_other("/admin/api_tokens"));
+    }
+    let expires_at = if expires_in_days.is_empty() {
+        None
+    } else {
+        match expires_in_days.parse::<u16>() {
+            Ok(days) if days > 0 => Some(Utc::now() + Duration::days(days.into())),
+            _ => {
+                FlashMessage::error("The expiry must be a positive number of days.").send();
+                return Ok(see_other("/admin/api_tokens"));
+            }
+        }
+    };
+
+    let token = generate_api_token();
+    let scopes: Vec<String> = scopes.iter().map(|scope| scope.as_str().to_string()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (token_id, user_id, name, token_hash, scopes, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        Uuid::new_v4(),
+        **user_id,
+        name,
+        hash_api_token(&token),
+        &scopes,
+        Utc::now(),
+        expires_at,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store an API token.")
+    .map_err(e500)?;
+
+    // The only time the token is ever shown
+    render(&ApiTokenCreatedPage {
+        name: &name,
+        token: token.expose_secret(),
+        flash_messages: FlashMessages::default(),
+    })
+}
+
+#[tracing::instrument(name = "Revoke an API token", skip(pool), fields(user_id = %*user_id))]
+pub async fn revoke_api_token(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // Users can only revoke their own tokens
+    let revoked = sqlx::query!(
+        r#"
+        UPDATE api_tokens SET revoked_at = now()
+        WHERE token_id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        path.into_inner(),
+        **user_id,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to revoke an API token.")
+    .map_err(e500)?
+    .rows_affected();
+
+    if revoked == 0 {
+        FlashMessage::error("There is no such token, or it has already been revoked.").send();
+    } else {
+        FlashMessage::info("The token has been revoked.").send();
+    }
+    Ok(see_other("/admin/api_tokens"))
+}
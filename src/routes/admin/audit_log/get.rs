@@ -0,0 +1,116 @@
+use crate::audit_log::{get_audit_log, to_csv, AuditAction, AuditLogEntry, AuditLogFilter};
+use crate::templates::{render, render_with_status, ErrorPage, FlashMessages};
+use crate::utils::e500;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse};
+use askama::Template;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use sqlx::PgPool;
+
+// The page only shows the most recent entries, the export has all of them
+const PAGE_LIMIT: i64 = 500;
+
+/// The filters of the page, as submitted by its form: empty fields match everything.
+#[derive(serde::Deserialize)]
+pub struct QueryParameters {
+    #[serde(default)]
+    action: String,
+    #[serde(default)]
+    actor: String,
+    // `YYYY-MM-DD`, both inclusive
+    #[serde(default)]
+    since: String,
+    #[serde(default)]
+    until: String,
+}
+
+impl QueryParameters {
+    fn filter(&self) -> Result<AuditLogFilter, String> {
+        let action = match self.action.as_str() {
+            "" => None,
+            action => Some(AuditAction::parse(action).ok_or_else(|| format!("Unknown action: {}.", action))?),
+        };
+        let actor = match self.actor.trim() {
+            "" => None,
+            actor => Some(actor.to_string()),
+        };
+        Ok(AuditLogFilter {
+            action,
+            actor,
+            since: parse_day(&self.since)?,
+            until: parse_day(&self.until)?.map(|day| day + Duration::days(1)),
+        })
+    }
+
+    // For the export link, so that it exports what the page shows
+    fn query_string(&self) -> String {
+        url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("action", &self.action)
+            .append_pair("actor", &self.actor)
+            .append_pair("since", &self.since)
+            .append_pair("until", &self.until)
+            .finish()
+    }
+}
+
+// The start of a `YYYY-MM-DD` day, in UTC
+fn parse_day(day: &str) -> Result<Option<DateTime<Utc>>, String> {
+    if day.is_empty() {
+        return Ok(None);
+    }
+    let date = NaiveDate::parse_from_str(day, "%Y-%m-%d").map_err(|_| format!("Invalid date: {}.", day))?;
+    Ok(Some(DateTime::from_utc(date.and_hms(0, 0, 0), Utc)))
+}
+
+fn invalid_filter(message: &str) -> Result<HttpResponse, actix_web::Error> {
+    render_with_status(HttpResponse::BadRequest(), &ErrorPage::new("Invalid filter", message))
+}
+
+#[derive(Template)]
+#[template(path = "admin/audit_log.html")]
+struct AuditLogPage<'a> {
+    entries: Vec<AuditLogEntry>,
+    parameters: &'a QueryParameters,
+    actions: &'static [AuditAction],
+    export_query: String,
+    limit: usize,
+    flash_messages: FlashMessages,
+}
+
+pub async fn audit_log_page(
+    parameters: web::Query<QueryParameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: FlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = match parameters.filter() {
+        Ok(filter) => filter,
+        Err(message) => return invalid_filter(&message),
+    };
+    render(&AuditLogPage {
+        entries: get_audit_log(&pool, &filter, Some(PAGE_LIMIT)).await.map_err(e500)?,
+        parameters: &parameters,
+        actions: &AuditAction::ALL,
+        export_query: parameters.query_string(),
+        limit: PAGE_LIMIT as usize,
+        flash_messages,
+    })
+}
+
+#[tracing::instrument(name = "Export the audit log", skip(parameters, pool))]
+pub async fn export_audit_log(
+    parameters: web::Query<QueryParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = match parameters.filter() {
+        Ok(filter) => filter,
+        Err(message) => return invalid_filter(&message),
+    };
+    let entries = get_audit_log(&pool, &filter, None).await.map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("audit_log.csv".to_string())],
+        })
+        .body(to_csv(&entries)))
+}
@@ -1,14 +1,33 @@
 use crate::session_state::TypedSession;
 use actix_web::http::header::LOCATION;
-use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web::{web, HttpResponse};
+use crate::authentication::{Permission, Role};
 use crate::utils::{e500};
+use crate::csrf::CsrfToken;
+use crate::templates::{render, FlashMessages};
 use anyhow::Context;
+use askama::Template;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+#[derive(Template)]
+#[template(path = "admin/dashboard.html")]
+struct DashboardPage {
+    username: String,
+    can_manage_users: bool,
+    can_view_reports: bool,
+    can_manage_subscribers: bool,
+    can_view_audit_log: bool,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+}
+
 pub async fn admin_dashboard(
     session: TypedSession,
     pool: web::Data<PgPool>,
+    role: web::ReqData<Role>,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
 ) -> Result<HttpResponse, actix_web::Error> {
     let username = if let Some(user_id) = session.get_user_id().map_err(e500)? {
         get_username(user_id, &pool).await.map_err(e500)?
@@ -17,29 +36,15 @@ pub async fn admin_dashboard(
             .insert_header((LOCATION, "/login"))
             .finish());
     };
-    Ok(HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta http-equiv="content-type" content="text/html; charset=utf-8">
-    <title>Admin dashboard</title>
-</head>
-<body>
-    <p>Welcome {username}!</p>
-    <p>Available actions:</p>
-    <ol>
-        <li><a href="/admin/password">Change password</a></li>
-        <li>
-          <form name="logoutForm" action="/admin/logout" method="post">
-            <input type="submit" value="Logout">
-          </form>
-        </li>
-    </ol>
-</body>
-</html>"#,
-        )))
+    render(&DashboardPage {
+        username,
+        can_manage_users: role.can(Permission::ManageUsers),
+        can_view_reports: role.can(Permission::ViewReports),
+        can_manage_subscribers: role.can(Permission::ManageSubscribers),
+        can_view_audit_log: role.can(Permission::ViewAuditLog),
+        csrf_token,
+        flash_messages,
+    })
 }
 
 #[tracing::instrument(name = "Get username", skip(pool))]
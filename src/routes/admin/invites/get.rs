@@ -0,0 +1,58 @@
+use crate::authentication::Role;
+use crate::utils::e500;
+use crate::csrf::CsrfToken;
+use crate::templates::{render, FlashMessages};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use askama::Template;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+struct PendingInvite {
+    invite_id: Uuid,
+    email: String,
+    role: Role,
+    invited_by: String,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/invites.html")]
+struct InvitesPage {
+    invites: Vec<PendingInvite>,
+    roles: [Role; 4],
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+}
+
+pub async fn invites_page(
+    pool: web::Data<PgPool>,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render(&InvitesPage {
+        invites: get_pending_invites(&pool).await.map_err(e500)?,
+        roles: Role::ALL,
+        csrf_token,
+        flash_messages,
+    })
+}
+
+#[tracing::instrument(name = "Get pending admin invites", skip(pool))]
+async fn get_pending_invites(pool: &PgPool) -> Result<Vec<PendingInvite>, anyhow::Error> {
+    let invites = sqlx::query_as!(
+        PendingInvite,
+        r#"
+        SELECT i.invite_id, i.email, i.role AS "role: Role", u.username AS invited_by, i.expires_at
+        FROM admin_invites i
+        JOIN users u ON u.user_id = i.invited_by
+        WHERE i.accepted_at IS NULL AND i.revoked_at IS NULL AND i.expires_at > now()
+        ORDER BY i.created_at
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve pending admin invites.")?;
+    Ok(invites)
+}
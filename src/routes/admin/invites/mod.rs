@@ -0,0 +1,5 @@
+mod get;
+pub use get::invites_page;
+
+mod post;
+pub use post::{hash_invite_token, invite_user, revoke_invite};
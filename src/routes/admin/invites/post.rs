@@ -0,0 +1,140 @@
+use crate::authentication::{Role, UserId};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::routes::generate_subscription_token;
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// How long an invitee has to accept
+const INVITE_VALIDITY_DAYS: i64 = 3;
+
+#[derive(serde::Deserialize)]
+pub struct InviteFormData {
+    email: String,
+    role: Role,
+}
+
+#[tracing::instrument(
+    name = "Invite an admin user",
+    skip(form, pool, email_client, base_url, current_user),
+    fields(email = %form.email, role = %form.role)
+)]
+pub async fn invite_user(
+    form: web::Form<InviteFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    current_user: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let InviteFormData { email, role } = form.0;
+    let email = match SubscriberEmail::parse(email) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(e.to_string()).send();
+            return Ok(see_other("/admin/invites"));
+        }
+    };
+
+    let invite_token = generate_subscription_token();
+    store_invite(&pool, &email, role, &invite_token, **current_user)
+        .await
+        .context("Failed to store the admin invite.")
+        .map_err(e500)?;
+    send_invite(&email_client, &email, role, &base_url.0, &invite_token)
+        .await
+        .context("Failed to send the admin invite.")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!("An invite has been sent to {}.", email)).send();
+    Ok(see_other("/admin/invites"))
+}
+
+#[tracing::instrument(name = "Revoke an admin invite", skip(pool))]
+pub async fn revoke_invite(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let revoked = sqlx::query!(
+        r#"
+        UPDATE admin_invites SET revoked_at = now()
+        WHERE invite_id = $1 AND accepted_at IS NULL AND revoked_at IS NULL
+        "#,
+        path.into_inner(),
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to revoke an admin invite.")
+    .map_err(e500)?
+    .rows_affected();
+
+    if revoked == 0 {
+        FlashMessage::error("The invite has already been used or revoked.").send();
+    } else {
+        FlashMessage::info("The invite has been revoked.").send();
+    }
+    Ok(see_other("/admin/invites"))
+}
+
+/// Invite tokens are stored hashed: a database leak must not let anyone in.
+pub fn hash_invite_token(invite_token: &str) -> String {
+    format!("{:x}", Sha256::digest(invite_token.as_bytes()))
+}
+
+#[tracing::instrument(name = "Store admin invite in the database", skip(pool, invite_token))]
+async fn store_invite(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+    role: Role,
+    invite_token: &str,
+    invited_by: Uuid,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO admin_invites (invite_id, email, role, invite_token_hash, invited_by, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        Uuid::new_v4(),
+        email.as_ref(),
+        role as Role,
+        hash_invite_token(invite_token),
+        invited_by,
+        now,
+        now + Duration::days(INVITE_VALIDITY_DAYS),
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Send an admin invite", skip(email_client, base_url, invite_token))]
+async fn send_invite(
+    email_client: &EmailClient,
+    email: &SubscriberEmail,
+    role: Role,
+    base_url: &str,
+    invite_token: &str,
+) -> Result<(), reqwest::Error> {
+    let invite_link = format!("{}/invites/accept?invite_token={}", base_url, invite_token);
+    let plain_body = format!(
+        "You have been invited to help run our newsletter as {}.\n\
+        Visit {} within {} days to set up your account.",
+        role, invite_link, INVITE_VALIDITY_DAYS
+    );
+    let html_body = format!(
+        "You have been invited to help run our newsletter as {}.<br />\
+        Click <a href=\"{}\">here</a> within {} days to set up your account.",
+        role, invite_link, INVITE_VALIDITY_DAYS
+    );
+
+    email_client
+        .send_email(email, "You have been invited to the newsletter admin", &html_body, &plain_body)
+        .await
+}
@@ -0,0 +1,26 @@
+use crate::login_protection::{LoginProtection, Lockout};
+use crate::utils::e500;
+use crate::csrf::CsrfToken;
+use crate::templates::{render, FlashMessages};
+use actix_web::{web, HttpResponse};
+use askama::Template;
+
+#[derive(Template)]
+#[template(path = "admin/lockouts.html")]
+struct LockoutsPage {
+    lockouts: Vec<Lockout>,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+}
+
+pub async fn lockouts_page(
+    login_protection: web::Data<LoginProtection>,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render(&LockoutsPage {
+        lockouts: login_protection.lockouts().await.map_err(e500)?,
+        csrf_token,
+        flash_messages,
+    })
+}
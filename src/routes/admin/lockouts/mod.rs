@@ -0,0 +1,5 @@
+mod get;
+pub use get::lockouts_page;
+
+mod post;
+pub use post::unlock_logins;
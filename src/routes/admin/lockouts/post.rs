@@ -0,0 +1,26 @@
+use crate::authentication::UserId;
+use crate::login_protection::{LockoutKind, LoginProtection};
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+
+#[derive(serde::Deserialize)]
+pub struct UnlockFormData {
+    kind: LockoutKind,
+    value: String,
+}
+
+#[tracing::instrument(
+    name = "Unlock logins",
+    skip(form, login_protection),
+    fields(kind = form.kind.as_str(), value = %form.value, unlocked_by = %*current_user)
+)]
+pub async fn unlock_logins(
+    form: web::Form<UnlockFormData>,
+    login_protection: web::Data<LoginProtection>,
+    current_user: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    login_protection.unlock(form.kind, &form.value).await.map_err(e500)?;
+    FlashMessage::info("Logins have been unlocked.").send();
+    Ok(see_other("/admin/lockouts"))
+}
@@ -1,13 +1,29 @@
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use crate::authentication::{revoke_session, UserId};
 use crate::session_state::TypedSession;
 use crate::utils::{e500, see_other};
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
 
-pub async fn log_out(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
+pub async fn log_out(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    origin: RequestOrigin,
+) -> Result<HttpResponse, actix_web::Error> {
     if session.get_user_id().map_err(e500)?.is_none() {
         return Ok(see_other("/login"));
     }
     
+    if let Some(session_id) = session.get_session_id().map_err(e500)? {
+        revoke_session(&pool, **user_id, session_id).await.map_err(e500)?;
+    }
+    AuditEntry::new(AuditAction::LoggedOut)
+        .actor(**user_id)
+        .record(pool.get_ref(), &origin)
+        .await
+        .map_err(e500)?;
     session.log_out();
     FlashMessage::info("You have successfully logged out.").send();
     return Ok(see_other("/login"));
@@ -1,7 +1,27 @@
+mod api_tokens;
+mod audit_log;
 mod dashboard;
+mod invites;
+mod lockouts;
 mod logout;
+mod passkeys;
 mod password;
+mod reports;
+mod sessions;
+mod subscribers;
+mod two_factor;
+mod users;
 
+pub use api_tokens::*;
+pub use audit_log::*;
+pub use invites::*;
+pub use lockouts::*;
+pub use passkeys::*;
 pub use password::*;
+pub use reports::*;
+pub use sessions::*;
+pub use subscribers::*;
+pub use two_factor::*;
+pub use users::*;
 pub use logout::log_out;
-pub use dashboard::admin_dashboard;
\ No newline at end of file
+pub use dashboard::{admin_dashboard, get_username};
\ No newline at end of file
@@ -0,0 +1,56 @@
+use crate::authentication::UserId;
+use crate::utils::e500;
+use crate::csrf::CsrfToken;
+use crate::templates::{render, FlashMessages};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use askama::Template;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+struct Passkey {
+    passkey_id: Uuid,
+    name: String,
+    created_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/passkeys.html")]
+struct PasskeysPage {
+    passkeys: Vec<Passkey>,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+}
+
+pub async fn passkeys_page(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render(&PasskeysPage {
+        passkeys: get_passkeys(&pool, **user_id).await.map_err(e500)?,
+        csrf_token,
+        flash_messages,
+    })
+}
+
+#[tracing::instrument(name = "Get the passkeys of a user", skip(pool))]
+async fn get_passkeys(pool: &PgPool, user_id: Uuid) -> Result<Vec<Passkey>, anyhow::Error> {
+    let passkeys = sqlx::query_as!(
+        Passkey,
+        r#"
+        SELECT passkey_id, name, created_at, last_used_at
+        FROM passkeys
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve passkeys.")?;
+    Ok(passkeys)
+}
@@ -0,0 +1,5 @@
+mod get;
+pub use get::passkeys_page;
+
+mod post;
+pub use post::{delete_passkey, passkey_registration_options, register_passkey, rename_passkey};
@@ -0,0 +1,147 @@
+use crate::authentication::{
+    generate_passkey_challenge, get_passkey_credential_ids, store_passkey, totp_enabled, two_factor_required,
+    RegistrationResponse, RelyingParty, UserId,
+};
+use crate::routes::admin::dashboard::get_username;
+use crate::session_state::TypedSession;
+use crate::utils::{e500, script_redirect, see_other};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct NewPasskeyData {
+    name: String,
+    #[serde(flatten)]
+    response: RegistrationResponse,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RenameFormData {
+    name: String,
+}
+
+/// Start adding a passkey: what the page's script passes on to the browser.
+#[tracing::instrument(name = "Start registering a passkey", skip(pool, rp, session), fields(user_id = %*user_id))]
+pub async fn passkey_registration_options(
+    pool: web::Data<PgPool>,
+    rp: web::Data<RelyingParty>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = **user_id;
+    let username = get_username(user_id, &pool).await.map_err(e500)?;
+    let existing = get_passkey_credential_ids(&pool, user_id).await.map_err(e500)?;
+    let challenge = generate_passkey_challenge();
+    session.insert_passkey_registration_challenge(&challenge).map_err(e500)?;
+    Ok(HttpResponse::Ok().json(rp.registration_options(&challenge, user_id, &username, &existing)))
+}
+
+#[tracing::instrument(name = "Register a passkey", skip(data, pool, rp, session), fields(user_id = %*user_id))]
+pub async fn register_passkey(
+    data: web::Json<NewPasskeyData>,
+    pool: web::Data<PgPool>,
+    rp: web::Data<RelyingParty>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let challenge = match session.take_passkey_registration_challenge().map_err(e500)? {
+        Some(challenge) => challenge,
+        None => {
+            FlashMessage::error("Adding the passkey took too long, please try again.").send();
+            return Ok(script_redirect(StatusCode::BAD_REQUEST, "/admin/passkeys"));
+        }
+    };
+    let name = data.name.trim();
+    if name.is_empty() {
+        FlashMessage::error("The passkey needs a name.").send();
+        return Ok(script_redirect(StatusCode::BAD_REQUEST, "/admin/passkeys"));
+    }
+    let passkey = match rp.verify_registration(&challenge, &data.response) {
+        Ok(passkey) => passkey,
+        Err(e) => {
+            tracing::warn!(error.cause_chain = ?e, "Rejected a new passkey");
+            FlashMessage::error("The passkey could not be verified.").send();
+            return Ok(script_redirect(StatusCode::BAD_REQUEST, "/admin/passkeys"));
+        }
+    };
+
+    if store_passkey(&pool, **user_id, name, &passkey).await.map_err(e500)? {
+        FlashMessage::info("The passkey has been added.").send();
+        Ok(script_redirect(StatusCode::OK, "/admin/passkeys"))
+    } else {
+        FlashMessage::error("This passkey is already registered.").send();
+        Ok(script_redirect(StatusCode::BAD_REQUEST, "/admin/passkeys"))
+    }
+}
+
+#[tracing::instrument(name = "Rename a passkey", skip(form, pool), fields(user_id = %*user_id))]
+pub async fn rename_passkey(
+    path: web::Path<Uuid>,
+    form: web::Form<RenameFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = form.name.trim();
+    if name.is_empty() {
+        FlashMessage::error("The passkey needs a name.").send();
+        return Ok(see_other("/admin/passkeys"));
+    }
+    // Users can only rename their own passkeys
+    let renamed = sqlx::query!(
+        "UPDATE passkeys SET name = $1 WHERE passkey_id = $2 AND user_id = $3",
+        name,
+        path.into_inner(),
+        **user_id,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to rename a passkey.")
+    .map_err(e500)?
+    .rows_affected();
+
+    if renamed == 0 {
+        FlashMessage::error("There is no such passkey.").send();
+    } else {
+        FlashMessage::info("The passkey has been renamed.").send();
+    }
+    Ok(see_other("/admin/passkeys"))
+}
+
+#[tracing::instrument(name = "Delete a passkey", skip(pool), fields(user_id = %*user_id))]
+pub async fn delete_passkey(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = **user_id;
+    // As with authenticator apps, the last second factor stays when one is required
+    if two_factor_required(&pool).await.map_err(e500)? && !totp_enabled(&pool, user_id).await.map_err(e500)? {
+        let remaining = get_passkey_credential_ids(&pool, user_id).await.map_err(e500)?.len();
+        if remaining <= 1 {
+            FlashMessage::error("Two-factor authentication is required, so your last passkey cannot be deleted.")
+                .send();
+            return Ok(see_other("/admin/passkeys"));
+        }
+    }
+    let deleted = sqlx::query!(
+        "DELETE FROM passkeys WHERE passkey_id = $1 AND user_id = $2",
+        path.into_inner(),
+        user_id,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to delete a passkey.")
+    .map_err(e500)?
+    .rows_affected();
+
+    if deleted == 0 {
+        FlashMessage::error("There is no such passkey.").send();
+    } else {
+        FlashMessage::info("The passkey has been deleted.").send();
+    }
+    Ok(see_other("/admin/passkeys"))
+}
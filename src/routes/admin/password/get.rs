@@ -1,61 +1,34 @@
+use crate::authentication::password_change_required;
 use crate::session_state::TypedSession;
 use crate::utils::{e500, see_other};
-use actix_web::http::header::ContentType;
-use actix_web::HttpResponse;
-use actix_web_flash_messages::IncomingFlashMessages;
-use std::fmt::Write;
+use crate::csrf::CsrfToken;
+use crate::templates::{render, FlashMessages};
+use actix_web::{web, HttpResponse};
+use askama::Template;
+use sqlx::PgPool;
+
+#[derive(Template)]
+#[template(path = "admin/password.html")]
+struct ChangePasswordPage {
+    change_required: bool,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+}
 
 pub async fn change_password_form(
     session: TypedSession,
-    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
 ) -> Result<HttpResponse, actix_web::Error> {
-    if session.get_user_id().map_err(e500)?.is_none() {
-        return Ok(see_other("/login"));
+    let user_id = match session.get_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => return Ok(see_other("/login")),
     };
-    let mut msg_html = String::new();
-    for m in flash_messages.iter() {
-        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
-    }
-    Ok(HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta http-equiv="content-type" content="text/html; charset=utf-8">
-    <title>Change Password</title>
-</head>
-<body>
-    {msg_html}
-    <form action="/admin/password" method="post">
-        <label>Current password
-            <input
-                type="password"
-                placeholder="Enter current password"
-                name="current_password"
-            >
-        </label>
-        <br>
-        <label>New password
-            <input
-                type="password"
-                placeholder="Enter new password"
-                name="new_password"
-            >
-        </label>
-        <br>
-        <label>Confirm new password
-            <input
-                type="password"
-                placeholder="Type the new password again"
-                name="new_password_check"
-            >
-        </label>
-        <br>
-        <button type="submit">Change password</button>
-    </form>
-    <p><a href="/admin/dashboard">&lt;- Back</a></p>
-</body>
-</html>"#,
-        )))
-}
\ No newline at end of file
+    let change_required = password_change_required(&pool, user_id).await.map_err(e500)?;
+    render(&ChangePasswordPage {
+        change_required,
+        csrf_token,
+        flash_messages,
+    })
+}
@@ -1,13 +1,13 @@
-use crate::authentication::{validate_credentials, AuthError, Credentials, UserId};
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use crate::authentication::{check_password_policy, revoke_user_sessions, validate_credentials, AuthError, Credentials, UserId};
 use crate::routes::admin::dashboard::get_username;
-use crate::session_state::TypedSession;
 use crate::utils::{e500, see_other};
-use actix_web::error::InternalError;
 use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
 use secrecy::{ExposeSecret, Secret};
 use sqlx::PgPool;
-use uuid::Uuid;
+use crate::configuration::PasswordHashingSettings;
+use crate::session_state::TypedSession;
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
@@ -16,23 +16,13 @@ pub struct FormData {
     new_password_check: Secret<String>,
 }
 
-async fn reject_anonymous_users(
-    session: TypedSession
-    ) -> Result<Uuid, actix_web::Error> {
-    match session.get_user_id().map_err(e500)? {
-        Some(user_id) => Ok(user_id),
-        None => {
-            let response = see_other("/login");
-            let e = anyhow::anyhow!("The user has not logged in");
-            Err(InternalError::from_response(e, response).into())
-        }
-    }
-}
-
 pub async fn change_password(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
     user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    origin: RequestOrigin,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
     if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
@@ -42,6 +32,14 @@ pub async fn change_password(
         .send();
         return Ok(see_other("/admin/password"));
     }
+    if form.new_password.expose_secret() == form.current_password.expose_secret() {
+        FlashMessage::error("The new password must be different from the current one.").send();
+        return Ok(see_other("/admin/password"));
+    }
+    if let Err(e) = check_password_policy(&form.new_password) {
+        FlashMessage::error(e.to_string()).send();
+        return Ok(see_other("/admin/password"));
+    }
 
     let username = get_username(*user_id, &pool).await.map_err(e500)?;
     let credentials = Credentials {
@@ -49,7 +47,7 @@ pub async fn change_password(
         password: form.0.current_password,
     };
 
-    if let Err(e) = validate_credentials(credentials, &pool).await {
+    if let Err(e) = validate_credentials(credentials, &hashing, &pool).await {
         return match e {
             AuthError::InvalidCredentials(_) => {
                 FlashMessage::error("The current password is incorrect.").send();
@@ -59,42 +57,20 @@ pub async fn change_password(
         };
     }
     
-    crate::authentication::change_password(*user_id, form.0.new_password, &pool)
+    crate::authentication::change_password(*user_id, form.0.new_password, &hashing, &pool)
+        .await
+        .map_err(e500)?;
+    // Only the session that changed the password stays logged in
+    let current_session_id = session.get_session_id().map_err(e500)?;
+    revoke_user_sessions(pool.get_ref(), *user_id, current_session_id)
+        .await
+        .map_err(e500)?;
+    AuditEntry::new(AuditAction::PasswordChanged)
+        .actor(*user_id)
+        .record(pool.get_ref(), &origin)
         .await
         .map_err(e500)?;
         
     FlashMessage::error("Your password has been changed.").send();
     Ok(see_other("/admin/password"))
 }
-
-#[tokio::test]
-async fn new_password_fields_must_match() {
-    // Arrange
-    let app = spawn_app().await;
-    let new_password = Uuid::new_v4().to_string();
-    let another_new_password = Uuid::new_v4().to_string();
-
-    // Act - Part 1 - Login
-    app.post_login(&serde_json::json!({
-        "username": &app.test_user.username,
-        "password": &app.test_user.password
-    }))
-    .await;
-
-    // Act - Part 2 - Try to change password
-    let response = app
-        .post_change_password(&serde_json::json!({
-            "current_password": &app.test_user.password,
-            "new_password": &new_password,
-            "new_password_check": &another_new_password,
-        }))
-        .await;
-    assert_is_redirect_to(&response, "/admin/password");
-
-    // Act - Part 3 - Follow the redirect
-    let html_page = app.get_change_password_html().await;
-    assert!(html_page.contains(
-        "<p><i>You entered two different new passwords - \
-         the field values must match.</i></p>"
-    ));
-}
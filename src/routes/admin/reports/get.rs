@@ -0,0 +1,72 @@
+use crate::domain::SubscriptionStatus;
+use crate::utils::e500;
+use crate::templates::{render, FlashMessages};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use askama::Template;
+use sqlx::PgPool;
+
+struct StatusCount {
+    status: SubscriptionStatus,
+    count: i64,
+}
+
+struct RecentActivity {
+    sign_ups: i64,
+    confirmations: i64,
+    unsubscriptions: i64,
+}
+
+#[derive(Template)]
+#[template(path = "admin/reports.html")]
+struct ReportsPage {
+    status_counts: Vec<StatusCount>,
+    last_30_days: RecentActivity,
+    flash_messages: FlashMessages,
+}
+
+pub async fn reports_page(
+    pool: web::Data<PgPool>,
+    flash_messages: FlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render(&ReportsPage {
+        status_counts: get_status_counts(&pool).await.map_err(e500)?,
+        last_30_days: get_recent_activity(&pool).await.map_err(e500)?,
+        flash_messages,
+    })
+}
+
+#[tracing::instrument(name = "Count subscribers by status", skip(pool))]
+async fn get_status_counts(pool: &PgPool) -> Result<Vec<StatusCount>, anyhow::Error> {
+    let counts = sqlx::query_as!(
+        StatusCount,
+        r#"
+        SELECT status AS "status: SubscriptionStatus", COUNT(*) AS "count!"
+        FROM subscriptions
+        GROUP BY status
+        ORDER BY status
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to count subscribers by status.")?;
+    Ok(counts)
+}
+
+#[tracing::instrument(name = "Count recent subscription changes", skip(pool))]
+async fn get_recent_activity(pool: &PgPool) -> Result<RecentActivity, anyhow::Error> {
+    let activity = sqlx::query_as!(
+        RecentActivity,
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE subscribed_at > now() - interval '30 days') AS "sign_ups!",
+            COUNT(*) FILTER (WHERE confirmed_at > now() - interval '30 days') AS "confirmations!",
+            COUNT(*) FILTER (WHERE unsubscribed_at > now() - interval '30 days') AS "unsubscriptions!"
+        FROM subscriptions
+        "#,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform a query to count recent subscription changes.")?;
+    Ok(activity)
+}
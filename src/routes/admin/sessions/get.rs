@@ -0,0 +1,45 @@
+use crate::authentication::{get_active_sessions, ActiveSession, UserId};
+use crate::configuration::SessionSettings;
+use crate::session_state::TypedSession;
+use crate::utils::e500;
+use crate::csrf::CsrfToken;
+use crate::templates::{render, FlashMessages};
+use actix_web::{web, HttpResponse};
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Template)]
+#[template(path = "admin/sessions.html")]
+struct SessionsPage {
+    sessions: Vec<ActiveSession>,
+    current_session_id: Option<Uuid>,
+    idle_timeout_minutes: u64,
+    absolute_timeout_hours: u64,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+}
+
+impl SessionsPage {
+    fn is_current(&self, session: &ActiveSession) -> bool {
+        self.current_session_id == Some(session.session_id)
+    }
+}
+
+pub async fn sessions_page(
+    pool: web::Data<PgPool>,
+    settings: web::Data<SessionSettings>,
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render(&SessionsPage {
+        sessions: get_active_sessions(&pool, &settings, **user_id).await.map_err(e500)?,
+        current_session_id: session.get_session_id().map_err(e500)?,
+        idle_timeout_minutes: settings.idle_timeout_minutes,
+        absolute_timeout_hours: settings.absolute_timeout_hours,
+        csrf_token,
+        flash_messages,
+    })
+}
@@ -0,0 +1,5 @@
+mod get;
+pub use get::sessions_page;
+
+mod post;
+pub use post::{log_out_everywhere, revoke_one_session};
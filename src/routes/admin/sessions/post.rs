@@ -0,0 +1,51 @@
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use crate::authentication::{revoke_session, revoke_user_sessions, UserId};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[tracing::instrument(name = "Revoke a session", skip(pool, session), fields(user_id = %*user_id))]
+pub async fn revoke_one_session(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let session_id = path.into_inner();
+    // Only the user's own sessions can be revoked
+    if !revoke_session(&pool, **user_id, session_id).await.map_err(e500)? {
+        FlashMessage::error("That session does not exist or has already ended.").send();
+        return Ok(see_other("/admin/sessions"));
+    }
+    if session.get_session_id().map_err(e500)? == Some(session_id) {
+        session.log_out();
+        FlashMessage::info("You have successfully logged out.").send();
+        return Ok(see_other("/login"));
+    }
+    FlashMessage::info("The session has been revoked.").send();
+    Ok(see_other("/admin/sessions"))
+}
+
+#[tracing::instrument(name = "Log out everywhere", skip(pool, session, origin), fields(user_id = %*user_id))]
+pub async fn log_out_everywhere(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    origin: RequestOrigin,
+) -> Result<HttpResponse, actix_web::Error> {
+    revoke_user_sessions(pool.get_ref(), **user_id, None)
+        .await
+        .map_err(e500)?;
+    AuditEntry::new(AuditAction::LoggedOut)
+        .actor(**user_id)
+        .target("all sessions")
+        .record(pool.get_ref(), &origin)
+        .await
+        .map_err(e500)?;
+    session.log_out();
+    FlashMessage::info("You have been logged out of all your sessions.").send();
+    Ok(see_other("/login"))
+}
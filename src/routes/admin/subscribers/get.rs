@@ -0,0 +1,72 @@
+use crate::domain::SubscriptionStatus;
+use crate::utils::e500;
+use crate::csrf::CsrfToken;
+use crate::templates::{render, FlashMessages};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use askama::Template;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    // E.g. `pending`, all subscribers when left out or unknown
+    #[serde(default)]
+    status: Option<String>,
+}
+
+struct Subscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: SubscriptionStatus,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/subscribers.html")]
+struct SubscribersPage {
+    subscribers: Vec<Subscriber>,
+    statuses: [SubscriptionStatus; 6],
+    selected_status: Option<SubscriptionStatus>,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+}
+
+pub async fn subscribers_page(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let selected_status = parameters.status.as_deref().and_then(SubscriptionStatus::parse);
+    render(&SubscribersPage {
+        subscribers: get_subscribers(&pool, selected_status).await.map_err(e500)?,
+        statuses: SubscriptionStatus::ALL,
+        selected_status,
+        csrf_token,
+        flash_messages,
+    })
+}
+
+#[tracing::instrument(name = "Get subscribers", skip(pool))]
+async fn get_subscribers(
+    pool: &PgPool,
+    status: Option<SubscriptionStatus>,
+) -> Result<Vec<Subscriber>, anyhow::Error> {
+    let subscribers = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status AS "status: SubscriptionStatus", subscribed_at
+        FROM subscriptions
+        WHERE $1::subscription_status IS NULL OR status = $1
+        ORDER BY subscribed_at DESC
+        "#,
+        status as Option<SubscriptionStatus>,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve subscribers.")?;
+    Ok(subscribers)
+}
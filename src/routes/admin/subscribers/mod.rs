@@ -0,0 +1,5 @@
+mod get;
+pub use get::subscribers_page;
+
+mod post;
+pub use post::change_subscriber_status;
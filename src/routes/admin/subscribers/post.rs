@@ -0,0 +1,70 @@
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use crate::authentication::UserId;
+use crate::domain::SubscriptionStatus;
+use crate::routes::{change_subscription_status, ChangeStatusError};
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct StatusFormData {
+    status: String,
+}
+
+// Support staff act on behalf of subscribers, e.g. unsubscribe someone who
+// asked by email, within the same transitions as the subscribers themselves
+#[tracing::instrument(name = "Change the status of a subscriber", skip(form, pool, origin))]
+pub async fn change_subscriber_status(
+    path: web::Path<Uuid>,
+    form: web::Form<StatusFormData>,
+    pool: web::Data<PgPool>,
+    current_user: web::ReqData<UserId>,
+    origin: RequestOrigin,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = path.into_inner();
+    let to = match SubscriptionStatus::parse(&form.status) {
+        Some(status) => status,
+        None => {
+            FlashMessage::error(format!("There is no {} status.", form.status)).send();
+            return Ok(see_other("/admin/subscribers"));
+        }
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let from = match change_subscription_status(&mut transaction, subscriber_id, to).await {
+        Ok(from) => from,
+        Err(e @ ChangeStatusError::IllegalTransition(_)) => {
+            FlashMessage::error(e.to_string()).send();
+            return Ok(see_other("/admin/subscribers"));
+        }
+        Err(ChangeStatusError::UnknownSubscriber(_)) => {
+            FlashMessage::error("There is no such subscriber.").send();
+            return Ok(see_other("/admin/subscribers"));
+        }
+        Err(e) => return Err(e500(e)),
+    };
+    if let Some(from) = from {
+        AuditEntry::new(AuditAction::SubscriberStatusChanged)
+            .actor(**current_user)
+            .target(subscriber_id)
+            .change("status", from.as_str(), to.as_str())
+            .record(&mut *transaction, &origin)
+            .await
+            .map_err(e500)?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change the status of a subscriber.")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!("The subscriber is now {}.", to)).send();
+    Ok(see_other("/admin/subscribers"))
+}
@@ -0,0 +1,63 @@
+use crate::authentication::{
+    generate_totp_secret, qr_code_svg, totp_enabled, totp_provisioning_uri, two_factor_required, UserId,
+};
+use crate::routes::admin::dashboard::get_username;
+use crate::session_state::TypedSession;
+use crate::utils::e500;
+use crate::csrf::CsrfToken;
+use crate::templates::{render, FlashMessages};
+use actix_web::{web, HttpResponse};
+use askama::Template;
+use sqlx::PgPool;
+
+// Shown as the account name's prefix in authenticator apps
+const TOTP_ISSUER: &str = "zero2prod";
+
+// What a user who has not enabled two-factor authentication yet needs to set it up
+struct Enrollment {
+    qr_code: String,
+    secret: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/two_factor.html")]
+struct TwoFactorPage {
+    // `None` once two-factor authentication is enabled
+    enrollment: Option<Enrollment>,
+    required: bool,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+}
+
+pub async fn two_factor_page(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = **user_id;
+    let enrollment = if totp_enabled(&pool, user_id).await.map_err(e500)? {
+        None
+    } else {
+        // Keep the same secret across reloads until it is confirmed
+        let secret = match session.get_pending_totp_secret().map_err(e500)? {
+            Some(secret) => secret,
+            None => {
+                let secret = generate_totp_secret();
+                session.insert_pending_totp_secret(&secret).map_err(e500)?;
+                secret
+            }
+        };
+        let username = get_username(user_id, &pool).await.map_err(e500)?;
+        // Generated by us, so it can go into the page as is
+        let qr_code = qr_code_svg(&totp_provisioning_uri(&secret, TOTP_ISSUER, &username)).map_err(e500)?;
+        Some(Enrollment { qr_code, secret })
+    };
+    render(&TwoFactorPage {
+        enrollment,
+        required: two_factor_required(&pool).await.map_err(e500)?,
+        csrf_token,
+        flash_messages,
+    })
+}
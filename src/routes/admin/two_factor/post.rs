@@ -0,0 +1,74 @@
+use crate::authentication::{
+    disable_two_factor, enable_two_factor, two_factor_required, verify_second_factor, verify_totp_code, UserId,
+};
+use crate::session_state::TypedSession;
+use crate::templates::{render, FlashMessages};
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use askama::Template;
+use chrono::Utc;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct CodeFormData {
+    code: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/two_factor_recovery_codes.html")]
+struct RecoveryCodesPage {
+    recovery_codes: Vec<String>,
+    flash_messages: FlashMessages,
+}
+
+#[tracing::instrument(name = "Enroll in two-factor authentication", skip(form, session, pool), fields(user_id = %*user_id))]
+pub async fn enroll_two_factor(
+    form: web::Form<CodeFormData>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let secret = match session.get_pending_totp_secret().map_err(e500)? {
+        Some(secret) => secret,
+        None => return Ok(see_other("/admin/two_factor")),
+    };
+    let now = Utc::now().timestamp() as u64;
+    let used_step = match verify_totp_code(&secret, &form.code, now, None) {
+        Some(step) => step,
+        None => {
+            FlashMessage::error("The code is not valid, please try again.").send();
+            return Ok(see_other("/admin/two_factor"));
+        }
+    };
+
+    let recovery_codes = enable_two_factor(&pool, **user_id, &secret, used_step)
+        .await
+        .map_err(e500)?;
+    session.remove_pending_totp_secret();
+
+    // The only time the recovery codes are ever shown
+    render(&RecoveryCodesPage {
+        recovery_codes,
+        flash_messages: FlashMessages::default(),
+    })
+}
+
+#[tracing::instrument(name = "Disable two-factor authentication", skip(form, pool), fields(user_id = %*user_id))]
+pub async fn remove_two_factor(
+    form: web::Form<CodeFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if two_factor_required(&pool).await.map_err(e500)? {
+        FlashMessage::error("Two-factor authentication is required for all users.").send();
+        return Ok(see_other("/admin/two_factor"));
+    }
+    if !verify_second_factor(&pool, **user_id, &form.code).await.map_err(e500)? {
+        FlashMessage::error("The code is not valid.").send();
+        return Ok(see_other("/admin/two_factor"));
+    }
+    disable_two_factor(&pool, **user_id).await.map_err(e500)?;
+    FlashMessage::info("Two-factor authentication has been disabled.").send();
+    Ok(see_other("/admin/two_factor"))
+}
@@ -0,0 +1,60 @@
+use crate::authentication::{two_factor_required, Role};
+use crate::utils::e500;
+use crate::csrf::CsrfToken;
+use crate::templates::{render, FlashMessages};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+struct AdminUser {
+    user_id: Uuid,
+    username: String,
+    role: Role,
+    disabled: bool,
+    two_factor: bool,
+    password_login_disabled: bool,
+}
+
+#[derive(Template)]
+#[template(path = "admin/users.html")]
+struct UsersPage {
+    users: Vec<AdminUser>,
+    roles: [Role; 4],
+    two_factor_required: bool,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+}
+
+pub async fn users_page(
+    pool: web::Data<PgPool>,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render(&UsersPage {
+        users: get_users(&pool).await.map_err(e500)?,
+        roles: Role::ALL,
+        two_factor_required: two_factor_required(&pool).await.map_err(e500)?,
+        csrf_token,
+        flash_messages,
+    })
+}
+
+#[tracing::instrument(name = "Get admin users", skip(pool))]
+async fn get_users(pool: &PgPool) -> Result<Vec<AdminUser>, anyhow::Error> {
+    let users = sqlx::query_as!(
+        AdminUser,
+        r#"
+        SELECT user_id, username, role AS "role: Role", disabled,
+            totp_secret IS NOT NULL OR EXISTS (SELECT 1 FROM passkeys p WHERE p.user_id = users.user_id) AS "two_factor!",
+            password_login_disabled
+        FROM users
+        ORDER BY username
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve admin users.")?;
+    Ok(users)
+}
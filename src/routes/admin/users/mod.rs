@@ -0,0 +1,8 @@
+mod get;
+pub use get::users_page;
+
+mod post;
+pub use post::{
+    create_user, change_user_role, change_two_factor_requirement, disable_password_login, disable_user,
+    enable_password_login, enable_user,
+};
@@ -0,0 +1,192 @@
+use crate::audit_log::RequestOrigin;
+use crate::authentication::{create_admin_user, set_two_factor_required, CreateUserError, NewAdminUser, Role, UserId};
+use crate::configuration::PasswordHashingSettings;
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct NewUserFormData {
+    username: String,
+    // Where password reset links go, optional
+    #[serde(default)]
+    email: String,
+    password: Secret<String>,
+    role: Role,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RoleFormData {
+    role: Role,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TwoFactorRequirementFormData {
+    required: bool,
+}
+
+pub async fn create_user(
+    form: web::Form<NewUserFormData>,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
+    current_user: web::ReqData<UserId>,
+    origin: RequestOrigin,
+) -> Result<HttpResponse, actix_web::Error> {
+    let NewUserFormData { username, email, password, role } = form.0;
+    let username = username.trim().to_string();
+    let new_user = NewAdminUser { username: username.clone(), email: Some(email), password, role };
+    match create_admin_user(&pool, &hashing, new_user, Some(**current_user), &origin).await {
+        Ok(_) => FlashMessage::info(format!("{} has been added as {}.", username, role)).send(),
+        Err(CreateUserError::UnexpectedError(e)) => return Err(e500(e)),
+        Err(e) => FlashMessage::error(e.to_string()).send(),
+    }
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(name = "Change the role of an admin user", skip(form, pool, current_user))]
+pub async fn change_user_role(
+    path: web::Path<Uuid>,
+    form: web::Form<RoleFormData>,
+    pool: web::Data<PgPool>,
+    current_user: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = path.into_inner();
+    // Owners could otherwise lock everyone out of user management
+    if user_id == **current_user {
+        FlashMessage::error("You cannot change your own role.").send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    let updated = sqlx::query!(
+        r#"UPDATE users SET role = $1 WHERE user_id = $2"#,
+        form.role as Role,
+        user_id,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to change the role of an admin user.")
+    .map_err(e500)?
+    .rows_affected();
+
+    if updated == 0 {
+        FlashMessage::error("There is no such user.").send();
+    } else {
+        FlashMessage::info("The role has been changed.").send();
+    }
+    Ok(see_other("/admin/users"))
+}
+
+pub async fn disable_user(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    current_user: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    set_disabled(path.into_inner(), true, &pool, *current_user.into_inner()).await
+}
+
+pub async fn enable_user(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    current_user: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    set_disabled(path.into_inner(), false, &pool, *current_user.into_inner()).await
+}
+
+#[tracing::instrument(name = "Enable or disable an admin user", skip(pool))]
+async fn set_disabled(
+    user_id: Uuid,
+    disabled: bool,
+    pool: &PgPool,
+    current_user_id: Uuid,
+) -> Result<HttpResponse, actix_web::Error> {
+    // Which also guarantees that at least one owner stays enabled
+    if user_id == current_user_id {
+        FlashMessage::error("You cannot disable your own account.").send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    let updated = sqlx::query!(
+        r#"UPDATE users SET disabled = $1 WHERE user_id = $2"#,
+        disabled,
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to enable or disable an admin user.")
+    .map_err(e500)?
+    .rows_affected();
+
+    if updated == 0 {
+        FlashMessage::error("There is no such user.").send();
+    } else if disabled {
+        FlashMessage::info("The user has been disabled.").send();
+    } else {
+        FlashMessage::info("The user has been enabled.").send();
+    }
+    Ok(see_other("/admin/users"))
+}
+
+pub async fn disable_password_login(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    set_password_login_disabled(path.into_inner(), true, &pool).await
+}
+
+pub async fn enable_password_login(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    set_password_login_disabled(path.into_inner(), false, &pool).await
+}
+
+#[tracing::instrument(name = "Enable or disable password login for an admin user", skip(pool))]
+async fn set_password_login_disabled(
+    user_id: Uuid,
+    disabled: bool,
+    pool: &PgPool,
+) -> Result<HttpResponse, actix_web::Error> {
+    // Sign-in links are then the only way in: they need somewhere to go
+    let updated = sqlx::query!(
+        r#"
+        UPDATE users SET password_login_disabled = $1
+        WHERE user_id = $2 AND (NOT $1 OR email IS NOT NULL)
+        "#,
+        disabled,
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to enable or disable password login for an admin user.")
+    .map_err(e500)?
+    .rows_affected();
+
+    if updated == 0 && disabled {
+        FlashMessage::error("Password login can only be disabled for users with an email address.").send();
+    } else if updated == 0 {
+        FlashMessage::error("There is no such user.").send();
+    } else if disabled {
+        FlashMessage::info("The user can now only log in with emailed sign-in links.").send();
+    } else {
+        FlashMessage::info("The user can log in with their password again.").send();
+    }
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(name = "Change the two-factor authentication requirement", skip(form, pool), fields(required = form.required))]
+pub async fn change_two_factor_requirement(
+    form: web::Form<TwoFactorRequirementFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    set_two_factor_required(&pool, form.required).await.map_err(e500)?;
+    if form.required {
+        FlashMessage::info("Two-factor authentication is now required for all users.").send();
+    } else {
+        FlashMessage::info("Two-factor authentication is now optional.").send();
+    }
+    Ok(see_other("/admin/users"))
+}
@@ -1,9 +1,14 @@
+use crate::templates::{render, FlashMessages};
 use actix_web::{get, HttpResponse};
-use actix_web::http::header::ContentType;
+use askama::Template;
+
+#[derive(Template)]
+#[template(path = "home.html")]
+struct HomePage {
+    flash_messages: FlashMessages,
+}
 
 #[get("/")]
-pub async fn home() -> HttpResponse {
-    return HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(include_str!("home.html"));
+pub async fn home(flash_messages: FlashMessages) -> Result<HttpResponse, actix_web::Error> {
+    render(&HomePage { flash_messages })
 }
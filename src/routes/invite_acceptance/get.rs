@@ -0,0 +1,64 @@
+use crate::routes::hash_invite_token;
+use crate::templates::{render, render_with_status, ErrorPage, FlashMessages};
+use crate::utils::e500;
+use actix_web::{get, web, HttpResponse};
+use anyhow::Context;
+use askama::Template;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    invite_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "invite_acceptance.html")]
+struct AcceptInvitePage<'a> {
+    email: &'a str,
+    invite_token: &'a str,
+    flash_messages: FlashMessages,
+}
+
+#[tracing::instrument(name = "Show the invite acceptance form", skip(parameters, pool, flash_messages))]
+#[get("/invites/accept")]
+pub async fn accept_invite_form(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: FlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = match get_pending_invite_email(&pool, &parameters.invite_token)
+        .await
+        .map_err(e500)?
+    {
+        Some(email) => email,
+        None => return invalid_invite(),
+    };
+    render(&AcceptInvitePage {
+        email: &email,
+        invite_token: &parameters.invite_token,
+        flash_messages,
+    })
+}
+
+pub fn invalid_invite() -> Result<HttpResponse, actix_web::Error> {
+    render_with_status(
+        HttpResponse::Unauthorized(),
+        &ErrorPage::new("Invalid invite", "This invite is invalid, expired or has already been used."),
+    )
+}
+
+#[tracing::instrument(name = "Get the email of a pending invite", skip(pool, invite_token))]
+async fn get_pending_invite_email(pool: &PgPool, invite_token: &str) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT email FROM admin_invites
+        WHERE invite_token_hash = $1
+            AND accepted_at IS NULL AND revoked_at IS NULL AND expires_at > now()
+        "#,
+        hash_invite_token(invite_token),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve an admin invite.")?;
+    Ok(row.map(|r| r.email))
+}
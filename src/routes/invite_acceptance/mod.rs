@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::accept_invite_form;
+pub use post::accept_invite;
@@ -0,0 +1,142 @@
+use crate::authentication::{check_password_policy, hash_password, Role};
+use crate::configuration::PasswordHashingSettings;
+use crate::routes::hash_invite_token;
+use super::get::invalid_invite;
+use crate::utils::{e500, see_other};
+use actix_web::{post, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    invite_token: String,
+    username: String,
+    password: Secret<String>,
+    password_check: Secret<String>,
+}
+
+struct PendingInvite {
+    invite_id: Uuid,
+    email: String,
+    role: Role,
+}
+
+#[tracing::instrument(
+    name = "Accept an admin invite",
+    skip(form, pool, hashing),
+    fields(username = %form.username)
+)]
+#[post("/invites/accept")]
+pub async fn accept_invite(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let FormData { invite_token, username, password, password_check } = form.0;
+    let form_location = format!("/invites/accept?invite_token={}", invite_token);
+    let username = username.trim().to_string();
+    if username.is_empty() {
+        FlashMessage::error("The username cannot be empty.").send();
+        return Ok(see_other(&form_location));
+    }
+    if password.expose_secret() != password_check.expose_secret() {
+        FlashMessage::error("You entered two different passwords - the field values must match.").send();
+        return Ok(see_other(&form_location));
+    }
+    if let Err(e) = check_password_policy(&password) {
+        FlashMessage::error(e.to_string()).send();
+        return Ok(see_other(&form_location));
+    }
+    let password_hash = hash_password(password, &hashing).await.map_err(e500)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let invite = match get_pending_invite(&mut transaction, &invite_token).await.map_err(e500)? {
+        Some(invite) => invite,
+        None => {
+            return invalid_invite()
+        }
+    };
+    let user_id = match insert_user(&mut transaction, &username, &invite.email, &password_hash, invite.role)
+        .await
+        .map_err(e500)?
+    {
+        Some(user_id) => user_id,
+        None => {
+            FlashMessage::error(format!("The username {} is already taken.", username)).send();
+            return Ok(see_other(&form_location));
+        }
+    };
+    sqlx::query!(
+        r#"UPDATE admin_invites SET accepted_at = now(), accepted_by = $1 WHERE invite_id = $2"#,
+        user_id,
+        invite.invite_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to mark the admin invite as accepted.")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to accept an admin invite.")
+        .map_err(e500)?;
+
+    FlashMessage::info("Your account is ready, you can now log in.").send();
+    Ok(see_other("/login"))
+}
+
+// Locks the invite, so that it can only be accepted once.
+async fn get_pending_invite(
+    transaction: &mut Transaction<'_, Postgres>,
+    invite_token: &str,
+) -> Result<Option<PendingInvite>, anyhow::Error> {
+    let invite = sqlx::query_as!(
+        PendingInvite,
+        r#"
+        SELECT invite_id, email, role AS "role: Role" FROM admin_invites
+        WHERE invite_token_hash = $1
+            AND accepted_at IS NULL AND revoked_at IS NULL AND expires_at > now()
+        FOR UPDATE
+        "#,
+        hash_invite_token(invite_token),
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to perform a query to retrieve an admin invite.")?;
+    Ok(invite)
+}
+
+// Returns `None` if the username is taken.
+async fn insert_user(
+    transaction: &mut Transaction<'_, Postgres>,
+    username: &str,
+    email: &str,
+    password_hash: &Secret<String>,
+    role: Role,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let user_id = Uuid::new_v4();
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, email, password_hash, role)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (username) DO NOTHING
+        "#,
+        user_id,
+        username,
+        email,
+        password_hash.expose_secret(),
+        role as Role,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to insert the invited user.")?
+    .rows_affected();
+    Ok(if inserted == 1 { Some(user_id) } else { None })
+}
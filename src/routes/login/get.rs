@@ -1,43 +1,31 @@
+use crate::authentication::OidcClient;
+use crate::configuration::MagicLinkSettings;
+use crate::csrf::CsrfToken;
+use crate::templates::{render, FlashMessages};
+use actix_web::{get, web, HttpResponse};
+use askama::Template;
 
-use actix_web::{get, HttpRequest, HttpResponse};
-use actix_web::http::header::ContentType;
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginPage<'a> {
+    // The name of the single sign-on provider, if there is one
+    sso_name: Option<&'a str>,
+    magic_links_enabled: bool,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+}
 
 #[get("/login")]
-pub async fn login_form(request: HttpRequest) -> HttpResponse {
-    let error_html = match request.cookie("_flash") {
-        None => "".into(),
-        Some(cookie) => format!("<p><i>{}</i></p>", cookie.value())
-    };
-
-    return HttpResponse::Ok()
-    .content_type(ContentType::html())
-    .body(format!(
-        r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-<meta http-equiv="content-type" content="text/html; charset=utf-8">
-<title>Login</title>
-</head>
-<body>
-{error_html}
-<form action="/login" method="post">
-    <label>Username
-        <input
-            type="text"
-            placeholder="Enter Username"
-            name="username"
-        >
-    </label>
-    <label>Password
-        <input
-            type="password"
-            placeholder="Enter Password"
-            name="password"
-        >
-    </label>
-    <button type="submit">Login</button>
-</form>
-</body>
-</html>"#,
-    ))
+pub async fn login_form(
+    oidc: web::Data<Option<OidcClient>>,
+    magic_link: web::Data<MagicLinkSettings>,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render(&LoginPage {
+        sso_name: oidc.as_ref().as_ref().map(OidcClient::display_name),
+        magic_links_enabled: magic_link.enabled,
+        csrf_token,
+        flash_messages,
+    })
 }
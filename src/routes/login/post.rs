@@ -1,5 +1,6 @@
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
 use crate::session_state::TypedSession;
-use crate::authentication::{validate_credentials, Credentials, AuthError};
+use crate::authentication::{password_login_enabled, start_session, two_factor_enabled, validate_credentials, Credentials, AuthError};
 use actix_web::error::InternalError;
 use actix_web::{web, post, HttpResponse, ResponseError};
 use actix_web::http::header::LOCATION;
@@ -8,29 +9,78 @@ use actix_web_flash_messages::FlashMessage;
 use secrecy::Secret;
 use sqlx::PgPool;
 use crate::routes::error_chain_fmt;
+use crate::configuration::{PasswordHashingSettings, SessionSettings};
+use crate::login_protection::LoginProtection;
+use crate::utils::client_ip;
+use actix_web::HttpRequest;
 
 
 #[post("/login")]
 #[tracing::instrument(
-    skip(form, pool, session),
-    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+    skip(form, request, pool, hashing, session_settings, login_protection, session),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty, ip = tracing::field::Empty)
 )]
-pub async fn login(form: web::Form<FormData>, pool: web::Data<PgPool>, session: TypedSession) -> Result<HttpResponse, InternalError<LoginError>> {
+pub async fn login(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
+    session_settings: web::Data<SessionSettings>,
+    login_protection: web::Data<LoginProtection>,
+    session: TypedSession,
+) -> Result<HttpResponse, InternalError<LoginError>> {
     let credentials = Credentials {
         username: form.0.username, 
         password: form.0.password
     };
+    let username = credentials.username.clone();
+    let ip = client_ip(&request);
 
     tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-    match validate_credentials(credentials, &pool).await {
+    tracing::Span::current().record("ip", &tracing::field::display(&ip));
+    // Locked out attempts are refused without even looking at the password
+    if login_protection.is_locked_out(&username, &ip).await.map_err(|e| login_redirect(LoginError::UnexpectedError(e)))? {
+        tracing::info!("Refused a login attempt during a lockout");
+        return Err(login_redirect(LoginError::LockedOut));
+    }
+    let delay = login_protection.delay(&username, &ip).await.map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+
+    match validate_credentials(credentials, &hashing, &pool).await {
         Ok(user_id) => {
             tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
-            session.renew();
-            session.insert_user_id(user_id)
-                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            // Only told once the password is known to be right
+            if !password_login_enabled(&pool, user_id).await.map_err(|e| login_redirect(LoginError::UnexpectedError(e)))? {
+                return Err(login_redirect(LoginError::PasswordLoginDisabled));
+            }
+            let two_factor = two_factor_enabled(&pool, user_id)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            if two_factor {
+                session.renew();
+                // Only a marker for the second step: the user is not logged in yet,
+                // and past failures are only forgotten once the code is right
+                session.insert_pending_two_factor_user_id(user_id)
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+                return Ok(HttpResponse::SeeOther().insert_header((LOCATION, "/login/two_factor")).finish());
+            }
+            login_protection.record_success(&username).await.map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            start_session(&pool, &session_settings, &session, user_id, &request)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
             return Ok(HttpResponse::SeeOther().insert_header((LOCATION, "/admin/dashboard")).finish());
         }
         Err(e) => {
+            if let AuthError::InvalidCredentials(_) = e {
+                login_protection.record_failure(&username, &ip).await.map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+                AuditEntry::new(AuditAction::LoginFailed)
+                    .target(&username)
+                    .record(pool.get_ref(), &RequestOrigin::of(&request))
+                    .await
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            }
             let e = match e {
                 AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
                 AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into())
@@ -60,6 +110,11 @@ pub struct FormData {
 pub enum LoginError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    // Deliberately vague: it must not tell whether the username exists
+    #[error("Too many failed login attempts, please try again later.")]
+    LockedOut,
+    #[error("Password login is disabled for your account, please log in with an emailed sign-in link.")]
+    PasswordLoginDisabled,
     #[error("Something went wrong")]
     UnexpectedError(#[from] anyhow::Error)
 }
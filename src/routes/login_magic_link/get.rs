@@ -0,0 +1,19 @@
+use crate::csrf::CsrfToken;
+use crate::templates::{render, FlashMessages};
+use actix_web::{get, HttpResponse};
+use askama::Template;
+
+#[derive(Template)]
+#[template(path = "login_magic_link.html")]
+struct MagicLinkPage {
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+}
+
+#[get("/login/magic_link")]
+pub async fn magic_link_form(csrf_token: CsrfToken, flash_messages: FlashMessages) -> Result<HttpResponse, actix_web::Error> {
+    render(&MagicLinkPage {
+        csrf_token,
+        flash_messages,
+    })
+}
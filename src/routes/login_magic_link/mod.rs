@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::magic_link_form;
+pub use post::request_magic_link;
@@ -0,0 +1,179 @@
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::routes::{generate_subscription_token, sign_reset_token};
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::login_protection::LoginProtection;
+use crate::utils::{client_ip, e500, see_other};
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// How long a sign-in link can be used for
+const MAGIC_LINK_VALIDITY_MINUTES: i64 = 15;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    username_or_email: String,
+}
+
+struct MagicLinkRecipient {
+    user_id: Uuid,
+    email: SubscriberEmail,
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Request a sign-in link",
+    skip(form, request, pool, email_client, base_url, hmac_secret, login_protection, origin),
+    fields(username_or_email = %form.username_or_email)
+)]
+#[post("/login/magic_link")]
+pub async fn request_magic_link(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    login_protection: web::Data<LoginProtection>,
+    origin: RequestOrigin,
+) -> Result<HttpResponse, actix_web::Error> {
+    let username_or_email = form.username_or_email.trim();
+    // As for password resets: past the limit, the answer is the same but nothing happens
+    if login_protection
+        .link_request_limit_exceeded(username_or_email, &client_ip(&request))
+        .await
+        .map_err(e500)?
+    {
+        return Ok(magic_link_requested());
+    }
+    // Whether or not anyone matches, so that this does not tell either
+    AuditEntry::new(AuditAction::MagicLinkRequested)
+        .target(username_or_email)
+        .record(pool.get_ref(), &origin)
+        .await
+        .map_err(e500)?;
+    let recipients = get_magic_link_recipients(&pool, username_or_email).await.map_err(e500)?;
+    if recipients.is_empty() {
+        tracing::info!("There is no enabled user with an email address for this username or email");
+    }
+    // Email addresses are not unique: everyone they belong to gets a link of their own.
+    // Storing and sending happen in the background, as for password resets.
+    for recipient in recipients {
+        tokio::spawn(issue_magic_link(
+            pool.clone().into_inner(),
+            email_client.clone().into_inner(),
+            base_url.0.clone(),
+            hmac_secret.clone().into_inner(),
+            recipient,
+        ));
+    }
+
+    Ok(magic_link_requested())
+}
+
+fn magic_link_requested() -> HttpResponse {
+    FlashMessage::info("If that account exists, a sign-in link has been sent to its email address.").send();
+    see_other("/login")
+}
+
+#[tracing::instrument(name = "Get the recipients of a sign-in link", skip(pool))]
+async fn get_magic_link_recipients(
+    pool: &PgPool,
+    username_or_email: &str,
+) -> Result<Vec<MagicLinkRecipient>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT user_id, email AS "email!" FROM users
+        WHERE (username = $1 OR lower(email) = lower($1)) AND email IS NOT NULL AND NOT disabled
+        "#,
+        username_or_email,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve the email of a user.")?;
+    let mut recipients = Vec::new();
+    for row in rows {
+        match SubscriberEmail::parse(row.email) {
+            Ok(email) => recipients.push(MagicLinkRecipient { user_id: row.user_id, email }),
+            Err(e) => tracing::warn!(error.message = %e, "A user has an invalid email address"),
+        }
+    }
+    Ok(recipients)
+}
+
+#[tracing::instrument(name = "Issue a sign-in link", skip_all, fields(user_id = %recipient.user_id))]
+async fn issue_magic_link(
+    pool: std::sync::Arc<PgPool>,
+    email_client: std::sync::Arc<EmailClient>,
+    base_url: String,
+    hmac_secret: std::sync::Arc<HmacSecret>,
+    recipient: MagicLinkRecipient,
+) {
+    let token = generate_subscription_token();
+    if let Err(e) = store_magic_link(&pool, recipient.user_id, &sign_reset_token(&hmac_secret, &token)).await {
+        tracing::error!(error.cause_chain = ?e, "Failed to store a sign-in link");
+        return;
+    }
+    if let Err(e) = send_magic_link(&email_client, &recipient.email, &base_url, &token).await {
+        tracing::error!(error.cause_chain = ?e, "Failed to send a sign-in link email");
+    }
+}
+
+// Only the latest link of a user works: the ones sent before are thrown away.
+#[tracing::instrument(name = "Store a sign-in link in the database", skip(pool, token_signature))]
+async fn store_magic_link(pool: &PgPool, user_id: Uuid, token_signature: &str) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await.context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        "DELETE FROM magic_links WHERE user_id = $1 AND used_at IS NULL",
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete previous sign-in links.")?;
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO magic_links (link_id, user_id, token_signature, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        token_signature,
+        now,
+        now + Duration::minutes(MAGIC_LINK_VALIDITY_MINUTES),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to insert a sign-in link.")?;
+    transaction.commit().await.context("Failed to commit SQL transaction to store a sign-in link.")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Send a sign-in link email", skip(email_client, base_url, token))]
+async fn send_magic_link(
+    email_client: &EmailClient,
+    email: &SubscriberEmail,
+    base_url: &str,
+    token: &str,
+) -> Result<(), reqwest::Error> {
+    let link = format!("{}/login/magic_link/confirm?token={}", base_url, token);
+    let plain_body = format!(
+        "Someone asked for a link to log in to your newsletter admin account.\n\
+        Visit {} within {} minutes to log in. If it wasn't you, you can ignore this email.",
+        link, MAGIC_LINK_VALIDITY_MINUTES
+    );
+    let html_body = format!(
+        "Someone asked for a link to log in to your newsletter admin account.<br />\
+        Click <a href=\"{}\">here</a> within {} minutes to log in. If it wasn't you, you can ignore this email.",
+        link, MAGIC_LINK_VALIDITY_MINUTES
+    );
+
+    email_client
+        .send_email(email, "Your newsletter admin sign-in link", &html_body, &plain_body)
+        .await
+}
@@ -0,0 +1,69 @@
+use crate::csrf::CsrfToken;
+use crate::routes::sign_reset_token;
+use crate::startup::HmacSecret;
+use crate::templates::{render, render_with_status, ErrorPage, FlashMessages};
+use crate::utils::e500;
+use actix_web::{get, web, HttpResponse};
+use anyhow::Context;
+use askama::Template;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    token: String,
+}
+
+#[derive(Template)]
+#[template(path = "login_magic_link_confirm.html")]
+struct ConfirmMagicLinkPage<'a> {
+    token: &'a str,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+}
+
+// Opening the link only shows a button: mail scanners that follow links
+// must not use it up before the user gets to it.
+#[tracing::instrument(name = "Show the sign-in link confirmation", skip(parameters, pool, hmac_secret, csrf_token, flash_messages))]
+#[get("/login/magic_link/confirm")]
+pub async fn magic_link_confirm_form(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let signature = sign_reset_token(&hmac_secret, &parameters.token);
+    if !pending_magic_link_exists(&pool, &signature).await.map_err(e500)? {
+        return invalid_magic_link();
+    }
+    render(&ConfirmMagicLinkPage {
+        token: &parameters.token,
+        csrf_token,
+        flash_messages,
+    })
+}
+
+pub fn invalid_magic_link() -> Result<HttpResponse, actix_web::Error> {
+    render_with_status(
+        HttpResponse::Unauthorized(),
+        &ErrorPage::new(
+            "Invalid link",
+            "This sign-in link is invalid, expired or has already been used.",
+        ),
+    )
+}
+
+#[tracing::instrument(name = "Check for a pending sign-in link", skip(pool, signature))]
+async fn pending_magic_link_exists(pool: &PgPool, signature: &str) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT link_id FROM magic_links
+        WHERE token_signature = $1 AND used_at IS NULL AND expires_at > now()
+        "#,
+        signature,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve a sign-in link.")?;
+    Ok(row.is_some())
+}
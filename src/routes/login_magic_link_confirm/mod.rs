@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::magic_link_confirm_form;
+pub use post::log_in_with_magic_link;
@@ -0,0 +1,67 @@
+use super::get::invalid_magic_link;
+use crate::authentication::{start_session, two_factor_enabled};
+use crate::configuration::SessionSettings;
+use crate::routes::sign_reset_token;
+use crate::session_state::TypedSession;
+use crate::startup::HmacSecret;
+use crate::utils::{e500, see_other};
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    token: String,
+}
+
+// Logs in the same way as a password does, second factor included
+#[tracing::instrument(
+    name = "Log in with a magic link",
+    skip(form, request, pool, hmac_secret, settings, session),
+    fields(user_id = tracing::field::Empty)
+)]
+#[post("/login/magic_link/confirm")]
+pub async fn log_in_with_magic_link(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    settings: web::Data<SessionSettings>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let signature = sign_reset_token(&hmac_secret, &form.token);
+    let user_id = match use_magic_link(&pool, &signature).await.map_err(e500)? {
+        Some(user_id) => user_id,
+        None => return invalid_magic_link(),
+    };
+    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+
+    if two_factor_enabled(&pool, user_id).await.map_err(e500)? {
+        session.renew();
+        // Only a marker for the second step: the user is not logged in yet
+        session.insert_pending_two_factor_user_id(user_id).map_err(e500)?;
+        return Ok(see_other("/login/two_factor"));
+    }
+    start_session(&pool, &settings, &session, user_id, &request).await.map_err(e500)?;
+    Ok(see_other("/admin/dashboard"))
+}
+
+// Consumes the link and returns whose it is, unless the user has been disabled since
+#[tracing::instrument(name = "Use a sign-in link", skip(pool, signature))]
+async fn use_magic_link(pool: &PgPool, signature: &str) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE magic_links m SET used_at = now()
+        FROM users u
+        WHERE m.user_id = u.user_id AND NOT u.disabled
+            AND m.token_signature = $1 AND m.used_at IS NULL AND m.expires_at > now()
+        RETURNING m.user_id
+        "#,
+        signature,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to mark a sign-in link as used.")?;
+    Ok(row.map(|row| row.user_id))
+}
@@ -0,0 +1,288 @@
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use crate::authentication::{get_enabled_user_role, hash_password, start_session, two_factor_enabled, OidcClient, OidcIdentity, Role};
+use crate::configuration::{PasswordHashingSettings, SessionSettings};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct CallbackParameters {
+    code: Option<String>,
+    state: Option<String>,
+    // Set by the provider instead of `code` when it did not log the user in
+    error: Option<String>,
+}
+
+// Whether someone the provider vouches for gets in, and as whom
+enum SsoUser {
+    Found(Uuid),
+    Refused(String),
+}
+
+#[tracing::instrument(name = "Send the user to the single sign-on provider", skip(oidc, session))]
+#[get("/login/oidc")]
+pub async fn start_oidc_login(
+    oidc: web::Data<Option<OidcClient>>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let oidc = match oidc.get_ref() {
+        Some(oidc) => oidc,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let (url, pending) = match oidc.authorization_url().await {
+        Ok(started) => started,
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "Failed to start a single sign-on login");
+            FlashMessage::error("Single sign-on is unavailable, please try again later.").send();
+            return Ok(see_other("/login"));
+        }
+    };
+    session.insert_pending_oidc_login(&pending).map_err(e500)?;
+    Ok(see_other(url.as_str()))
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Finish a single sign-on login",
+    skip(parameters, request, oidc, pool, hashing, settings, session),
+    fields(user_id = tracing::field::Empty)
+)]
+#[get("/login/oidc/callback")]
+pub async fn oidc_callback(
+    parameters: web::Query<CallbackParameters>,
+    request: HttpRequest,
+    oidc: web::Data<Option<OidcClient>>,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
+    settings: web::Data<SessionSettings>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let oidc = match oidc.get_ref() {
+        Some(oidc) => oidc,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let pending = match session.take_pending_oidc_login().map_err(e500)? {
+        Some(pending) => pending,
+        None => {
+            FlashMessage::error("Your single sign-on login has expired, please try again.").send();
+            return Ok(see_other("/login"));
+        }
+    };
+    // The state ties the answer to the login this browser started
+    let code = match (&parameters.code, &parameters.state, &parameters.error) {
+        (Some(code), Some(state), None) if *state == pending.state => code,
+        _ => {
+            tracing::info!(error = ?parameters.error, "The provider did not log the user in");
+            FlashMessage::error("Single sign-on failed, please try again.").send();
+            return Ok(see_other("/login"));
+        }
+    };
+    let identity = match oidc.exchange_code(code, &pending).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "Failed to finish a single sign-on login");
+            FlashMessage::error("Single sign-on failed, please try again.").send();
+            return Ok(see_other("/login"));
+        }
+    };
+
+    let origin = RequestOrigin::of(&request);
+    let user_id = match sso_user(&pool, &hashing, oidc, &identity, &origin).await.map_err(e500)? {
+        SsoUser::Found(user_id) => user_id,
+        SsoUser::Refused(reason) => {
+            AuditEntry::new(AuditAction::LoginFailed)
+                .target(identity.email.as_deref().unwrap_or(&identity.subject))
+                .change("sso", None::<bool>, false)
+                .record(pool.get_ref(), &origin)
+                .await
+                .map_err(e500)?;
+            FlashMessage::error(reason).send();
+            return Ok(see_other("/login"));
+        }
+    };
+    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+
+    if two_factor_enabled(&pool, user_id).await.map_err(e500)? {
+        session.renew();
+        // Only a marker for the second step: the user is not logged in yet
+        session.insert_pending_two_factor_user_id(user_id).map_err(e500)?;
+        return Ok(see_other("/login/two_factor"));
+    }
+    start_session(&pool, &settings, &session, user_id, &request).await.map_err(e500)?;
+    Ok(see_other("/admin/dashboard"))
+}
+
+// Roles come from the provider's groups when users are created, and on every
+// login when roles are synced.
+#[tracing::instrument(name = "Match a single sign-on identity with a user", skip(pool, hashing, oidc, origin))]
+async fn sso_user(
+    pool: &PgPool,
+    hashing: &PasswordHashingSettings,
+    oidc: &OidcClient,
+    identity: &OidcIdentity,
+    origin: &RequestOrigin,
+) -> Result<SsoUser, anyhow::Error> {
+    let role = match oidc.role_for(&identity.groups) {
+        Some(role) => role,
+        None => return Ok(SsoUser::Refused("Your single sign-on account does not give you access.".into())),
+    };
+
+    let (user_id, disabled) = match find_linked_user(pool, identity, oidc.link_by_email()).await? {
+        Some(user) => user,
+        None if oidc.create_users() => match create_user(pool, hashing, identity, role, origin).await? {
+            Some(user_id) => (user_id, false),
+            None => {
+                return Ok(SsoUser::Refused(
+                    "An account with your username already exists, ask an owner to link it.".into(),
+                ))
+            }
+        },
+        None => return Ok(SsoUser::Refused("There is no account for you, ask an owner to add one.".into())),
+    };
+    if disabled {
+        return Ok(SsoUser::Refused("Your account is disabled.".into()));
+    }
+    if oidc.sync_roles() {
+        sync_role(pool, user_id, role).await?;
+    }
+    Ok(SsoUser::Found(user_id))
+}
+
+// Owners could otherwise all be demoted by a change of groups on the provider,
+// leaving nobody to manage users.
+async fn sync_role(pool: &PgPool, user_id: Uuid, role: Role) -> Result<(), anyhow::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE users SET role = $1
+        WHERE user_id = $2 AND role <> $1
+            AND (role <> 'owner' OR EXISTS (
+                SELECT 1 FROM users o WHERE o.role = 'owner' AND NOT o.disabled AND o.user_id <> $2
+            ))
+        "#,
+        role as Role,
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to change the role of a single sign-on user.")?
+    .rows_affected();
+    if updated == 0 && role != Role::Owner {
+        let current = get_enabled_user_role(pool, user_id).await?;
+        if current == Some(Role::Owner) {
+            tracing::warn!(%user_id, %role, "Kept the last active owner despite their single sign-on groups");
+        }
+    }
+    Ok(())
+}
+
+// The user who logged in with this identity before, else, when linking by email,
+// the only one with its verified email address, who is then linked to it for good.
+async fn find_linked_user(
+    pool: &PgPool,
+    identity: &OidcIdentity,
+    link_by_email: bool,
+) -> Result<Option<(Uuid, bool)>, anyhow::Error> {
+    let linked = sqlx::query!(
+        "SELECT user_id, disabled FROM users WHERE oidc_subject = $1",
+        identity.subject,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve a single sign-on user.")?;
+    if let Some(user) = linked {
+        return Ok(Some((user.user_id, user.disabled)));
+    }
+
+    let email = match &identity.email {
+        Some(email) if link_by_email => email,
+        _ => return Ok(None),
+    };
+    let matches = sqlx::query!(
+        r#"
+        SELECT user_id, disabled,
+            NOT password_login_disabled OR totp_secret IS NOT NULL
+                OR EXISTS (SELECT 1 FROM passkeys p WHERE p.user_id = users.user_id) AS "has_credentials!"
+        FROM users
+        WHERE lower(email) = lower($1) AND oidc_subject IS NULL
+        "#,
+        email,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve users by email.")?;
+    // Email addresses are not unique: guessing between several users would be unsafe.
+    // Nor may a provider account with the same address stand in for a password or
+    // second factor of ours.
+    if matches.len() != 1 || matches[0].has_credentials {
+        return Ok(None);
+    }
+    sqlx::query!(
+        "UPDATE users SET oidc_subject = $1 WHERE user_id = $2",
+        identity.subject,
+        matches[0].user_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to link a user to their single sign-on identity.")?;
+    Ok(Some((matches[0].user_id, matches[0].disabled)))
+}
+
+// `None` if the username is taken. Created users get a random password nobody
+// knows: they log in through the provider, or reset it.
+async fn create_user(
+    pool: &PgPool,
+    hashing: &PasswordHashingSettings,
+    identity: &OidcIdentity,
+    role: Role,
+    origin: &RequestOrigin,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let username = identity
+        .preferred_username
+        .as_ref()
+        .or(identity.email.as_ref())
+        .unwrap_or(&identity.subject);
+    let password: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(32)
+        .collect();
+    let password_hash = hash_password(Secret::new(password), hashing).await?;
+
+    let user_id = Uuid::new_v4();
+    let mut transaction = pool.begin().await.context("Failed to acquire a Postgres connection from the pool")?;
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, email, password_hash, role, oidc_subject)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        username,
+        identity.email,
+        password_hash.expose_secret(),
+        role as Role,
+        identity.subject,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to insert a single sign-on user.")?
+    .rows_affected();
+    if inserted == 0 {
+        return Ok(None);
+    }
+    AuditEntry::new(AuditAction::UserCreatedBySso)
+        .actor(user_id)
+        .target(username)
+        .change("role", None::<&str>, role.as_str())
+        .record(&mut transaction, origin)
+        .await?;
+    transaction.commit().await.context("Failed to commit SQL transaction to create a user.")?;
+    Ok(Some(user_id))
+}
@@ -0,0 +1,97 @@
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use crate::authentication::{
+    find_passkey, generate_passkey_challenge, get_passkey_credential_ids, record_passkey_use, start_session,
+    AssertionResponse, RelyingParty,
+};
+use crate::configuration::SessionSettings;
+use crate::session_state::TypedSession;
+use crate::utils::{e500, script_redirect};
+use actix_web::http::StatusCode;
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+/// What the login page's script passes on to the browser to use a passkey.
+/// Once the password has been checked, only that user's passkeys are offered,
+/// as a second factor. Otherwise any of ours will do, as long as the
+/// authenticator verified the user with a PIN or biometrics.
+#[tracing::instrument(name = "Start a passkey login", skip(pool, rp, session))]
+#[post("/login/passkey/options")]
+pub async fn passkey_login_options(
+    pool: web::Data<PgPool>,
+    rp: web::Data<RelyingParty>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let challenge = generate_passkey_challenge();
+    session.insert_passkey_login_challenge(&challenge).map_err(e500)?;
+    let options = match session.get_pending_two_factor_user_id().map_err(e500)? {
+        Some(user_id) => {
+            let allowed = get_passkey_credential_ids(&pool, user_id).await.map_err(e500)?;
+            rp.login_options(&challenge, &allowed, false)
+        }
+        None => rp.login_options(&challenge, &[], true),
+    };
+    Ok(HttpResponse::Ok().json(options))
+}
+
+#[tracing::instrument(
+    name = "Log in with a passkey",
+    skip(response, request, pool, rp, settings, session),
+    fields(user_id = tracing::field::Empty)
+)]
+#[post("/login/passkey")]
+pub async fn log_in_with_passkey(
+    response: web::Json<AssertionResponse>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    rp: web::Data<RelyingParty>,
+    settings: web::Data<SessionSettings>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pending_user_id = session.get_pending_two_factor_user_id().map_err(e500)?;
+    // Where to try again
+    let retry = if pending_user_id.is_some() { "/login/two_factor" } else { "/login" };
+    let challenge = match session.take_passkey_login_challenge().map_err(e500)? {
+        Some(challenge) => challenge,
+        None => {
+            FlashMessage::error("Your passkey login has expired, please try again.").send();
+            return Ok(script_redirect(StatusCode::UNAUTHORIZED, retry));
+        }
+    };
+
+    let passkey = find_passkey(&pool, &response.credential_id)
+        .await
+        .map_err(e500)?
+        // As a second factor, only the passkeys of whoever entered the password count
+        .filter(|passkey| match pending_user_id {
+            Some(user_id) => user_id == passkey.user_id,
+            None => true,
+        });
+    let verified = match &passkey {
+        Some(passkey) => match rp.verify_assertion(&challenge, &response, passkey, pending_user_id.is_none()) {
+            Ok(sign_count) => record_passkey_use(&pool, passkey, sign_count).await.map_err(e500)?,
+            Err(e) => {
+                tracing::warn!(error.cause_chain = ?e, "Rejected a passkey login");
+                false
+            }
+        },
+        None => false,
+    };
+    let user_id = match (verified, passkey) {
+        (true, Some(passkey)) => passkey.user_id,
+        (_, passkey) => {
+            let mut entry = AuditEntry::new(AuditAction::LoginFailed).change("passkey", None::<bool>, false);
+            if let Some(user_id) = pending_user_id.or_else(|| passkey.map(|passkey| passkey.user_id)) {
+                entry = entry.actor(user_id);
+            }
+            entry.record(pool.get_ref(), &RequestOrigin::of(&request)).await.map_err(e500)?;
+            FlashMessage::error("The passkey could not be verified.").send();
+            return Ok(script_redirect(StatusCode::UNAUTHORIZED, retry));
+        }
+    };
+    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+
+    session.remove_pending_two_factor_user_id();
+    start_session(&pool, &settings, &session, user_id, &request).await.map_err(e500)?;
+    Ok(script_redirect(StatusCode::OK, "/admin/dashboard"))
+}
@@ -0,0 +1,37 @@
+use crate::authentication::{has_passkeys, totp_enabled};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use crate::csrf::CsrfToken;
+use crate::templates::{render, FlashMessages};
+use actix_web::{get, web, HttpResponse};
+use askama::Template;
+use sqlx::PgPool;
+
+#[derive(Template)]
+#[template(path = "login_two_factor.html")]
+struct TwoFactorPage {
+    // Which second factors the user can choose from
+    totp: bool,
+    passkeys: bool,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+}
+
+#[get("/login/two_factor")]
+pub async fn two_factor_form(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    csrf_token: CsrfToken,
+    flash_messages: FlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = match session.get_pending_two_factor_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => return Ok(see_other("/login")),
+    };
+    render(&TwoFactorPage {
+        totp: totp_enabled(&pool, user_id).await.map_err(e500)?,
+        passkeys: has_passkeys(&pool, user_id).await.map_err(e500)?,
+        csrf_token,
+        flash_messages,
+    })
+}
@@ -0,0 +1,66 @@
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use crate::authentication::{start_session, verify_second_factor};
+use crate::configuration::SessionSettings;
+use crate::login_protection::LoginProtection;
+use crate::routes::get_username;
+use crate::session_state::TypedSession;
+use crate::utils::{client_ip, e500, see_other};
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+// After this many wrong codes the password has to be entered again
+const MAX_ATTEMPTS: u32 = 5;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    code: String,
+}
+
+#[post("/login/two_factor")]
+#[tracing::instrument(skip(form, request, pool, settings, login_protection, session), fields(user_id = tracing::field::Empty))]
+pub async fn verify_two_factor(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    settings: web::Data<SessionSettings>,
+    login_protection: web::Data<LoginProtection>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = match session.get_pending_two_factor_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => return Ok(see_other("/login")),
+    };
+    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+
+    // Wrong codes count against the same limits as wrong passwords: otherwise
+    // logging in again would give anyone with the password fresh attempts
+    let username = get_username(user_id, &pool).await.map_err(e500)?;
+    let ip = client_ip(&request);
+    if login_protection.is_locked_out(&username, &ip).await.map_err(e500)? {
+        session.log_out();
+        FlashMessage::error("Too many failed login attempts, please try again later.").send();
+        return Ok(see_other("/login"));
+    }
+    if session.record_two_factor_attempt().map_err(e500)? > MAX_ATTEMPTS {
+        session.log_out();
+        FlashMessage::error("Too many invalid codes, please log in again.").send();
+        return Ok(see_other("/login"));
+    }
+    if !verify_second_factor(&pool, user_id, &form.code).await.map_err(e500)? {
+        login_protection.record_failure(&username, &ip).await.map_err(e500)?;
+        AuditEntry::new(AuditAction::LoginFailed)
+            .actor(user_id)
+            .change("second_factor", None::<bool>, false)
+            .record(pool.get_ref(), &RequestOrigin::of(&request))
+            .await
+            .map_err(e500)?;
+        FlashMessage::error("The code is not valid.").send();
+        return Ok(see_other("/login/two_factor"));
+    }
+
+    login_protection.record_success(&username).await.map_err(e500)?;
+    session.remove_pending_two_factor_user_id();
+    start_session(&pool, &settings, &session, user_id, &request).await.map_err(e500)?;
+    Ok(see_other("/admin/dashboard"))
+}
@@ -1,15 +1,39 @@
 mod admin;
 mod health_check;
 mod home;
+mod invite_acceptance;
 mod login;
+mod login_magic_link;
+mod login_oidc;
+mod login_passkey;
+mod login_magic_link_confirm;
+mod login_two_factor;
 mod newsletter;
+mod password_reset;
+mod password_reset_confirm;
+mod setup;
 mod subscriptions;
+mod subscriptions_challenge;
+mod subscriptions_change_email;
+mod subscriptions_change_email_confirm;
 mod subscriptions_confirm;
 
 pub use admin::*;
 pub use health_check::*;
 pub use home::*;
+pub use invite_acceptance::*;
 pub use login::*;
+pub use login_magic_link::*;
+pub use login_magic_link_confirm::*;
+pub use login_oidc::*;
+pub use login_passkey::*;
+pub use login_two_factor::*;
 pub use newsletter::*;
+pub use password_reset::*;
+pub use password_reset_confirm::*;
+pub use setup::*;
 pub use subscriptions::*;
+pub use subscriptions_challenge::*;
+pub use subscriptions_change_email::*;
+pub use subscriptions_change_email_confirm::*;
 pub use subscriptions_confirm::*;
@@ -3,36 +3,37 @@ use actix_web::http::StatusCode;
 use sqlx::PgPool;
 use crate::routes::error_chain_fmt;
 use crate::email_client::EmailClient;
-use crate::domain::SubscriberEmail;
+use crate::configuration::{ApiSettings, PasswordHashingSettings};
+use crate::domain::{SubscriberEmail, SubscriptionStatus};
 use anyhow::Context;
-use crate::authentication::{validate_credentials, basic_authentication, AuthError};
+use crate::authentication::{validate_credentials, validate_api_token, basic_authentication, bearer_token, get_enabled_user_role, password_change_required, password_login_enabled, two_factor_enabled, ApiScope, AuthError, Permission};
+use crate::login_protection::LoginProtection;
+use crate::utils::client_ip;
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use uuid::Uuid;
 
 
 // Dummy implementation
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(body, pool, email_client, request),
+    skip(body, pool, email_client, hashing, api, login_protection, request),
     // trace who is calling
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 #[post("/newsletter")]
 pub async fn publish_newsletter(
-    body: web::Json<BodyData>, pool: web::Data<PgPool>, email_client: web::Data<EmailClient>, request: HttpRequest,
+    body: web::Json<BodyData>, pool: web::Data<PgPool>, email_client: web::Data<EmailClient>, hashing: web::Data<PasswordHashingSettings>, api: web::Data<ApiSettings>, login_protection: web::Data<LoginProtection>, request: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
-    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
-    // tracing who is calling
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-
-    let user_id = validate_credentials(credentials, &pool)
-        .await
-        .map_err(|e| match e {
-            AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
-            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
-        })?;
+    let user_id = authenticate(&request, &api, &hashing, &login_protection, &pool).await?;
 
     tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+    let role = get_enabled_user_role(&pool, user_id).await?;
+    if !matches!(role, Some(role) if role.can(Permission::PublishNewsletters)) {
+        return Err(PublishError::Forbidden);
+    }
 
     let subscribers = get_confirmed_subscribers(&pool).await?;
+    let mut recipients = 0;
     for subscriber in subscribers {
         match subscriber {
             Ok(subscriber) => {
@@ -47,6 +48,7 @@ pub async fn publish_newsletter(
                     .with_context(|| {
                         format!("Failed to send newsletter to {}", subscriber.email)
                     })?;
+                recipients += 1;
             }
             Err(error) => {
                 tracing::warn!(
@@ -60,11 +62,74 @@ pub async fn publish_newsletter(
             }
         }
     } 
+    AuditEntry::new(AuditAction::NewsletterPublished)
+        .actor(user_id)
+        .target(&body.title)
+        .change("recipients", None::<u64>, recipients)
+        .record(pool.get_ref(), &RequestOrigin::of(&request))
+        .await?;
     return Ok(HttpResponse::Ok().finish());
 }
 
 
 
+// API tokens must carry the publishing scope. Passwords, when still accepted,
+// are only limited by the user's role, and count against the same lockouts as
+// the login form.
+async fn authenticate(
+    request: &HttpRequest,
+    api: &ApiSettings,
+    hashing: &PasswordHashingSettings,
+    login_protection: &LoginProtection,
+    pool: &PgPool,
+) -> Result<Uuid, PublishError> {
+    let to_publish_error = |e: AuthError| match e {
+        AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
+        AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+    };
+    if let Some(token) = bearer_token(request.headers()) {
+        let owner = validate_api_token(&token, pool).await.map_err(to_publish_error)?;
+        if !owner.scopes.contains(&ApiScope::PublishNewsletters) {
+            return Err(PublishError::Forbidden);
+        }
+        return Ok(owner.user_id);
+    }
+    if !api.basic_auth_enabled {
+        return Err(PublishError::AuthError(anyhow::anyhow!("Missing API token, basic authentication is disabled.")));
+    }
+
+    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
+    // tracing who is calling
+    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    let username = credentials.username.clone();
+    let ip = client_ip(request);
+    if login_protection.is_locked_out(&username, &ip).await? {
+        return Err(PublishError::AuthError(anyhow::anyhow!("Too many failed login attempts.")));
+    }
+    let user_id = match validate_credentials(credentials, hashing, pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => {
+            if let AuthError::InvalidCredentials(_) = e {
+                login_protection.record_failure(&username, &ip).await?;
+            }
+            return Err(to_publish_error(e));
+        }
+    };
+    if !password_login_enabled(pool, user_id).await.map_err(PublishError::UnexpectedError)? {
+        return Err(PublishError::AuthError(anyhow::anyhow!("Password login is disabled for this user.")));
+    }
+    // The default password is no password at all
+    if password_change_required(pool, user_id).await.map_err(PublishError::UnexpectedError)? {
+        return Err(PublishError::AuthError(anyhow::anyhow!("The user must change their password first.")));
+    }
+    // The password alone is not enough for them anywhere else
+    if two_factor_enabled(pool, user_id).await.map_err(PublishError::UnexpectedError)? {
+        return Err(PublishError::AuthError(anyhow::anyhow!("The user has a second factor, an API token is required.")));
+    }
+    login_protection.record_success(&username).await?;
+    Ok(user_id)
+}
+
 #[derive(serde::Deserialize)]
 pub struct BodyData {
     title: String,
@@ -87,8 +152,9 @@ async fn get_confirmed_subscribers(pool: &PgPool) -> Result< Vec< Result<Confirm
         r#"
         SELECT email
         FROM subscriptions
-        WHERE status = 'confirmed'
+        WHERE status = $1
         "#,
+        SubscriptionStatus::Confirmed as SubscriptionStatus,
     )
     .fetch_all(pool)
     .await?;
@@ -108,6 +174,8 @@ async fn get_confirmed_subscribers(pool: &PgPool) -> Result< Vec< Result<Confirm
 pub enum PublishError {
     #[error("Authentication failed.")]
     AuthError(#[source] anyhow::Error),
+    #[error("The user is not allowed to publish newsletters.")]
+    Forbidden,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -123,6 +191,7 @@ impl ResponseError for PublishError {
         match self {
             PublishError::UnexpectedError(_) => return StatusCode::INTERNAL_SERVER_ERROR,
             PublishError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            PublishError::Forbidden => StatusCode::FORBIDDEN,
         }
     }
 }
@@ -0,0 +1,14 @@
+use crate::templates::{render, FlashMessages};
+use actix_web::{get, HttpResponse};
+use askama::Template;
+
+#[derive(Template)]
+#[template(path = "password_reset.html")]
+struct PasswordResetPage {
+    flash_messages: FlashMessages,
+}
+
+#[get("/password_reset")]
+pub async fn password_reset_form(flash_messages: FlashMessages) -> Result<HttpResponse, actix_web::Error> {
+    render(&PasswordResetPage { flash_messages })
+}
@@ -0,0 +1,179 @@
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::routes::generate_subscription_token;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::login_protection::LoginProtection;
+use crate::utils::{client_ip, e500, see_other};
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
+use sha2::Sha256;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// How long a reset link can be used for
+const RESET_VALIDITY_MINUTES: i64 = 60;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    username: String,
+}
+
+struct ResetRecipient {
+    user_id: Uuid,
+    email: SubscriberEmail,
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(form, request, pool, email_client, base_url, hmac_secret, login_protection, origin),
+    fields(username = %form.username)
+)]
+#[post("/password_reset")]
+pub async fn request_password_reset(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    login_protection: web::Data<LoginProtection>,
+    origin: RequestOrigin,
+) -> Result<HttpResponse, actix_web::Error> {
+    // Nothing is recorded or sent past the limit, but the answer stays the same
+    if login_protection
+        .link_request_limit_exceeded(form.username.trim(), &client_ip(&request))
+        .await
+        .map_err(e500)?
+    {
+        return Ok(reset_requested());
+    }
+    // Whether or not the username exists, so that this does not tell either
+    AuditEntry::new(AuditAction::PasswordResetRequested)
+        .target(form.username.trim())
+        .record(pool.get_ref(), &origin)
+        .await
+        .map_err(e500)?;
+    match get_reset_recipient(&pool, form.username.trim()).await.map_err(e500)? {
+        // Storing and sending happen in the background: how long the response
+        // takes must not tell whether the username exists.
+        Some(recipient) => {
+            tokio::spawn(issue_password_reset(
+                pool.into_inner(),
+                email_client.into_inner(),
+                base_url.0.clone(),
+                hmac_secret.into_inner(),
+                recipient,
+            ));
+        }
+        None => tracing::info!("There is no enabled user with an email address for this username"),
+    }
+
+    Ok(reset_requested())
+}
+
+fn reset_requested() -> HttpResponse {
+    FlashMessage::info("If that username exists, a password reset link has been sent to its email address.").send();
+    see_other("/login")
+}
+
+/// Reset tokens are stored as their signature with the application secret:
+/// neither a database leak nor a guess yields a working link.
+pub fn sign_reset_token(hmac_secret: &HmacSecret, reset_token: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.0.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(reset_token.as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+#[tracing::instrument(name = "Get the recipient of a password reset", skip(pool))]
+async fn get_reset_recipient(pool: &PgPool, username: &str) -> Result<Option<ResetRecipient>, anyhow::Error> {
+    let row = sqlx::query!(
+        "SELECT user_id, email FROM users WHERE username = $1 AND NOT disabled",
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve the email of a user.")?;
+    let (user_id, email) = match row {
+        Some(row) => match row.email {
+            Some(email) => (row.user_id, email),
+            None => return Ok(None),
+        },
+        None => return Ok(None),
+    };
+    match SubscriberEmail::parse(email) {
+        Ok(email) => Ok(Some(ResetRecipient { user_id, email })),
+        Err(e) => {
+            tracing::warn!(error.message = %e, "A user has an invalid email address");
+            Ok(None)
+        }
+    }
+}
+
+#[tracing::instrument(name = "Issue a password reset", skip_all, fields(user_id = %recipient.user_id))]
+async fn issue_password_reset(
+    pool: std::sync::Arc<PgPool>,
+    email_client: std::sync::Arc<EmailClient>,
+    base_url: String,
+    hmac_secret: std::sync::Arc<HmacSecret>,
+    recipient: ResetRecipient,
+) {
+    let reset_token = generate_subscription_token();
+    if let Err(e) = store_password_reset(&pool, recipient.user_id, &sign_reset_token(&hmac_secret, &reset_token)).await {
+        tracing::error!(error.cause_chain = ?e, "Failed to store a password reset");
+        return;
+    }
+    if let Err(e) = send_password_reset(&email_client, &recipient.email, &base_url, &reset_token).await {
+        tracing::error!(error.cause_chain = ?e, "Failed to send a password reset email");
+    }
+}
+
+#[tracing::instrument(name = "Store a password reset in the database", skip(pool, reset_token_signature))]
+async fn store_password_reset(pool: &PgPool, user_id: Uuid, reset_token_signature: &str) -> Result<(), anyhow::Error> {
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO password_resets (reset_id, user_id, reset_token_signature, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        reset_token_signature,
+        now,
+        now + Duration::minutes(RESET_VALIDITY_MINUTES),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to insert a password reset.")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Send a password reset email", skip(email_client, base_url, reset_token))]
+async fn send_password_reset(
+    email_client: &EmailClient,
+    email: &SubscriberEmail,
+    base_url: &str,
+    reset_token: &str,
+) -> Result<(), reqwest::Error> {
+    let reset_link = format!("{}/password_reset/confirm?reset_token={}", base_url, reset_token);
+    let plain_body = format!(
+        "Someone asked to reset the password of your newsletter admin account.\n\
+        Visit {} within {} minutes to choose a new one. If it wasn't you, you can ignore this email.",
+        reset_link, RESET_VALIDITY_MINUTES
+    );
+    let html_body = format!(
+        "Someone asked to reset the password of your newsletter admin account.<br />\
+        Click <a href=\"{}\">here</a> within {} minutes to choose a new one. If it wasn't you, you can ignore this email.",
+        reset_link, RESET_VALIDITY_MINUTES
+    );
+
+    email_client
+        .send_email(email, "Reset your newsletter admin password", &html_body, &plain_body)
+        .await
+}
@@ -0,0 +1,63 @@
+use crate::routes::sign_reset_token;
+use crate::startup::HmacSecret;
+use crate::templates::{render, render_with_status, ErrorPage, FlashMessages};
+use crate::utils::e500;
+use actix_web::{get, web, HttpResponse};
+use anyhow::Context;
+use askama::Template;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    reset_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "password_reset_confirm.html")]
+struct ChooseNewPasswordPage<'a> {
+    reset_token: &'a str,
+    flash_messages: FlashMessages,
+}
+
+#[tracing::instrument(name = "Show the password reset form", skip(parameters, pool, hmac_secret, flash_messages))]
+#[get("/password_reset/confirm")]
+pub async fn reset_password_form(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    flash_messages: FlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let signature = sign_reset_token(&hmac_secret, &parameters.reset_token);
+    if !pending_reset_exists(&pool, &signature).await.map_err(e500)? {
+        return invalid_reset_link();
+    }
+    render(&ChooseNewPasswordPage {
+        reset_token: &parameters.reset_token,
+        flash_messages,
+    })
+}
+
+pub fn invalid_reset_link() -> Result<HttpResponse, actix_web::Error> {
+    render_with_status(
+        HttpResponse::Unauthorized(),
+        &ErrorPage::new(
+            "Invalid link",
+            "This password reset link is invalid, expired or has already been used.",
+        ),
+    )
+}
+
+#[tracing::instrument(name = "Check for a pending password reset", skip(pool, signature))]
+pub(super) async fn pending_reset_exists(pool: &PgPool, signature: &str) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT reset_id FROM password_resets
+        WHERE reset_token_signature = $1 AND used_at IS NULL AND expires_at > now()
+        "#,
+        signature,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve a password reset.")?;
+    Ok(row.is_some())
+}
@@ -0,0 +1,116 @@
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use crate::authentication::{check_password_policy, hash_password, revoke_user_sessions};
+use crate::configuration::PasswordHashingSettings;
+use crate::routes::sign_reset_token;
+use crate::startup::HmacSecret;
+use super::get::{invalid_reset_link, pending_reset_exists};
+use crate::utils::{e500, see_other};
+use actix_web::{post, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    reset_token: String,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+#[tracing::instrument(name = "Reset a password", skip(form, pool, hashing, hmac_secret, origin), fields(user_id = tracing::field::Empty))]
+#[post("/password_reset/confirm")]
+pub async fn reset_password(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
+    hmac_secret: web::Data<HmacSecret>,
+    origin: RequestOrigin,
+) -> Result<HttpResponse, actix_web::Error> {
+    let FormData { reset_token, new_password, new_password_check } = form.0;
+    // Checked before hashing anything, so that made-up tokens cost us nothing
+    let signature = sign_reset_token(&hmac_secret, &reset_token);
+    if !pending_reset_exists(&pool, &signature).await.map_err(e500)? {
+        return invalid_reset_link();
+    }
+    let form_location = format!("/password_reset/confirm?reset_token={}", reset_token);
+    if new_password.expose_secret() != new_password_check.expose_secret() {
+        FlashMessage::error("You entered two different new passwords - the field values must match.").send();
+        return Ok(see_other(&form_location));
+    }
+    if let Err(e) = check_password_policy(&new_password) {
+        FlashMessage::error(e.to_string()).send();
+        return Ok(see_other(&form_location));
+    }
+    let password_hash = hash_password(new_password, &hashing).await.map_err(e500)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    // Checked again while consuming it, as it may have been used in the meantime
+    let user_id = match use_pending_reset(&mut transaction, &signature).await.map_err(e500)? {
+        Some(user_id) => user_id,
+        None => {
+            return invalid_reset_link()
+        }
+    };
+    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1, password_change_required = false WHERE user_id = $2",
+        password_hash.expose_secret(),
+        user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to change user's password in the database.")
+    .map_err(e500)?;
+    // Whoever might have got hold of the old password must not stay logged in
+    revoke_user_sessions(&mut *transaction, user_id, None).await.map_err(e500)?;
+    AuditEntry::new(AuditAction::PasswordReset)
+        .actor(user_id)
+        .record(&mut *transaction, &origin)
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reset a password.")
+        .map_err(e500)?;
+
+    FlashMessage::info("Your password has been reset, you can now log in.").send();
+    Ok(see_other("/login"))
+}
+
+// Consumes the reset, along with any other pending one for the same user,
+// and returns whose password it resets.
+async fn use_pending_reset(
+    transaction: &mut Transaction<'_, Postgres>,
+    signature: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id FROM password_resets
+        WHERE reset_token_signature = $1 AND used_at IS NULL AND expires_at > now()
+        FOR UPDATE
+        "#,
+        signature,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to perform a query to retrieve a password reset.")?;
+    let user_id = match row {
+        Some(row) => row.user_id,
+        None => return Ok(None),
+    };
+    sqlx::query!(
+        "UPDATE password_resets SET used_at = now() WHERE user_id = $1 AND used_at IS NULL",
+        user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to mark password resets as used.")?;
+    Ok(Some(user_id))
+}
@@ -0,0 +1,41 @@
+use crate::bootstrap::setup_token_is_valid;
+use crate::templates::{render, render_with_status, ErrorPage, FlashMessages};
+use crate::utils::e500;
+use actix_web::{get, web, HttpResponse};
+use askama::Template;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    setup_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "setup.html")]
+struct SetupPage<'a> {
+    setup_token: &'a str,
+    flash_messages: FlashMessages,
+}
+
+#[tracing::instrument(name = "Show the setup form", skip(parameters, pool, flash_messages))]
+#[get("/setup")]
+pub async fn setup_form(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: FlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !setup_token_is_valid(&pool, &parameters.setup_token).await.map_err(e500)? {
+        return invalid_setup_token();
+    }
+    render(&SetupPage {
+        setup_token: &parameters.setup_token,
+        flash_messages,
+    })
+}
+
+pub fn invalid_setup_token() -> Result<HttpResponse, actix_web::Error> {
+    render_with_status(
+        HttpResponse::Unauthorized(),
+        &ErrorPage::new("Invalid setup link", "This setup link is invalid, expired or has already been used."),
+    )
+}
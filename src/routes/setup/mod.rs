@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::setup_form;
+pub use post::set_up;
@@ -0,0 +1,57 @@
+use super::get::invalid_setup_token;
+use crate::audit_log::RequestOrigin;
+use crate::authentication::{check_password_policy, hash_password};
+use crate::bootstrap::create_first_owner;
+use crate::configuration::PasswordHashingSettings;
+use crate::utils::{e500, see_other};
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    setup_token: String,
+    username: String,
+    password: Secret<String>,
+    password_check: Secret<String>,
+}
+
+#[tracing::instrument(
+    name = "Set up the first owner",
+    skip(form, request, pool, hashing),
+    fields(username = %form.username)
+)]
+#[post("/setup")]
+pub async fn set_up(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let FormData { setup_token, username, password, password_check } = form.0;
+    let form_location = format!("/setup?setup_token={}", setup_token);
+    let username = username.trim();
+    if username.is_empty() {
+        FlashMessage::error("The username cannot be empty.").send();
+        return Ok(see_other(&form_location));
+    }
+    if password.expose_secret() != password_check.expose_secret() {
+        FlashMessage::error("You entered two different passwords - the field values must match.").send();
+        return Ok(see_other(&form_location));
+    }
+    if let Err(e) = check_password_policy(&password) {
+        FlashMessage::error(e.to_string()).send();
+        return Ok(see_other(&form_location));
+    }
+    let password_hash = hash_password(password, &hashing).await.map_err(e500)?;
+
+    let created = create_first_owner(&pool, Some(&setup_token), username, &password_hash, &RequestOrigin::of(&request))
+        .await
+        .map_err(e500)?;
+    if created.is_none() {
+        return invalid_setup_token();
+    }
+    FlashMessage::info("Your account is ready, you can now log in.").send();
+    Ok(see_other("/login"))
+}
@@ -1,4 +1,4 @@
-use actix_web::{post, web, HttpResponse, ResponseError};
+use actix_web::{web, Either, HttpRequest, HttpResponse, ResponseError};
 use actix_web::http::StatusCode;
 use anyhow::Context;
 use chrono::Utc;
@@ -7,21 +7,51 @@ use uuid::Uuid;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 
-use crate::domain::{NewSubscriber, SubscriberName, SubscriberEmail};
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use crate::domain::{IllegalTransition, NewSubscriber, SubscriberName, SubscriberEmail, SubscriptionStatus};
 use crate::email_client::EmailClient;
+use crate::sign_up_protection::{BlockReason, SignUpProtection};
 use crate::startup::ApplicationBaseUrl;
+use crate::utils::client_ip;
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug)]
 pub struct FormData {
+    // Missing fields are reported by the domain parsers as empty ones.
+    #[serde(default)]
     email: String,
+    #[serde(default)]
     name: String,
+    // Honeypot: hidden from humans with CSS, bots tend to fill it in
+    #[serde(default)]
+    website: String,
+    // Solution to a challenge from `GET /subscriptions/challenge`
+    #[serde(default)]
+    pow_challenge: Option<String>,
+    #[serde(default)]
+    pow_nonce: Option<String>,
  }
 
+/// A validation failure on a single input field, as reported to API clients.
+#[derive(serde::Serialize, Debug)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, code: &'static str, message: impl std::fmt::Display) -> Self {
+        Self { field, code, message: message.to_string() }
+    }
+}
+
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
-    #[error("{0}")]
-    ValidationError(String),
+    #[error("Invalid subscriber data: {0:?}")]
+    ValidationError(Vec<FieldError>),
+    #[error("Too many sign-up attempts, please try again later.")]
+    TooManyAttempts,
     // Transparent delegates both `Display`'s and `source`'s implementation
     // to the type wrapped by `UnexpectedError`.
     #[error(transparent)]
@@ -38,39 +68,86 @@ impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
             SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::TooManyAttempts => StatusCode::TOO_MANY_REQUESTS,
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscribeError::ValidationError(errors) => HttpResponse::build(self.status_code())
+                .json(serde_json::json!({ "errors": errors })),
+            SubscribeError::TooManyAttempts => HttpResponse::build(self.status_code()).body(self.to_string()),
+            SubscribeError::UnexpectedError(_) => HttpResponse::new(self.status_code()),
+        }
+    }
 }
 
 
 #[allow(clippy::async_yields_async)]
 #[tracing::instrument(
     name = "Adding a new subscribe",
-    skip(form, pool, email_client, base_url),
+    skip(body, request, pool, email_client, base_url, sign_up_protection),
     fields(
-        subscriber_email = %form.email,
-        subscriber_name = %form.name
+        subscriber_email = tracing::field::Empty,
+        subscriber_name = tracing::field::Empty
     )
 )]
-#[post("/subscriptions")]
 pub async fn subscribe(
-    form: web::Form<FormData>,
+    // Browsers post URL-encoded forms, our marketing site's JavaScript posts JSON.
+    body: Either<web::Json<FormData>, web::Form<FormData>>,
+    request: HttpRequest,
     pool: web::Data<PgPool>,
     // Get the email client from the app context
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    sign_up_protection: web::Data<SignUpProtection>,
 ) -> Result<HttpResponse, SubscribeError> {
-    // `form.0` gives us access to the underlying `FormData`
-    let new_subscriber = parse_subscriber(form.0).map_err(SubscribeError::ValidationError)?;
+    let form = match body {
+        Either::Left(json) => json.into_inner(),
+        Either::Right(form) => form.into_inner(),
+    };
+    tracing::Span::current()
+        .record("subscriber_email", &tracing::field::display(&form.email))
+        .record("subscriber_name", &tracing::field::display(&form.name));
+
+    let ip = client_ip(&request);
+    if let Some(reason) = screen_attempt(&sign_up_protection, &form, &ip).await? {
+        sign_up_protection.record_blocked_attempt(reason, &ip).await;
+        return blocked_response(reason);
+    }
+    let new_subscriber = parse_subscriber(form).map_err(SubscribeError::ValidationError)?;
+    if let Some(reason) = screen_subscriber(&sign_up_protection, &new_subscriber).await? {
+        sign_up_protection.record_blocked_attempt(reason, &ip).await;
+        return blocked_response(reason);
+    }
+
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;    // `web::Form` is a wrapper around `FormData`
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    let origin = RequestOrigin::of(&request);
+    let subscriber_id = match insert_subscriber(&mut transaction, &new_subscriber)
             .await
-            .context("Failed to insert new subscriber in the database.")?;
+            .context("Failed to insert new subscriber in the database.")? {
+        Some(subscriber_id) => {
+            AuditEntry::new(AuditAction::SubscriberAdded)
+                .target(subscriber_id)
+                .change("email", None::<&str>, new_subscriber.email.as_ref())
+                .change("name", None::<&str>, new_subscriber.name.as_ref())
+                .change("status", None::<&str>, SubscriptionStatus::Pending.as_str())
+                .record(&mut *transaction, &origin)
+                .await?;
+            subscriber_id
+        }
+        // Already signed up, perhaps with different casing. The answer is the
+        // same either way, so that sign-ups don't tell who is subscribed
+        None => match sign_up_again(&mut transaction, &new_subscriber, &origin).await? {
+            Some(subscriber_id) => subscriber_id,
+            None => return Ok(HttpResponse::Ok().finish()),
+        },
+    };
     let subscription_token = generate_subscription_token();
     store_token(&mut transaction, subscriber_id, &subscription_token)
         .await
@@ -86,8 +163,107 @@ pub async fn subscribe(
     Ok(HttpResponse::Ok().finish())
 }
 
+/// Bring back to the confirmation flow a subscriber signing up again: a pending
+/// one lost their confirmation email, an unsubscribed one changed their mind.
+/// Returns `None` for the others, who get no email.
+#[tracing::instrument(name = "Sign up an existing subscriber again", skip(transaction, new_subscriber, origin))]
+async fn sign_up_again(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+    origin: &RequestOrigin,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let existing = sqlx::query!(
+        r#"SELECT id, status AS "status: SubscriptionStatus" FROM subscriptions WHERE lower(email) = lower($1)"#,
+        new_subscriber.email.as_ref(),
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to perform a query to retrieve an existing subscriber.")?;
+    let existing = match existing {
+        Some(existing) => existing,
+        None => return Ok(None),
+    };
+    match existing.status {
+        SubscriptionStatus::Pending => Ok(Some(existing.id)),
+        SubscriptionStatus::Unsubscribed => {
+            let from = change_subscription_status(transaction, existing.id, SubscriptionStatus::Pending)
+                .await
+                .context("Failed to sign up an unsubscribed subscriber again.")?;
+            if let Some(from) = from {
+                AuditEntry::new(AuditAction::SubscriberStatusChanged)
+                    .target(existing.id)
+                    .change("status", from.as_str(), SubscriptionStatus::Pending.as_str())
+                    .record(&mut *transaction, origin)
+                    .await?;
+            }
+            Ok(Some(existing.id))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Run the abuse checks that don't need a valid subscriber, in order of cost.
+async fn screen_attempt(
+    sign_up_protection: &SignUpProtection,
+    form: &FormData,
+    ip: &str,
+) -> Result<Option<BlockReason>, anyhow::Error> {
+    if sign_up_protection.honeypot_enabled() && !form.website.is_empty() {
+        return Ok(Some(BlockReason::Honeypot));
+    }
+    if sign_up_protection.ip_rate_limit_exceeded(ip).await? {
+        return Ok(Some(BlockReason::IpRateLimit));
+    }
+    if let Some(difficulty) = sign_up_protection.proof_of_work_difficulty() {
+        let solved = match (&form.pow_challenge, &form.pow_nonce) {
+            (Some(challenge), Some(nonce)) => {
+                sign_up_protection
+                    .verify_proof_of_work(challenge, nonce, difficulty)
+                    .await?
+            }
+            _ => false,
+        };
+        if !solved {
+            return Ok(Some(BlockReason::ProofOfWork));
+        }
+    }
+    Ok(None)
+}
+
+/// Run the abuse checks that depend on who is signing up.
+async fn screen_subscriber(
+    sign_up_protection: &SignUpProtection,
+    new_subscriber: &NewSubscriber,
+) -> Result<Option<BlockReason>, anyhow::Error> {
+    if sign_up_protection.is_blocked_domain(&new_subscriber.email) {
+        return Ok(Some(BlockReason::DisposableDomain));
+    }
+    if sign_up_protection.email_rate_limit_exceeded(&new_subscriber.email).await? {
+        return Ok(Some(BlockReason::EmailRateLimit));
+    }
+    Ok(None)
+}
+
+fn blocked_response(reason: BlockReason) -> Result<HttpResponse, SubscribeError> {
+    match reason {
+        // Pretend all went well, so that bots don't learn about the honeypot.
+        BlockReason::Honeypot => Ok(HttpResponse::Ok().finish()),
+        BlockReason::DisposableDomain => Err(SubscribeError::ValidationError(vec![FieldError::new(
+            "email",
+            "disposable_domain",
+            "Disposable email addresses are not accepted.",
+        )])),
+        BlockReason::ProofOfWork => Err(SubscribeError::ValidationError(vec![FieldError::new(
+            "proof_of_work",
+            "invalid",
+            "A valid solution to a recent challenge is required.",
+        )])),
+        BlockReason::IpRateLimit | BlockReason::EmailRateLimit => Err(SubscribeError::TooManyAttempts),
+    }
+}
+
 /// Generate a random 25-characters-long case-sensitive subscription token.
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -95,10 +271,22 @@ fn generate_subscription_token() -> String {
         .collect()
 }
 
-pub fn parse_subscriber(form: FormData) -> Result<NewSubscriber, String> {
-    let name = SubscriberName::parse(form.name)?;
-    let email = SubscriberEmail::parse(form.email)?;
-    Ok(NewSubscriber{ email, name })
+/// Parse both fields, collecting every validation failure instead of
+/// stopping at the first one.
+pub fn parse_subscriber(form: FormData) -> Result<NewSubscriber, Vec<FieldError>> {
+    match (SubscriberName::parse(form.name), SubscriberEmail::parse(form.email)) {
+        (Ok(name), Ok(email)) => Ok(NewSubscriber{ email, name }),
+        (name, email) => {
+            let mut errors = Vec::new();
+            if let Err(e) = name {
+                errors.push(FieldError::new("name", e.code(), &e));
+            }
+            if let Err(e) = email {
+                errors.push(FieldError::new("email", e.code(), &e));
+            }
+            Err(errors)
+        }
+    }
 }
 
 #[tracing::instrument(
@@ -181,21 +369,113 @@ pub async fn send_confirmation_email(
     name = "Saving new subscriber details in the database"
     skip(new_subscriber, transaction)
 )]
-pub async fn insert_subscriber(transaction: &mut Transaction<'_, Postgres>, new_subscriber: &NewSubscriber) -> Result<Uuid, sqlx::Error> {
+// Returns `None`, without inserting anything, when the address is already taken.
+pub async fn insert_subscriber(transaction: &mut Transaction<'_, Postgres>, new_subscriber: &NewSubscriber) -> Result<Option<Uuid>, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+    let now = Utc::now();
+    let inserted = sqlx::query!(
         r#"
             INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-            VALUES ($1, $2, $3, $4, 'pending_confirmation')
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT DO NOTHING
         "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
-        Utc::now()
+        now,
+        SubscriptionStatus::Pending as SubscriptionStatus,
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    if inserted == 0 {
+        return Ok(None);
+    }
+    record_status_change(transaction, subscriber_id, None, SubscriptionStatus::Pending, now).await?;
+    Ok(Some(subscriber_id))
+}
+
+#[derive(thiserror::Error)]
+pub enum ChangeStatusError {
+    #[error(transparent)]
+    IllegalTransition(#[from] IllegalTransition),
+    #[error("There is no subscriber with id {0}.")]
+    UnknownSubscriber(Uuid),
+    #[error("Failed to change the subscription status.")]
+    DatabaseError(#[from] sqlx::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ChangeStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Move a subscriber to `to`, if the transition is legal, and record it.
+/// Returns the status the subscriber had, or `None` when asking for the current
+/// status, which is a no-op.
+#[tracing::instrument(name = "Change subscription status", skip(transaction))]
+pub async fn change_subscription_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    to: SubscriptionStatus,
+) -> Result<Option<SubscriptionStatus>, ChangeStatusError> {
+    let from = sqlx::query!(
+        r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    .ok_or(ChangeStatusError::UnknownSubscriber(subscriber_id))?
+    .status;
+    if from == to {
+        return Ok(None);
+    }
+    from.transition_to(to)?;
+
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = $2,
+            confirmed_at = COALESCE($3, confirmed_at),
+            unsubscribed_at = COALESCE($4, unsubscribed_at)
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        to as SubscriptionStatus,
+        (to == SubscriptionStatus::Confirmed).then(|| now),
+        (to == SubscriptionStatus::Unsubscribed).then(|| now),
+    )
+    .execute(&mut *transaction)
+    .await?;
+    record_status_change(transaction, subscriber_id, Some(from), to, now).await?;
+    Ok(Some(from))
+}
+
+async fn record_status_change(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    from: Option<SubscriptionStatus>,
+    to: SubscriptionStatus,
+    changed_at: chrono::DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_status_history (id, subscriber_id, from_status, to_status, changed_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        from as Option<SubscriptionStatus>,
+        to as SubscriptionStatus,
+        changed_at,
     )
     .execute(transaction)
     .await?;
-    Ok(subscriber_id)
+    Ok(())
 }
 
 
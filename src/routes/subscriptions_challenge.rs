@@ -0,0 +1,15 @@
+use actix_web::{web, HttpResponse};
+
+use crate::sign_up_protection::SignUpProtection;
+
+/// Hand out a proof of work challenge to be solved before calling `subscribe`.
+/// Returns 404 when proof of work is disabled.
+#[tracing::instrument(name = "Issue a sign-up challenge", skip(sign_up_protection))]
+pub async fn subscription_challenge(
+    sign_up_protection: web::Data<SignUpProtection>,
+) -> HttpResponse {
+    match sign_up_protection.proof_of_work_difficulty() {
+        Some(difficulty) => HttpResponse::Ok().json(sign_up_protection.issue_challenge(difficulty)),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
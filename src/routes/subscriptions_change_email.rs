@@ -0,0 +1,167 @@
+use actix_web::{post, web, HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::routes::{error_chain_fmt, generate_subscription_token, get_subscriber_id_from_token};
+use crate::startup::ApplicationBaseUrl;
+
+#[derive(serde::Deserialize)]
+struct FormData {
+    subscription_token: String,
+    new_email: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum ChangeEmailError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The provided token is not valid.")]
+    UnknownToken,
+    #[error("{0} already belongs to another subscriber.")]
+    EmailAlreadyInUse(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ChangeEmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ChangeEmailError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ChangeEmailError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ChangeEmailError::UnknownToken => StatusCode::UNAUTHORIZED,
+            ChangeEmailError::EmailAlreadyInUse(_) => StatusCode::CONFLICT,
+            ChangeEmailError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Start moving a subscription to a new address.
+///
+/// Subscribers identify themselves with the token they received in their
+/// confirmation link. `subscriptions.email` is left untouched: we only store
+/// the request and send a confirmation link to the new address.
+#[tracing::instrument(
+    name = "Request a subscriber email change",
+    skip(form, pool, email_client, base_url),
+    fields(new_email = %form.new_email)
+)]
+#[post("/subscriptions/change_email")]
+pub async fn change_email(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ChangeEmailError> {
+    let FormData { subscription_token, new_email } = form.0;
+    let new_email = SubscriberEmail::parse(new_email)
+        .map_err(|e| ChangeEmailError::ValidationError(e.to_string()))?;
+
+    let subscriber_id = get_subscriber_id_from_token(&pool, &subscription_token)
+        .await
+        .context("Failed to retrieve the subscriber associated with the provided token.")?
+        .ok_or(ChangeEmailError::UnknownToken)?;
+
+    match get_subscriber_id_from_email(&pool, &new_email).await? {
+        Some(id) if id == subscriber_id => {
+            return Err(ChangeEmailError::ValidationError(format!(
+                "{} is already the address of this subscription.",
+                new_email
+            )));
+        }
+        Some(_) => return Err(ChangeEmailError::EmailAlreadyInUse(new_email.to_string())),
+        None => {}
+    }
+
+    let email_change_token = generate_subscription_token();
+    store_email_change_request(&pool, subscriber_id, &new_email, &email_change_token)
+        .await
+        .context("Failed to store the email change request.")?;
+
+    send_email_change_confirmation(&email_client, &new_email, &base_url.0, &email_change_token)
+        .await
+        .context("Failed to send the email change confirmation.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Get subscriber_id from email", skip(pool))]
+pub async fn get_subscriber_id_from_email(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE lower(email) = lower($1)"#,
+        email.as_ref(),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to look up a subscriber by email.")?;
+
+    Ok(row.map(|r| r.id))
+}
+
+#[tracing::instrument(
+    name = "Store email change request in the database",
+    skip(pool, email_change_token)
+)]
+async fn store_email_change_request(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    new_email: &SubscriberEmail,
+    email_change_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_change_requests (email_change_token, subscriber_id, new_email, requested_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        email_change_token,
+        subscriber_id,
+        new_email.as_ref(),
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Send an email change confirmation to the new address",
+    skip(email_client, base_url, email_change_token)
+)]
+async fn send_email_change_confirmation(
+    email_client: &EmailClient,
+    new_email: &SubscriberEmail,
+    base_url: &str,
+    email_change_token: &str,
+) -> Result<(), reqwest::Error> {
+    let confirmation_link = format!(
+        "{}/subscriptions/change_email/confirm?email_change_token={}",
+        base_url, email_change_token
+    );
+    let plain_body = format!(
+        "You asked to move your newsletter subscription to this address.\n\
+        Visit {} to confirm the change.",
+        confirmation_link
+    );
+    let html_body = format!(
+        "You asked to move your newsletter subscription to this address.<br />\
+        Click <a href=\"{}\">here</a> to confirm the change.",
+        confirmation_link
+    );
+
+    email_client
+        .send_email(new_email, "Confirm your new email address", &html_body, &plain_body)
+        .await
+}
@@ -0,0 +1,187 @@
+use actix_web::{get, web, HttpResponse};
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::routes::ChangeEmailError;
+
+// Links older than this no longer change anything
+const EMAIL_CHANGE_VALIDITY_HOURS: i64 = 24;
+
+#[derive(serde::Deserialize)]
+struct Parameters {
+    email_change_token: String,
+}
+
+struct EmailChangeRequest {
+    subscriber_id: Uuid,
+    old_email: String,
+    new_email: String,
+}
+
+#[tracing::instrument(
+    name = "Confirm a subscriber email change",
+    skip(parameters, pool, email_client, origin)
+)]
+#[get("/subscriptions/change_email/confirm")]
+pub async fn confirm_email_change(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    origin: RequestOrigin,
+) -> Result<HttpResponse, ChangeEmailError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let request = get_email_change_request(&mut transaction, &parameters.email_change_token)
+        .await
+        .context("Failed to retrieve the email change request.")?
+        .ok_or(ChangeEmailError::UnknownToken)?;
+
+    // The address might have been claimed by someone else after the
+    // request was made: check again before swapping.
+    if email_belongs_to_another_subscriber(&mut transaction, &request)
+        .await
+        .context("Failed to check if the new email is already in use.")?
+    {
+        return Err(ChangeEmailError::EmailAlreadyInUse(request.new_email));
+    }
+
+    swap_subscriber_email(&mut transaction, &request)
+        .await
+        .context("Failed to update the subscriber email.")?;
+    AuditEntry::new(AuditAction::SubscriberEmailChanged)
+        .target(request.subscriber_id)
+        .change("email", &request.old_email, &request.new_email)
+        .record(&mut *transaction, &origin)
+        .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change a subscriber email.")?;
+
+    // The change has gone through either way: a notice that could not be sent
+    // is no reason to tell the subscriber otherwise
+    match SubscriberEmail::parse(request.old_email) {
+        Ok(old_email) => {
+            if let Err(e) = send_email_changed_notification(&email_client, &old_email, &request.new_email).await {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    "Failed to notify the previous address of the email change",
+                );
+            }
+        }
+        Err(error) => {
+            tracing::warn!(
+                error.cause_chain = ?error,
+                "Skipping the email change notification. \
+                The previous address is invalid",
+            );
+        }
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(
+    name = "Get email change request from token",
+    skip(transaction, email_change_token)
+)]
+async fn get_email_change_request(
+    transaction: &mut Transaction<'_, Postgres>,
+    email_change_token: &str,
+) -> Result<Option<EmailChangeRequest>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT r.subscriber_id, r.new_email, s.email AS old_email
+        FROM email_change_requests r
+        JOIN subscriptions s ON s.id = r.subscriber_id
+        WHERE r.email_change_token = $1 AND r.requested_at > $2
+        FOR UPDATE
+        "#,
+        email_change_token,
+        Utc::now() - Duration::hours(EMAIL_CHANGE_VALIDITY_HOURS),
+    )
+    .fetch_optional(transaction)
+    .await?;
+
+    Ok(row.map(|r| EmailChangeRequest {
+        subscriber_id: r.subscriber_id,
+        old_email: r.old_email,
+        new_email: r.new_email,
+    }))
+}
+
+async fn email_belongs_to_another_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    request: &EmailChangeRequest,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE lower(email) = lower($1) AND id <> $2"#,
+        request.new_email,
+        request.subscriber_id,
+    )
+    .fetch_optional(transaction)
+    .await?;
+
+    Ok(row.is_some())
+}
+
+#[tracing::instrument(
+    name = "Swap subscriber email",
+    skip(transaction, request),
+    fields(subscriber_id = %request.subscriber_id)
+)]
+async fn swap_subscriber_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    request: &EmailChangeRequest,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET email = $1 WHERE id = $2"#,
+        request.new_email,
+        request.subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    // Any other pending request for this subscriber is now stale.
+    sqlx::query!(
+        r#"DELETE FROM email_change_requests WHERE subscriber_id = $1"#,
+        request.subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Notify the previous address of an email change",
+    skip(email_client, old_email, new_email)
+)]
+async fn send_email_changed_notification(
+    email_client: &EmailClient,
+    old_email: &SubscriberEmail,
+    new_email: &str,
+) -> Result<(), reqwest::Error> {
+    let plain_body = format!(
+        "Your newsletter subscription has been moved to {}.\n\
+        If you did not request this change, please get in touch with us.",
+        new_email
+    );
+    let html_body = format!(
+        "Your newsletter subscription has been moved to {}.<br />\
+        If you did not request this change, please get in touch with us.",
+        new_email
+    );
+
+    email_client
+        .send_email(old_email, "Your subscription email has changed", &html_body, &plain_body)
+        .await
+}
@@ -2,6 +2,10 @@ use actix_web::{web, get, HttpResponse};
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::audit_log::{AuditAction, AuditEntry, RequestOrigin};
+use crate::domain::SubscriptionStatus;
+use crate::routes::{change_subscription_status, ChangeStatusError};
+
 #[derive(serde::Deserialize)]
 pub struct Parameters {
     subscription_token: String,
@@ -9,10 +13,10 @@ pub struct Parameters {
 
 #[tracing::instrument(
     name = "Confirm a pending subscriber",
-    skip(parameters)
+    skip(parameters, origin)
 )]
 #[get("/subscriptions/confirm")]
-pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>) -> HttpResponse {
+pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>, origin: RequestOrigin) -> HttpResponse {
     let id= match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
         Ok(id) => id,
         Err(_) => return HttpResponse::InternalServerError().finish(),
@@ -21,31 +25,37 @@ pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>
     match id {
         // Non existing otken
         None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            }
-            return HttpResponse::Ok().finish();
-        }
+        Some(subscriber_id) => match confirm_subscriber(&pool, subscriber_id, &origin).await {
+            Ok(()) => HttpResponse::Ok().finish(),
+            // e.g. the subscriber has unsubscribed or was deleted since
+            Err(ChangeStatusError::IllegalTransition(_)) => HttpResponse::Conflict().finish(),
+            Err(_) => HttpResponse::InternalServerError().finish(),
+        },
     }
     
 }
 
 #[tracing::instrument(
     name = "Mark subscriber as confirmed",
-    skip(subscriber_id, pool)
+    skip(subscriber_id, pool, origin)
 )]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
-        subscriber_id,
-    )
-    .execute(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid, origin: &RequestOrigin) -> Result<(), ChangeStatusError> {
+    let mut transaction = pool.begin().await?;
+    let from = change_subscription_status(&mut transaction, subscriber_id, SubscriptionStatus::Confirmed)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to confirm subscriber: {:?}", e);
+            e
+        })?;
+    // Following the link again changes nothing
+    if let Some(from) = from {
+        AuditEntry::new(AuditAction::SubscriberConfirmed)
+            .target(subscriber_id)
+            .change("status", from.as_str(), SubscriptionStatus::Confirmed.as_str())
+            .record(&mut *transaction, origin)
+            .await?;
+    }
+    transaction.commit().await?;
 
     return Ok(());
 }
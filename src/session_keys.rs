@@ -0,0 +1,222 @@
+use crate::configuration::SessionSettings;
+use actix_web::body::MessageBody;
+use actix_web::cookie::{Cookie, CookieJar, Key};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderValue, COOKIE};
+use actix_web::web;
+use actix_web_lab::middleware::Next;
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+
+// Set explicitly on `SessionMiddleware`, so that we know which cookie to look at
+pub const SESSION_COOKIE_NAME: &str = "id";
+
+// Shorter keys are rejected by the `cookie` crate, and would be guessable anyway
+const MIN_KEY_LENGTH: usize = 64;
+
+/// The key session cookies are encrypted with, and the one they were encrypted
+/// with before the last rotation.
+#[derive(Clone)]
+pub struct SessionKeys {
+    pub current: Key,
+    pub previous: Option<Key>,
+}
+
+impl SessionKeys {
+    pub fn load(settings: &SessionSettings) -> Result<Self, anyhow::Error> {
+        let current = match load_key("session.key", &settings.key, &settings.key_file)? {
+            Some(key) => key,
+            None if settings.require_key => {
+                anyhow::bail!("No session key configured: set `session.key` or `session.key_file`.")
+            }
+            None => {
+                tracing::warn!("No session key configured, sessions will not survive a restart");
+                Key::generate()
+            }
+        };
+        let previous = load_key("session.previous_key", &settings.previous_key, &settings.previous_key_file)?;
+        Ok(Self { current, previous })
+    }
+
+    /// Re-encrypt a session cookie value from the previous key with the current one.
+    /// Returns `None` for values that need no rewriting or that no key can open.
+    fn reencrypt(&self, value: &str) -> Option<String> {
+        let previous = self.previous.as_ref()?;
+        let cookie = Cookie::new(SESSION_COOKIE_NAME, value.to_string());
+        let jar = CookieJar::new();
+        if jar.private(&self.current).decrypt(cookie.clone()).is_some() {
+            return None;
+        }
+        let decrypted = jar.private(previous).decrypt(cookie)?;
+        let mut jar = CookieJar::new();
+        jar.private_mut(&self.current).add(decrypted);
+        jar.get(SESSION_COOKIE_NAME).map(|c| c.value().to_string())
+    }
+
+    // Rewrite the session cookie within a `Cookie` header, if it was encrypted with the previous key
+    fn rewrite_cookie_header(&self, header: &str) -> Option<String> {
+        let mut rewritten = false;
+        let pairs: Vec<String> = header
+            .split(';')
+            .map(|pair| {
+                let pair = pair.trim();
+                // Values are percent-encoded on the wire, as `SessionMiddleware` sets them
+                if let Ok(cookie) = Cookie::parse_encoded(pair) {
+                    if cookie.name() == SESSION_COOKIE_NAME {
+                        if let Some(value) = self.reencrypt(cookie.value()) {
+                            rewritten = true;
+                            return Cookie::new(SESSION_COOKIE_NAME, value).encoded().to_string();
+                        }
+                    }
+                }
+                pair.to_string()
+            })
+            .collect();
+        if rewritten {
+            Some(pairs.join("; "))
+        } else {
+            None
+        }
+    }
+}
+
+fn load_key(
+    name: &str,
+    inline: &Option<Secret<String>>,
+    file: &Option<String>,
+) -> Result<Option<Key>, anyhow::Error> {
+    let key = match (inline, file) {
+        (Some(_), Some(_)) => anyhow::bail!("Set either `{}` or `{}_file`, not both.", name, name),
+        (Some(key), None) => key.expose_secret().clone(),
+        (None, Some(path)) => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read `{}_file` from {}", name, path))?
+            .trim()
+            .to_string(),
+        (None, None) => return Ok(None),
+    };
+    if key.len() < MIN_KEY_LENGTH {
+        anyhow::bail!("`{}` must be at least {} bytes long.", name, MIN_KEY_LENGTH);
+    }
+    Ok(Some(Key::from(key.as_bytes())))
+}
+
+// Let sessions encrypted with the previous key through, by handing `SessionMiddleware`
+// their cookie re-encrypted with the current one. Must wrap `SessionMiddleware`.
+pub async fn accept_previous_session_key(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let keys = req.app_data::<web::Data<SessionKeys>>().cloned();
+    if let Some(keys) = keys {
+        let rewritten: Vec<Option<String>> = req
+            .headers()
+            .get_all(COOKIE)
+            .map(|h| h.to_str().ok().and_then(|h| keys.rewrite_cookie_header(h)))
+            .collect();
+        if rewritten.iter().any(Option::is_some) {
+            let originals: Vec<HeaderValue> = req.headers().get_all(COOKIE).cloned().collect();
+            let headers = req.headers_mut();
+            headers.remove(COOKIE);
+            for (original, rewritten) in originals.into_iter().zip(rewritten) {
+                let value = match rewritten.and_then(|h| HeaderValue::from_str(&h).ok()) {
+                    Some(value) => value,
+                    None => original,
+                };
+                headers.append(COOKIE, value);
+            }
+        }
+    }
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> SessionSettings {
+        SessionSettings {
+            idle_timeout_minutes: 60,
+            absolute_timeout_hours: 12,
+            key: None,
+            key_file: None,
+            previous_key: None,
+            previous_key_file: None,
+            require_key: true,
+        }
+    }
+
+    // The encrypted, percent-encoded `name=value` pair, as in a `Cookie` header
+    fn encrypt(key: &Key, value: &str) -> String {
+        let mut jar = CookieJar::new();
+        jar.private_mut(key).add(Cookie::new(SESSION_COOKIE_NAME, value.to_string()));
+        jar.get(SESSION_COOKIE_NAME).unwrap().encoded().to_string()
+    }
+
+    fn decrypt(key: &Key, pair: &str) -> Option<String> {
+        CookieJar::new()
+            .private(key)
+            .decrypt(Cookie::parse_encoded(pair.to_string()).unwrap())
+            .map(|c| c.value().to_string())
+    }
+
+    #[test]
+    fn a_missing_key_is_rejected_when_required() {
+        assert!(SessionKeys::load(&settings()).is_err());
+    }
+
+    #[test]
+    fn a_missing_key_is_generated_when_not_required() {
+        let mut settings = settings();
+        settings.require_key = false;
+        assert!(SessionKeys::load(&settings).is_ok());
+    }
+
+    #[test]
+    fn a_short_key_is_rejected() {
+        let mut settings = settings();
+        settings.key = Some(Secret::new("a".repeat(MIN_KEY_LENGTH - 1)));
+        assert!(SessionKeys::load(&settings).is_err());
+    }
+
+    #[test]
+    fn a_key_can_be_read_from_a_file() {
+        let path = std::env::temp_dir().join(format!("session-key-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, format!("{}\n", "k".repeat(MIN_KEY_LENGTH))).unwrap();
+        let mut settings = settings();
+        settings.key_file = Some(path.to_string_lossy().to_string());
+
+        let keys = SessionKeys::load(&settings).unwrap();
+
+        assert!(keys.current == Key::from("k".repeat(MIN_KEY_LENGTH).as_bytes()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn cookies_from_the_previous_key_are_reencrypted_with_the_current_one() {
+        let keys = SessionKeys {
+            current: Key::generate(),
+            previous: Some(Key::generate()),
+        };
+        let old = encrypt(keys.previous.as_ref().unwrap(), "session-state-key");
+        let header = format!("other=1; {}", old);
+
+        let rewritten = keys.rewrite_cookie_header(&header).unwrap();
+
+        let (other, session) = rewritten.split_once("; ").unwrap();
+        assert_eq!(other, "other=1");
+        assert_eq!(decrypt(&keys.current, session).as_deref(), Some("session-state-key"));
+    }
+
+    #[test]
+    fn cookies_from_the_current_or_an_unknown_key_are_left_alone() {
+        let keys = SessionKeys {
+            current: Key::generate(),
+            previous: Some(Key::generate()),
+        };
+        let current = encrypt(&keys.current, "a");
+        let unknown = encrypt(&Key::generate(), "a");
+
+        assert_eq!(keys.rewrite_cookie_header(&current), None);
+        assert_eq!(keys.rewrite_cookie_header(&unknown), None);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::authentication::PendingOidcLogin;
 use actix_session::Session;
 use uuid::Uuid;
 use actix_session::SessionExt;
@@ -9,12 +10,28 @@ use std::future::{Ready, ready};
 pub struct TypedSession(Session);
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    // Our own id for the session, pointing at its row in `user_sessions`
+    const SESSION_ID_KEY: &'static str = "session_id";
+    // Set once the password has been checked, until the second factor is too.
+    // It must never grant access to anything but the second login step.
+    const PENDING_TWO_FACTOR_USER_ID_KEY: &'static str = "pending_two_factor_user_id";
+    const TWO_FACTOR_ATTEMPTS_KEY: &'static str = "two_factor_attempts";
+    // Secret shown on the enrollment page, until the user confirms it with a code
+    const PENDING_TOTP_SECRET_KEY: &'static str = "pending_totp_secret";
+    // Between sending the user to the single sign-on provider and their return
+    const PENDING_OIDC_LOGIN_KEY: &'static str = "pending_oidc_login";
+    // Challenges the browser must have a passkey sign, each good for one answer
+    const PASSKEY_REGISTRATION_CHALLENGE_KEY: &'static str = "passkey_registration_challenge";
+    const PASSKEY_LOGIN_CHALLENGE_KEY: &'static str = "passkey_login_challenge";
+    // Embedded in our forms, see `crate::csrf`
+    const CSRF_TOKEN_KEY: &'static str = "csrf_token";
 
     pub fn renew(&self) {
         return self.0.renew();
     }
 
-    pub fn insert_user_id(&self, user_id: Uuid) -> Result<(), serde_json::Error> {
+    pub fn insert_user_id(&self, user_id: Uuid, session_id: Uuid) -> Result<(), serde_json::Error> {
+        self.0.insert(Self::SESSION_ID_KEY, session_id)?;
         return self.0.insert(Self::USER_ID_KEY, user_id);
     }
 
@@ -22,6 +39,82 @@ impl TypedSession {
         return self.0.get(Self::USER_ID_KEY);
     }
 
+    pub fn get_session_id(&self) -> Result<Option<Uuid>, serde_json::Error> {
+        self.0.get(Self::SESSION_ID_KEY)
+    }
+
+    pub fn insert_pending_two_factor_user_id(&self, user_id: Uuid) -> Result<(), serde_json::Error> {
+        self.0.remove(Self::TWO_FACTOR_ATTEMPTS_KEY);
+        self.0.insert(Self::PENDING_TWO_FACTOR_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_pending_two_factor_user_id(&self) -> Result<Option<Uuid>, serde_json::Error> {
+        self.0.get(Self::PENDING_TWO_FACTOR_USER_ID_KEY)
+    }
+
+    pub fn remove_pending_two_factor_user_id(&self) {
+        self.0.remove(Self::TWO_FACTOR_ATTEMPTS_KEY);
+        self.0.remove(Self::PENDING_TWO_FACTOR_USER_ID_KEY);
+    }
+
+    /// Count one more attempt at the second login step and return the total so far.
+    pub fn record_two_factor_attempt(&self) -> Result<u32, serde_json::Error> {
+        let attempts = self.0.get::<u32>(Self::TWO_FACTOR_ATTEMPTS_KEY)?.unwrap_or(0) + 1;
+        self.0.insert(Self::TWO_FACTOR_ATTEMPTS_KEY, attempts)?;
+        Ok(attempts)
+    }
+
+    pub fn insert_pending_totp_secret(&self, secret: &str) -> Result<(), serde_json::Error> {
+        self.0.insert(Self::PENDING_TOTP_SECRET_KEY, secret)
+    }
+
+    pub fn get_pending_totp_secret(&self) -> Result<Option<String>, serde_json::Error> {
+        self.0.get(Self::PENDING_TOTP_SECRET_KEY)
+    }
+
+    pub fn remove_pending_totp_secret(&self) {
+        self.0.remove(Self::PENDING_TOTP_SECRET_KEY);
+    }
+
+    pub fn insert_pending_oidc_login(&self, pending: &PendingOidcLogin) -> Result<(), serde_json::Error> {
+        self.0.insert(Self::PENDING_OIDC_LOGIN_KEY, pending)
+    }
+
+    /// The pending single sign-on login, which can only be finished once.
+    pub fn take_pending_oidc_login(&self) -> Result<Option<PendingOidcLogin>, serde_json::Error> {
+        let pending = self.0.get(Self::PENDING_OIDC_LOGIN_KEY)?;
+        self.0.remove(Self::PENDING_OIDC_LOGIN_KEY);
+        Ok(pending)
+    }
+
+    pub fn insert_passkey_registration_challenge(&self, challenge: &str) -> Result<(), serde_json::Error> {
+        self.0.insert(Self::PASSKEY_REGISTRATION_CHALLENGE_KEY, challenge)
+    }
+
+    pub fn take_passkey_registration_challenge(&self) -> Result<Option<String>, serde_json::Error> {
+        let challenge = self.0.get(Self::PASSKEY_REGISTRATION_CHALLENGE_KEY)?;
+        self.0.remove(Self::PASSKEY_REGISTRATION_CHALLENGE_KEY);
+        Ok(challenge)
+    }
+
+    pub fn insert_passkey_login_challenge(&self, challenge: &str) -> Result<(), serde_json::Error> {
+        self.0.insert(Self::PASSKEY_LOGIN_CHALLENGE_KEY, challenge)
+    }
+
+    pub fn take_passkey_login_challenge(&self) -> Result<Option<String>, serde_json::Error> {
+        let challenge = self.0.get(Self::PASSKEY_LOGIN_CHALLENGE_KEY)?;
+        self.0.remove(Self::PASSKEY_LOGIN_CHALLENGE_KEY);
+        Ok(challenge)
+    }
+
+    pub fn insert_csrf_token(&self, token: &str) -> Result<(), serde_json::Error> {
+        self.0.insert(Self::CSRF_TOKEN_KEY, token)
+    }
+
+    pub fn get_csrf_token(&self) -> Result<Option<String>, serde_json::Error> {
+        self.0.get(Self::CSRF_TOKEN_KEY)
+    }
+
     pub fn log_out(self) {
         return self.0.purge();
     }
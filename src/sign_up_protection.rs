@@ -0,0 +1,228 @@
+use crate::configuration::SignUpProtectionSettings;
+use crate::domain::SubscriberEmail;
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use redis::aio::ConnectionManager;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+
+const KEY_PREFIX: &str = "sign_up_protection";
+// How long a proof of work challenge can be solved for.
+const CHALLENGE_TTL_SECONDS: u64 = 600;
+
+/// Guards `subscribe` against bots: rate limits per client IP and per email
+/// address, an optional honeypot field and an optional proof of work.
+///
+/// Counters live in Redis so that limits hold across all our instances.
+pub struct SignUpProtection {
+    redis: ConnectionManager,
+    settings: SignUpProtectionSettings,
+    hmac_secret: Secret<String>,
+}
+
+/// Why a sign-up attempt was turned down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockReason {
+    Honeypot,
+    DisposableDomain,
+    IpRateLimit,
+    EmailRateLimit,
+    ProofOfWork,
+}
+
+impl BlockReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlockReason::Honeypot => "honeypot",
+            BlockReason::DisposableDomain => "disposable_domain",
+            BlockReason::IpRateLimit => "ip_rate_limit",
+            BlockReason::EmailRateLimit => "email_rate_limit",
+            BlockReason::ProofOfWork => "proof_of_work",
+        }
+    }
+}
+
+/// A proof of work puzzle: find a `nonce` such that the SHA-256 digest of
+/// `"{challenge}:{nonce}"` starts with `difficulty` zero bits.
+#[derive(serde::Serialize, Debug)]
+pub struct Challenge {
+    pub challenge: String,
+    pub difficulty: u8,
+}
+
+impl SignUpProtection {
+    pub fn new(
+        redis: ConnectionManager,
+        mut settings: SignUpProtectionSettings,
+        hmac_secret: Secret<String>,
+    ) -> Self {
+        // Compare domains the way `SubscriberEmail` stores them
+        for domain in settings.blocked_email_domains.iter_mut() {
+            *domain = idna::domain_to_ascii(domain.trim()).unwrap_or_else(|_| domain.to_lowercase());
+        }
+        Self { redis, settings, hmac_secret }
+    }
+
+    pub fn honeypot_enabled(&self) -> bool {
+        self.settings.honeypot_enabled
+    }
+
+    pub fn proof_of_work_difficulty(&self) -> Option<u8> {
+        self.settings.proof_of_work_difficulty
+    }
+
+    /// Whether `email` belongs to a blocked (disposable) domain or one of its subdomains.
+    pub fn is_blocked_domain(&self, email: &SubscriberEmail) -> bool {
+        let domain = email.domain();
+        self.settings.blocked_email_domains.iter().any(|blocked| {
+            domain == blocked || domain.ends_with(&format!(".{}", blocked))
+        })
+    }
+
+    /// Count an attempt from `ip` and tell whether it went over the limit.
+    pub async fn ip_rate_limit_exceeded(&self, ip: &str) -> Result<bool, anyhow::Error> {
+        let attempts = self.count_attempt(&format!("{}:ip:{}", KEY_PREFIX, ip)).await?;
+        Ok(attempts > self.settings.max_attempts_per_ip)
+    }
+
+    /// Count an attempt for `email` and tell whether it went over the limit.
+    pub async fn email_rate_limit_exceeded(&self, email: &SubscriberEmail) -> Result<bool, anyhow::Error> {
+        let key = format!("{}:email:{}", KEY_PREFIX, email.as_ref().to_lowercase());
+        let attempts = self.count_attempt(&key).await?;
+        Ok(attempts > self.settings.max_attempts_per_email)
+    }
+
+    // Fixed window counter: the first attempt in a window sets its expiry.
+    async fn count_attempt(&self, key: &str) -> Result<u64, anyhow::Error> {
+        let (attempts,): (u64,) = redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(key)
+            .arg(0)
+            .arg("EX")
+            .arg(self.settings.rate_limit_window_seconds)
+            .arg("NX")
+            .ignore()
+            .cmd("INCR")
+            .arg(key)
+            .query_async(&mut self.redis.clone())
+            .await
+            .context("Failed to count a sign-up attempt in Redis.")?;
+        Ok(attempts)
+    }
+
+    /// Log a blocked attempt and bump the per-reason counter operators can
+    /// look at in Redis (`sign_up_protection:blocked:<reason>`).
+    #[tracing::instrument(name = "Record a blocked sign-up attempt", skip(self))]
+    pub async fn record_blocked_attempt(&self, reason: BlockReason, ip: &str) {
+        tracing::warn!(reason = reason.as_str(), ip, "Blocked a sign-up attempt");
+        let key = format!("{}:blocked:{}", KEY_PREFIX, reason.as_str());
+        let outcome: Result<u64, _> = redis::cmd("INCR")
+            .arg(&key)
+            .query_async(&mut self.redis.clone())
+            .await;
+        if let Err(e) = outcome {
+            tracing::error!(error.cause_chain = ?e, "Failed to count a blocked sign-up attempt");
+        }
+    }
+
+    /// Issue a fresh challenge, signed so that we don't need to store it.
+    pub fn issue_challenge(&self, difficulty: u8) -> Challenge {
+        let issued_at = chrono::Utc::now().timestamp();
+        let random: String = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .map(char::from)
+            .take(16)
+            .collect();
+        let payload = format!("{}.{}", issued_at, random);
+        let signature = base64::encode_config(self.sign(&payload), base64::URL_SAFE_NO_PAD);
+        Challenge {
+            challenge: format!("{}.{}", payload, signature),
+            difficulty,
+        }
+    }
+
+    /// Check that `nonce` solves a challenge we issued recently and that the
+    /// challenge has not been used before.
+    pub async fn verify_proof_of_work(
+        &self,
+        challenge: &str,
+        nonce: &str,
+        difficulty: u8,
+    ) -> Result<bool, anyhow::Error> {
+        let (payload, signature) = match challenge.rsplit_once('.') {
+            Some(parts) => parts,
+            None => return Ok(false),
+        };
+        let signature = match base64::decode_config(signature, base64::URL_SAFE_NO_PAD) {
+            Ok(signature) => signature,
+            Err(_) => return Ok(false),
+        };
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        if mac.verify_slice(&signature).is_err() {
+            return Ok(false);
+        }
+
+        let issued_at: i64 = match payload.split_once('.').map(|(t, _)| t.parse()) {
+            Some(Ok(issued_at)) => issued_at,
+            _ => return Ok(false),
+        };
+        let age = chrono::Utc::now().timestamp() - issued_at;
+        if age < 0 || age as u64 > CHALLENGE_TTL_SECONDS {
+            return Ok(false);
+        }
+
+        let digest = Sha256::digest(format!("{}:{}", challenge, nonce).as_bytes());
+        if leading_zero_bits(&digest) < u32::from(difficulty) {
+            return Ok(false);
+        }
+
+        // Each challenge can only be redeemed once.
+        let first_use: Option<String> = redis::cmd("SET")
+            .arg(format!("{}:challenge:{}", KEY_PREFIX, payload))
+            .arg(1)
+            .arg("EX")
+            .arg(CHALLENGE_TTL_SECONDS)
+            .arg("NX")
+            .query_async(&mut self.redis.clone())
+            .await
+            .context("Failed to mark a proof of work challenge as used.")?;
+        Ok(first_use.is_some())
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(self.hmac_secret.expose_secret().as_bytes()).unwrap()
+    }
+
+    fn sign(&self, payload: &str) -> Vec<u8> {
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut count = 0;
+    for byte in bytes {
+        count += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::leading_zero_bits;
+
+    #[test]
+    fn leading_zero_bits_are_counted_across_bytes() {
+        assert_eq!(leading_zero_bits(&[0xff]), 0);
+        assert_eq!(leading_zero_bits(&[0x00, 0x10]), 11);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+    }
+}
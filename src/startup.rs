@@ -1,21 +1,47 @@
+use crate::bootstrap::bootstrap_owner;
 use crate::email_client::EmailClient;
+use crate::migrations::prepare_database;
 use crate::routes::{health_check, subscribe, confirm, publish_newsletter, admin_dashboard, log_out, change_password_form, change_password};
+use crate::routes::{change_email, confirm_email_change, subscription_challenge};
+use crate::routes::{users_page, create_user, change_user_role, change_two_factor_requirement, disable_user, enable_user};
+use crate::routes::{disable_password_login, enable_password_login};
+use crate::routes::{two_factor_page, enroll_two_factor, remove_two_factor, two_factor_form, verify_two_factor};
+use crate::routes::{invites_page, invite_user, revoke_invite, accept_invite, accept_invite_form};
+use crate::routes::{setup_form, set_up};
+use crate::routes::{lockouts_page, unlock_logins};
+use crate::routes::{api_tokens_page, create_api_token, revoke_api_token};
+use crate::routes::{sessions_page, revoke_one_session, log_out_everywhere};
+use crate::routes::{audit_log_page, export_audit_log};
+use crate::routes::{reports_page, subscribers_page, change_subscriber_status};
 use actix_web::dev::Server;
 use actix_web::web::{Data, self};
 use actix_web::{App, HttpServer};
 use sqlx::PgPool;
-use std::net::TcpListener;
+use std::net::{IpAddr, TcpListener};
 use tracing_actix_web::TracingLogger;
 use crate::configuration::Settings;
 use sqlx::postgres::PgPoolOptions;
-use crate::configuration::DatabaseSettings;
+use crate::configuration::{ApiSettings, DatabaseSettings, MagicLinkSettings, OidcSettings, LoginProtectionSettings, PasswordHashingSettings, SessionSettings, SignUpProtectionSettings};
+use crate::csrf::reject_cross_site_requests;
+use crate::login_protection::LoginProtection;
+use crate::session_keys::{accept_previous_session_key, SessionKeys, SESSION_COOKIE_NAME};
+use crate::sign_up_protection::SignUpProtection;
 use crate::routes::{home, login, login_form};
-use actix_session::SessionMiddleware;
-use actix_web::cookie::Key;
+use crate::routes::{magic_link_form, request_magic_link, magic_link_confirm_form, log_in_with_magic_link};
+use crate::routes::{start_oidc_login, oidc_callback};
+use crate::routes::{passkey_login_options, log_in_with_passkey};
+use crate::routes::{passkeys_page, passkey_registration_options, register_passkey, rename_passkey, delete_passkey};
+use crate::routes::{password_reset_form, request_password_reset, reset_password_form, reset_password};
+use actix_session::{SessionLength, SessionMiddleware};
+use actix_web::cookie::time;
 use actix_session::storage::RedisSessionStore;
 use secrecy::{ExposeSecret, Secret};
-use crate::authentication::reject_anonymous_users;
+use crate::authentication::{enforce_password_change, enforce_two_factor_enrollment, load_user_role, reject_anonymous_users, require_permission, OidcClient, Permission, RelyingParty};
 use actix_web_lab::middleware::from_fn;
+use actix_cors::Cors;
+use actix_web::http::header;
+use actix_web_flash_messages::FlashMessagesFramework;
+use actix_web_flash_messages::storage::CookieMessageStore;
 
 // A new type to hold the newly built server and its port
 pub struct Application {
@@ -29,26 +55,37 @@ pub struct Application {
 // a raw `String` would expose us to conflicts.
 pub struct ApplicationBaseUrl(pub String);
 
+// Key for the signatures of links we send by email
+#[derive(Clone)]
+pub struct HmacSecret(pub Secret<String>);
+
+// Proxies allowed to tell us the client IP address, see `utils::client_ip`
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
 impl Application {
     // We have converted the `build` function into a constructor for `Application`
     pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
+        // Fail at startup rather than on the first login
+        configuration
+            .password_hashing
+            .params()
+            .map_err(|e| anyhow::anyhow!("Invalid password hashing parameters: {}", e))?;
+        prepare_database(&connection_pool, &configuration.database).await?;
+        bootstrap_owner(
+            &connection_pool,
+            &configuration.bootstrap,
+            &configuration.password_hashing,
+            &configuration.application.base_url,
+        )
+        .await?;
 
-        let sender_email = configuration
+        // Build an `EmailClient` using `configuration`
+        let email_client = configuration
             .email_client
-            .sender()
+            .client()
             .expect("Invalid sender email address.");
 
-        let timeout = configuration.email_client.timeout();
-
-        // Build an `EmailClient` using `configuration`
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
-
         let address = format!("{}:{}"
             , configuration.application.host, configuration.application.port
         );
@@ -61,7 +98,17 @@ impl Application {
             connection_pool,
             email_client,
             configuration.application.base_url,
+            configuration.application.hmac_secret,
+            configuration.application.cors_allowed_origins,
+            configuration.application.trusted_proxies,
             configuration.redis_uri,
+            configuration.sign_up_protection,
+            configuration.login_protection,
+            configuration.session,
+            configuration.password_hashing,
+            configuration.api,
+            configuration.magic_link,
+            configuration.oidc,
         ).await?;
 
         // We "save" the bound port in one of `Application`'s fields
@@ -82,42 +129,183 @@ impl Application {
 
 
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
     base_url: String,
+    hmac_secret: Secret<String>,
+    cors_allowed_origins: Vec<String>,
+    trusted_proxies: Vec<IpAddr>,
     redis_uri: Secret<String>,
+    sign_up_protection: SignUpProtectionSettings,
+    login_protection: LoginProtectionSettings,
+    session: SessionSettings,
+    password_hashing: PasswordHashingSettings,
+    api: ApiSettings,
+    magic_link: MagicLinkSettings,
+    oidc: Option<OidcSettings>,
 ) -> Result<Server, anyhow::Error> {
     let db_pool = Data::new(db_pool);
     let email_client = Data::new(email_client);
+    let oidc = Data::new(oidc.map(|settings| OidcClient::new(settings, &base_url)));
+    let relying_party = Data::new(RelyingParty::from_base_url(&base_url)?);
     let base_url = Data::new(ApplicationBaseUrl(base_url));
-    let secret_key = Key::from("test".as_bytes());
+    let trusted_proxies = Data::new(TrustedProxies(trusted_proxies));
+    let password_hashing = Data::new(password_hashing);
+    let api = Data::new(api);
+    let magic_links_enabled = magic_link.enabled;
+    let magic_link = Data::new(magic_link);
+    // Keep session state in Redis no longer than a session may last
+    let session_length = SessionLength::BrowserSession {
+        state_ttl: Some(time::Duration::hours(session.absolute_timeout_hours as i64)),
+    };
+    let session = Data::new(session);
+    let hmac_secret_data = Data::new(HmacSecret(hmac_secret.clone()));
+    let session_keys = SessionKeys::load(&session)?;
+    let secret_key = session_keys.current.clone();
+    let session_keys = Data::new(session_keys);
+    let message_store = CookieMessageStore::builder(secret_key.clone()).build();
+    let message_framework = FlashMessagesFramework::builder(message_store).build();
     let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
+    let redis_connection = redis::Client::open(redis_uri.expose_secret().as_str())?
+        .get_tokio_connection_manager()
+        .await?;
+    let login_protection = Data::new(LoginProtection::new(redis_connection.clone(), login_protection));
+    let sign_up_protection = Data::new(SignUpProtection::new(
+        redis_connection,
+        sign_up_protection,
+        hmac_secret.clone(),
+    ));
 
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(from_fn(reject_cross_site_requests))
             // TracingLogger instead of default actix_web logger to return with request_id (and other information aswell)
+            .wrap(message_framework.clone())
             .wrap(TracingLogger::default())
-            .wrap(SessionMiddleware::new(redis_store.clone(), secret_key.clone()))
+            .wrap(
+                SessionMiddleware::builder(redis_store.clone(), secret_key.clone())
+                    .cookie_name(SESSION_COOKIE_NAME.to_string())
+                    .session_length(session_length.clone())
+                    .build(),
+            )
+            .wrap(from_fn(accept_previous_session_key))
 
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(sign_up_protection.clone())
+            .app_data(login_protection.clone())
+            .app_data(password_hashing.clone())
+            .app_data(api.clone())
+            .app_data(magic_link.clone())
+            .app_data(oidc.clone())
+            .app_data(relying_party.clone())
+            .app_data(session.clone())
+            .app_data(session_keys.clone())
+            .app_data(hmac_secret_data.clone())
 
             .service(home)
             .service(login)
             .service(login_form)
-            .service(subscribe)
+            .service(two_factor_form)
+            .service(verify_two_factor)
+            .configure(|cfg| {
+                if magic_links_enabled {
+                    cfg.service(magic_link_form)
+                        .service(request_magic_link)
+                        .service(magic_link_confirm_form)
+                        .service(log_in_with_magic_link);
+                }
+            })
+            .service(start_oidc_login)
+            .service(oidc_callback)
+            .service(passkey_login_options)
+            .service(log_in_with_passkey)
+            .service(password_reset_form)
+            .service(request_password_reset)
+            .service(reset_password_form)
+            .service(reset_password)
+            .service(web::resource("/subscriptions")
+                .wrap(subscriptions_cors(&cors_allowed_origins))
+                .route(web::post().to(subscribe))
+            )
+            .service(web::resource("/subscriptions/challenge")
+                .wrap(subscriptions_cors(&cors_allowed_origins))
+                .route(web::get().to(subscription_challenge))
+            )
             .service(health_check)
             .service(confirm)
+            .service(change_email)
+            .service(confirm_email_change)
             .service(publish_newsletter)
+            .service(accept_invite_form)
+            .service(accept_invite)
+            .service(setup_form)
+            .service(set_up)
             .service(web::scope("/admin")
-                .wrap(from_fn(reject_anonymous_user))
-                .route("/dashboard", web::post().to(admin_dashboard))
+                // Middleware run in the reverse order they are registered in
+                .wrap(from_fn(enforce_two_factor_enrollment))
+                .wrap(from_fn(enforce_password_change))
+                .wrap(from_fn(load_user_role))
+                .wrap(from_fn(reject_anonymous_users))
+                .route("/dashboard", web::get().to(admin_dashboard))
                 .route("/password", web::get().to(change_password_form))
                 .route("/password", web::post().to(change_password))
                 .route("/logout", web::post().to(log_out))
+                .route("/two_factor", web::get().to(two_factor_page))
+                .route("/two_factor", web::post().to(enroll_two_factor))
+                .route("/two_factor/disable", web::post().to(remove_two_factor))
+                .route("/passkeys", web::get().to(passkeys_page))
+                .route("/passkeys", web::post().to(register_passkey))
+                .route("/passkeys/options", web::post().to(passkey_registration_options))
+                .route("/passkeys/{passkey_id}/rename", web::post().to(rename_passkey))
+                .route("/passkeys/{passkey_id}/delete", web::post().to(delete_passkey))
+                .route("/api_tokens", web::get().to(api_tokens_page))
+                .route("/api_tokens", web::post().to(create_api_token))
+                .route("/api_tokens/{token_id}/revoke", web::post().to(revoke_api_token))
+                .route("/sessions", web::get().to(sessions_page))
+                .route("/sessions/revoke_all", web::post().to(log_out_everywhere))
+                .route("/sessions/{session_id}/revoke", web::post().to(revoke_one_session))
+                .service(web::scope("/users")
+                    .wrap(require_permission(Permission::ManageUsers))
+                    .route("", web::get().to(users_page))
+                    .route("", web::post().to(create_user))
+                    .route("/two_factor", web::post().to(change_two_factor_requirement))
+                    .route("/{user_id}/role", web::post().to(change_user_role))
+                    .route("/{user_id}/disable", web::post().to(disable_user))
+                    .route("/{user_id}/enable", web::post().to(enable_user))
+                    .route("/{user_id}/password_login/disable", web::post().to(disable_password_login))
+                    .route("/{user_id}/password_login/enable", web::post().to(enable_password_login))
+                )
+                .service(web::scope("/invites")
+                    .wrap(require_permission(Permission::ManageUsers))
+                    .route("", web::get().to(invites_page))
+                    .route("", web::post().to(invite_user))
+                    .route("/{invite_id}/revoke", web::post().to(revoke_invite))
+                )
+                .service(web::scope("/lockouts")
+                    .wrap(require_permission(Permission::ManageUsers))
+                    .route("", web::get().to(lockouts_page))
+                    .route("/unlock", web::post().to(unlock_logins))
+                )
+                .service(web::scope("/reports")
+                    .wrap(require_permission(Permission::ViewReports))
+                    .route("", web::get().to(reports_page))
+                )
+                .service(web::scope("/subscribers")
+                    .wrap(require_permission(Permission::ManageSubscribers))
+                    .route("", web::get().to(subscribers_page))
+                    .route("/{subscriber_id}/status", web::post().to(change_subscriber_status))
+                )
+                .service(web::scope("/audit_log")
+                    .wrap(require_permission(Permission::ViewAuditLog))
+                    .route("", web::get().to(audit_log_page))
+                    .route("/export", web::get().to(export_audit_log))
+                )
             )
        
     })
@@ -127,6 +315,19 @@ pub async fn run(
     return Ok(server);
 }
 
+// Let the configured origins (e.g. our marketing site) call `POST /subscriptions`
+// and fetch sign-up challenges from JavaScript. Requests from other origins are still served, they just don't
+// get CORS headers back, so that plain HTML forms keep working.
+fn subscriptions_cors(allowed_origins: &[String]) -> Cors {
+    let cors = Cors::default()
+        .allowed_methods(vec!["GET", "POST"])
+        .allowed_header(header::CONTENT_TYPE)
+        .block_on_origin_mismatch(false)
+        .max_age(3600);
+    allowed_origins
+        .iter()
+        .fold(cors, |cors, origin| cors.allowed_origin(origin))
+}
 
 pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
     return PgPoolOptions::new()
@@ -4,6 +4,7 @@ use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
 use tracing_subscriber::fmt::MakeWriter;
+use tokio::task::JoinHandle;
 
 
 // Compose multiple layers into a `tracing`'s subscriber.
@@ -55,3 +56,14 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     // what subscriber should be used to process spans.
     set_global_default(subscriber).expect("Failed to set subscriber");
 }
+
+// Run CPU-intensive work (e.g. password hashing) on tokio's blocking thread pool,
+// without losing track of the span it was started from.
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
+}
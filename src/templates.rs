@@ -0,0 +1,109 @@
+use crate::utils::e500;
+use actix_web::dev::Payload;
+use actix_web::http::header::ContentType;
+use actix_web::{FromRequest, HttpRequest, HttpResponse, HttpResponseBuilder};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use std::future::Future;
+use std::pin::Pin;
+
+/// The flash messages of the request, as `templates/flash.html` shows them at the top of every page.
+/// Every page template has them as its `flash_messages` field.
+#[derive(Default)]
+pub struct FlashMessages(Vec<String>);
+
+impl FlashMessages {
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+}
+
+impl From<&IncomingFlashMessages> for FlashMessages {
+    fn from(incoming: &IncomingFlashMessages) -> Self {
+        Self(incoming.iter().map(|m| m.content().to_string()).collect())
+    }
+}
+
+impl FromRequest for FlashMessages {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let incoming = IncomingFlashMessages::from_request(req, payload);
+        Box::pin(async move { Ok(Self::from(&incoming.await?)) })
+    }
+}
+
+/// A page with nothing but a message, e.g. for a link that no longer works.
+#[derive(Template)]
+#[template(path = "error.html")]
+pub struct ErrorPage<'a> {
+    pub title: &'a str,
+    pub message: &'a str,
+    pub flash_messages: FlashMessages,
+}
+
+impl<'a> ErrorPage<'a> {
+    pub fn new(title: &'a str, message: &'a str) -> Self {
+        Self {
+            title,
+            message,
+            flash_messages: FlashMessages::default(),
+        }
+    }
+}
+
+/// Render `template` as a `200 OK` HTML page.
+pub fn render(template: &impl Template) -> Result<HttpResponse, actix_web::Error> {
+    render_with_status(HttpResponse::Ok(), template)
+}
+
+pub fn render_with_status(
+    mut response: HttpResponseBuilder,
+    template: &impl Template,
+) -> Result<HttpResponse, actix_web::Error> {
+    let body = template.render().map_err(e500)?;
+    Ok(response.content_type(ContentType::html()).body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Template)]
+    #[template(source = "{% include \"flash.html\" %}", ext = "html")]
+    struct FlashOnly {
+        flash_messages: FlashMessages,
+    }
+
+    #[test]
+    fn flash_messages_are_escaped() {
+        let page = FlashOnly {
+            flash_messages: FlashMessages(vec!["<script>alert('hi')</script>".to_string()]),
+        };
+
+        let html = page.render().unwrap();
+
+        assert_eq!(html, "<p><i>&lt;script&gt;alert(&#x27;hi&#x27;)&lt;/script&gt;</i></p>\n");
+    }
+
+    #[test]
+    fn every_flash_message_gets_its_own_paragraph() {
+        let page = FlashOnly {
+            flash_messages: FlashMessages(vec!["One".to_string(), "Two".to_string()]),
+        };
+
+        let html = page.render().unwrap();
+
+        assert_eq!(html, "<p><i>One</i></p>\n<p><i>Two</i></p>\n");
+    }
+
+    #[test]
+    fn error_pages_share_the_layout_and_escape_their_message() {
+        let html = ErrorPage::new("Oops", "<b>Gone</b>").render().unwrap();
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<title>Oops</title>"));
+        assert!(html.contains("<p>&lt;b&gt;Gone&lt;/b&gt;</p>"));
+    }
+}
@@ -1,5 +1,9 @@
-use actix_web::http::header::LOCATION;
-use actix_web::HttpResponse;
+use crate::startup::TrustedProxies;
+use actix_web::http::header::{LOCATION, USER_AGENT};
+use actix_web::http::StatusCode;
+use actix_web::web::Data;
+use actix_web::{HttpRequest, HttpResponse};
+use std::net::IpAddr;
 
 // Return an opaque 500 while preserving the error root's cause for logging.
 pub fn e500<T>(e: T) -> actix_web::Error
@@ -12,4 +16,84 @@ pub fn see_other(location: &str) -> HttpResponse {
     HttpResponse::SeeOther()
         .insert_header((LOCATION, location))
         .finish()
-}
\ No newline at end of file
+}
+
+// For pages that call us from a script, e.g. to use a passkey: the script
+// sends the browser on to `location`, where flash messages are shown.
+pub fn script_redirect(status: StatusCode, location: &str) -> HttpResponse {
+    HttpResponse::build(status).json(serde_json::json!({ "redirect": location }))
+}
+
+// The client IP address. Requests relayed by one of the trusted proxies carry
+// it in `X-Forwarded-For`, which anybody else could make up.
+pub fn client_ip(request: &HttpRequest) -> String {
+    let peer = match request.peer_addr() {
+        Some(address) => address.ip(),
+        None => return "unknown".to_string(),
+    };
+    let trusted_proxies = request
+        .app_data::<Data<TrustedProxies>>()
+        .map(|proxies| proxies.0.as_slice())
+        .unwrap_or_default();
+    let forwarded_for = request
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|h| h.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    forwarded_client_ip(peer, &forwarded_for, trusted_proxies).to_string()
+}
+
+// Each proxy appends the address it got the request from: walk back from our
+// peer until the first hop we do not trust, which is the client as far as we
+// can tell. Whatever comes before it may have been made up by that client.
+fn forwarded_client_ip(peer: IpAddr, forwarded_for: &str, trusted_proxies: &[IpAddr]) -> IpAddr {
+    let mut client = peer;
+    let mut hops = forwarded_for.rsplit(',').map(str::trim).filter(|hop| !hop.is_empty());
+    while trusted_proxies.contains(&client) {
+        match hops.next().map(str::parse::<IpAddr>) {
+            Some(Ok(hop)) => client = hop,
+            _ => break,
+        }
+    }
+    client
+}
+
+// As sent by the client, so only good for showing back to people
+pub fn user_agent(request: &HttpRequest) -> &str {
+    request
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("unknown")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::forwarded_client_ip;
+    use std::net::IpAddr;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_when_the_peer_is_not_a_trusted_proxy() {
+        let client = forwarded_client_ip(ip("203.0.113.7"), "198.51.100.1", &[ip("10.0.0.1")]);
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn the_last_untrusted_hop_is_the_client() {
+        let proxies = [ip("10.0.0.1"), ip("10.0.0.2")];
+        let client = forwarded_client_ip(ip("10.0.0.1"), "198.51.100.1, 203.0.113.7, 10.0.0.2", &proxies);
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn a_trusted_proxy_without_a_valid_forwarded_for_is_the_client() {
+        let proxies = [ip("10.0.0.1")];
+        assert_eq!(forwarded_client_ip(ip("10.0.0.1"), "", &proxies), ip("10.0.0.1"));
+        assert_eq!(forwarded_client_ip(ip("10.0.0.1"), "not-an-ip", &proxies), ip("10.0.0.1"));
+    }
+}
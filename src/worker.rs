@@ -0,0 +1,62 @@
+use crate::configuration::{SessionSettings, Settings};
+use crate::startup::get_connection_pool;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use std::time::Duration;
+
+/// What one round of housekeeping removed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PrunedRecords {
+    pub sessions: u64,
+    pub password_resets: u64,
+    pub magic_links: u64,
+    pub setup_tokens: u64,
+}
+
+/// Remove what can no longer be used every `interval`. Logins only clean up
+/// after the user logging in, and one-time links are otherwise kept forever.
+pub async fn run_worker_until_stopped(configuration: Settings, interval: Duration) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&configuration.database);
+    loop {
+        match prune_expired_records(&pool, &configuration.session).await {
+            Ok(pruned) => tracing::info!(?pruned, "Pruned expired records"),
+            // The next round will try again
+            Err(e) => tracing::error!(error.cause_chain = ?e, "Failed to prune expired records"),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[tracing::instrument(name = "Prune expired records", skip(pool, settings))]
+pub async fn prune_expired_records(pool: &PgPool, settings: &SessionSettings) -> Result<PrunedRecords, anyhow::Error> {
+    let now = Utc::now();
+    let sessions = sqlx::query!(
+        r#"
+        DELETE FROM user_sessions
+        WHERE revoked_at IS NOT NULL OR last_seen_at < $1 OR created_at < $2
+        "#,
+        now - settings.idle_timeout(),
+        now - settings.absolute_timeout(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete expired sessions.")?
+    .rows_affected();
+    let password_resets = sqlx::query!("DELETE FROM password_resets WHERE expires_at < $1", now)
+        .execute(pool)
+        .await
+        .context("Failed to delete expired password resets.")?
+        .rows_affected();
+    let magic_links = sqlx::query!("DELETE FROM magic_links WHERE expires_at < $1", now)
+        .execute(pool)
+        .await
+        .context("Failed to delete expired magic links.")?
+        .rows_affected();
+    let setup_tokens = sqlx::query!("DELETE FROM setup_tokens WHERE expires_at < $1", now)
+        .execute(pool)
+        .await
+        .context("Failed to delete expired setup tokens.")?
+        .rows_affected();
+    Ok(PrunedRecords { sessions, password_resets, magic_links, setup_tokens })
+}
@@ -0,0 +1,55 @@
+// Passkeys are created and used by the browser through `navigator.credentials`,
+// which deals in bytes where our endpoints deal in base64url.
+function base64urlToBytes(value) {
+    const base64 = value.replace(/-/g, "+").replace(/_/g, "/");
+    const padded = base64 + "=".repeat((4 - base64.length % 4) % 4);
+    return Uint8Array.from(atob(padded), c => c.charCodeAt(0));
+}
+
+function bytesToBase64url(buffer) {
+    const binary = String.fromCharCode(...new Uint8Array(buffer));
+    return btoa(binary).replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+}
+
+// Our endpoints answer with where to go next, flash messages included
+async function postJson(url, body) {
+    const csrfToken = document.querySelector("input[name=csrf_token]").value;
+    const response = await fetch(url, {
+        method: "POST",
+        headers: { "Content-Type": "application/json", "X-CSRF-Token": csrfToken },
+        body: JSON.stringify(body),
+    });
+    return response.json();
+}
+
+function showPasskeyError(message) {
+    document.getElementById("passkey-error").textContent = message;
+}
+
+async function addPasskey(name) {
+    const options = await postJson("/admin/passkeys/options", {});
+    options.challenge = base64urlToBytes(options.challenge);
+    options.user.id = base64urlToBytes(options.user.id);
+    options.excludeCredentials.forEach(c => c.id = base64urlToBytes(c.id));
+    const credential = await navigator.credentials.create({ publicKey: options });
+    const result = await postJson("/admin/passkeys", {
+        name: name,
+        client_data_json: bytesToBase64url(credential.response.clientDataJSON),
+        attestation_object: bytesToBase64url(credential.response.attestationObject),
+    });
+    window.location = result.redirect;
+}
+
+async function logInWithPasskey() {
+    const options = await postJson("/login/passkey/options", {});
+    options.challenge = base64urlToBytes(options.challenge);
+    options.allowCredentials.forEach(c => c.id = base64urlToBytes(c.id));
+    const credential = await navigator.credentials.get({ publicKey: options });
+    const result = await postJson("/login/passkey", {
+        credential_id: bytesToBase64url(credential.rawId),
+        client_data_json: bytesToBase64url(credential.response.clientDataJSON),
+        authenticator_data: bytesToBase64url(credential.response.authenticatorData),
+        signature: bytesToBase64url(credential.response.signature),
+    });
+    window.location = result.redirect;
+}
@@ -0,0 +1,168 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::authentication::Role;
+
+// Invite `email` as the test user and return the link sent to them.
+async fn invite(app: &TestApp, email: &str, role: &str) -> reqwest::Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin_invites("", &serde_json::json!({"email": email, "role": role}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/invites");
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], email);
+    app.get_confirmation_links(&email_request).html
+}
+
+async fn accept(app: &TestApp, invite_link: &reqwest::Url, username: &str, password: &str) -> reqwest::Response {
+    let invite_token = invite_link
+        .query_pairs()
+        .find(|(key, _)| key == "invite_token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap();
+    app.api_client
+        .post(format!("{}/invites/accept", &app.address))
+        .form(&serde_json::json!({
+            "invite_token": invite_token,
+            "username": username,
+            "password": password,
+            "password_check": password,
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn invitees_can_set_up_their_account_and_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let invite_link = invite(&app, "ursula@example.com", "editor").await;
+    app.post_logout().await;
+    let password = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Open the link
+    let response = app.api_client.get(invite_link.clone()).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("ursula@example.com"));
+
+    // Act - Part 2 - Set up the account
+    let response = accept(&app, &invite_link, "ursula", &password).await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 3 - Log in
+    let response = app
+        .post_login(&serde_json::json!({"username": "ursula", "password": &password}))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let saved = sqlx::query!(r#"SELECT role AS "role: Role" FROM users WHERE username = 'ursula'"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.role, Role::Editor);
+}
+
+#[tokio::test]
+async fn invite_links_can_only_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let invite_link = invite(&app, "ursula@example.com", "editor").await;
+    accept(&app, &invite_link, "ursula", &Uuid::new_v4().to_string()).await;
+
+    // Act
+    let page = app.api_client.get(invite_link.clone()).send().await.unwrap();
+    let response = accept(&app, &invite_link, "another-ursula", &Uuid::new_v4().to_string()).await;
+
+    // Assert
+    assert_eq!(page.status().as_u16(), 401);
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn revoked_invites_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let invite_link = invite(&app, "ursula@example.com", "editor").await;
+    assert!(app.get_admin_invites_html().await.contains("ursula@example.com"));
+    let invite_id = sqlx::query!("SELECT invite_id FROM admin_invites")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .invite_id;
+
+    // Act
+    let response = app
+        .post_admin_invites(&format!("/{}/revoke", invite_id), &serde_json::json!({}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/invites");
+
+    // Assert
+    assert!(!app.get_admin_invites_html().await.contains("ursula@example.com"));
+    let response = accept(&app, &invite_link, "ursula", &Uuid::new_v4().to_string()).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn expired_invites_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let invite_link = invite(&app, "ursula@example.com", "editor").await;
+    sqlx::query!("UPDATE admin_invites SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = accept(&app, &invite_link, "ursula", &Uuid::new_v4().to_string()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn invitees_must_follow_the_password_policy() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let invite_link = invite(&app, "ursula@example.com", "editor").await;
+
+    // Act
+    let response = accept(&app, &invite_link, "ursula", "too-short").await;
+
+    // Assert
+    assert_is_redirect_to(&response, invite_link.as_str().trim_start_matches(&app.address));
+    let html_page = app.api_client.get(invite_link).send().await.unwrap().text().await.unwrap();
+    assert!(html_page.contains("<p><i>The new password must be at least 12 characters long.</i></p>"));
+}
+
+#[tokio::test]
+async fn only_owners_can_invite() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = app.add_user(Role::Editor).await;
+    app.login_as(&editor).await;
+
+    // Act
+    let response = app
+        .post_admin_invites("", &serde_json::json!({"email": "ursula@example.com", "role": "owner"}))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
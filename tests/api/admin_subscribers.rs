@@ -0,0 +1,120 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::authentication::Role;
+
+async fn create_pending_subscriber(app: &TestApp) -> Uuid {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the new subscriber.")
+        .id
+}
+
+async fn owner_pages_are_forbidden(app: &TestApp, role: Role) {
+    assert_eq!(app.get_admin_users().await.status().as_u16(), 403, "{} could see the users page.", role);
+    assert_eq!(app.get_admin_lockouts().await.status().as_u16(), 403, "{} could see the lockouts.", role);
+    assert_eq!(app.get_admin_audit_log("").await.status().as_u16(), 403, "{} could see the audit log.", role);
+}
+
+#[tokio::test]
+async fn analysts_can_view_reports_but_not_manage_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = create_pending_subscriber(&app).await;
+    let analyst = app.add_user(Role::Analyst).await;
+    app.login_as(&analyst).await;
+
+    // Act
+    let reports = app.get_admin_reports().await;
+    let subscribers = app.get_admin_subscribers().await;
+    let status_change = app.post_admin_subscriber_status(subscriber_id, "deleted").await;
+
+    // Assert
+    assert_eq!(reports.status().as_u16(), 200);
+    assert!(reports.text().await.unwrap().contains("<tr><td>pending</td><td>1</td></tr>"));
+    assert_eq!(subscribers.status().as_u16(), 403);
+    assert_eq!(status_change.status().as_u16(), 403);
+    owner_pages_are_forbidden(&app, Role::Analyst).await;
+}
+
+#[tokio::test]
+async fn support_users_can_manage_subscribers_but_not_view_reports() {
+    // Arrange
+    let app = spawn_app().await;
+    create_pending_subscriber(&app).await;
+    let support = app.add_user(Role::Support).await;
+    app.login_as(&support).await;
+
+    // Act
+    let subscribers = app.get_admin_subscribers().await;
+    let reports = app.get_admin_reports().await;
+
+    // Assert
+    assert_eq!(subscribers.status().as_u16(), 200);
+    assert!(subscribers.text().await.unwrap().contains("ursula_le_guin@gmail.com"));
+    assert_eq!(reports.status().as_u16(), 403);
+    owner_pages_are_forbidden(&app, Role::Support).await;
+}
+
+#[tokio::test]
+async fn editors_can_neither_view_reports_nor_manage_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = app.add_user(Role::Editor).await;
+    app.login_as(&editor).await;
+
+    // Act
+    let reports = app.get_admin_reports().await;
+    let subscribers = app.get_admin_subscribers().await;
+
+    // Assert
+    assert_eq!(reports.status().as_u16(), 403);
+    assert_eq!(subscribers.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn support_users_change_the_status_of_subscribers_within_legal_transitions() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = create_pending_subscriber(&app).await;
+    let support = app.add_user(Role::Support).await;
+    app.login_as(&support).await;
+
+    // Act - Part 1 - Delete the subscriber
+    let response = app.post_admin_subscriber_status(subscriber_id, "deleted").await;
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let html_page = app.get_admin_subscribers().await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>The subscriber is now deleted.</i></p>"));
+
+    // Act - Part 2 - Deleted subscribers cannot come back
+    let response = app.post_admin_subscriber_status(subscriber_id, "confirmed").await;
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let html_page = app.get_admin_subscribers().await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>A subscription cannot go from deleted to confirmed.</i></p>"));
+
+    // Assert
+    let saved = sqlx::query!(r#"SELECT status::text AS "status!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "deleted");
+    let entry = sqlx::query!(
+        "SELECT actor_id, diff FROM audit_log WHERE action = 'subscriber_status_changed'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(entry.actor_id, Some(support.user_id));
+    assert_eq!(entry.diff["status"]["to"], "deleted");
+}
@@ -0,0 +1,199 @@
+use crate::helpers::{assert_is_redirect_to, csrf_token_of, spawn_app};
+use uuid::Uuid;
+use zero2prod::authentication::Role;
+use zero2prod::csrf::CSRF_HEADER;
+
+#[tokio::test]
+async fn only_owners_can_manage_users() {
+    // Arrange
+    let app = spawn_app().await;
+    for role in [Role::Editor, Role::Analyst, Role::Support] {
+        let user = app.add_user(role).await;
+        app.login_as(&user).await;
+
+        // Act
+        let page = app.get_admin_users().await;
+        let creation = app
+            .post_admin_users(
+                "",
+                &serde_json::json!({
+                    "username": "intruder",
+                    "password": Uuid::new_v4().to_string(),
+                    "role": "owner",
+                }),
+            )
+            .await;
+
+        // Assert
+        assert_eq!(page.status().as_u16(), 403, "{} could see the users page.", role);
+        assert_eq!(creation.status().as_u16(), 403, "{} could create a user.", role);
+        app.post_logout().await;
+    }
+}
+
+#[tokio::test]
+async fn owners_can_add_users_who_can_then_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let password = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Add the user
+    let response = app
+        .post_admin_users(
+            "",
+            &serde_json::json!({
+                "username": "ursula",
+                "password": &password,
+                "role": "editor",
+            }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("<p><i>ursula has been added as editor.</i></p>"));
+    let entry = sqlx::query!("SELECT actor_id, target FROM audit_log WHERE action = 'user_created'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(entry.actor_id, Some(app.test_user.user_id));
+    assert_eq!(entry.target.as_deref(), Some("ursula"));
+
+    // Act - Part 3 - Log in as the new user
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({"username": "ursula", "password": &password}))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn new_users_must_follow_the_password_policy() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    // Act
+    app.post_admin_users(
+        "",
+        &serde_json::json!({"username": "ursula", "password": "too-short", "role": "editor"}),
+    )
+    .await;
+
+    // Assert
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("<p><i>The new password must be at least 12 characters long.</i></p>"));
+    let saved = sqlx::query!("SELECT user_id FROM users WHERE username = 'ursula'")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn owners_can_change_the_role_of_other_users() {
+    // Arrange
+    let app = spawn_app().await;
+    let analyst = app.add_user(Role::Analyst).await;
+    app.login_as(&app.test_user).await;
+
+    // Act
+    let response = app
+        .post_admin_users(
+            &format!("/{}/role", analyst.user_id),
+            &serde_json::json!({"role": "owner"}),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    // Assert
+    app.post_logout().await;
+    app.login_as(&analyst).await;
+    assert_eq!(app.get_admin_users().await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn owners_cannot_change_their_own_role() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    // Act
+    app.post_admin_users(
+        &format!("/{}/role", app.test_user.user_id),
+        &serde_json::json!({"role": "analyst"}),
+    )
+    .await;
+
+    // Assert
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("<p><i>You cannot change your own role.</i></p>"));
+}
+
+#[tokio::test]
+async fn disabled_users_cannot_log_in_and_lose_their_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = app.add_user(Role::Editor).await;
+    // A separate client, so that the editor's session survives the owner logging in
+    let editor_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    editor_client
+        .post(format!("{}/login", &app.address))
+        .header(CSRF_HEADER, csrf_token_of(&editor_client, &app.address).await)
+        .form(&serde_json::json!({"username": &editor.username, "password": &editor.password}))
+        .send()
+        .await
+        .unwrap();
+    app.login_as(&app.test_user).await;
+
+    // Act
+    let response = app
+        .post_admin_users(&format!("/{}/disable", editor.user_id), &serde_json::json!({}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    // Assert - Part 1 - The existing session is rejected
+    let response = editor_client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+    let html_page = editor_client
+        .get(format!("{}/login", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p><i>Your account has been disabled.</i></p>"));
+
+    // Assert - Part 2 - Logging in again fails
+    app.post_logout().await;
+    let response = app.login_as(&editor).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn owners_cannot_disable_themselves() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    // Act
+    app.post_admin_users(&format!("/{}/disable", app.test_user.user_id), &serde_json::json!({}))
+        .await;
+
+    // Assert
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("<p><i>You cannot disable your own account.</i></p>"));
+}
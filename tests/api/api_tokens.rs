@@ -0,0 +1,248 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::authentication::Role;
+use zero2prod::configuration::get_configuration;
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+// Create a token as the logged in user and return it
+async fn create_token(app: &TestApp, form: &[(&str, &str)]) -> String {
+    let response = app.post_admin_api_tokens("", form).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    html_page
+        .split(r#"<code id="api-token">"#)
+        .nth(1)
+        .and_then(|rest| rest.split("</code>").next())
+        .expect("The token is not shown after creating it")
+        .to_string()
+}
+
+async fn create_publishing_token(app: &TestApp) -> String {
+    app.login_as(&app.test_user).await;
+    create_token(app, &[("name", "CI"), ("scope", "newsletter:publish")]).await
+}
+
+async fn mock_no_emails(app: &TestApp) {
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn an_api_token_can_publish_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    mock_no_emails(&app).await;
+    let token = create_publishing_token(&app).await;
+
+    // Act
+    let response = app.post_newsletters_with_token(&token, newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let last_used_at = sqlx::query!("SELECT last_used_at FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .last_used_at;
+    assert!(last_used_at.is_some());
+}
+
+#[tokio::test]
+async fn api_tokens_are_stored_hashed_and_only_shown_once() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let token = create_publishing_token(&app).await;
+
+    // Assert
+    let token_hash = sqlx::query!("SELECT token_hash FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .token_hash;
+    assert_ne!(token_hash, token);
+    let html_page = app.get_admin_api_tokens_html().await;
+    assert!(html_page.contains("<td>CI</td>"));
+    assert!(!html_page.contains(&token));
+}
+
+#[tokio::test]
+async fn revoked_tokens_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    mock_no_emails(&app).await;
+    let token = create_publishing_token(&app).await;
+    let token_id = sqlx::query!("SELECT token_id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .token_id;
+
+    // Act
+    let response = app
+        .post_admin_api_tokens(&format!("/{}/revoke", token_id), &[("", "")])
+        .await;
+    assert_is_redirect_to(&response, "/admin/api_tokens");
+    let response = app.post_newsletters_with_token(&token, newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn expired_tokens_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let token = create_token(
+        &app,
+        &[("name", "CI"), ("scope", "newsletter:publish"), ("expires_in_days", "1")],
+    )
+    .await;
+    sqlx::query!("UPDATE api_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.post_newsletters_with_token(&token, newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn tokens_are_limited_by_their_owners_role() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = app.add_user(Role::Editor).await;
+    app.login_as(&editor).await;
+    let token = create_token(&app, &[("name", "CI"), ("scope", "newsletter:publish")]).await;
+    sqlx::query!("UPDATE users SET role = 'analyst' WHERE user_id = $1", editor.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.post_newsletters_with_token(&token, newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn analysts_cannot_create_publishing_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+    let analyst = app.add_user(Role::Analyst).await;
+    app.login_as(&analyst).await;
+
+    // Act
+    let response = app
+        .post_admin_api_tokens("", &[("name", "CI"), ("scope", "newsletter:publish")])
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/api_tokens");
+    let html_page = app.get_admin_api_tokens_html().await;
+    assert!(html_page.contains("<p><i>Your role does not allow the newsletter:publish scope.</i></p>"));
+}
+
+#[tokio::test]
+async fn users_cannot_revoke_someone_elses_token() {
+    // Arrange
+    let app = spawn_app().await;
+    mock_no_emails(&app).await;
+    let token = create_publishing_token(&app).await;
+    let token_id = sqlx::query!("SELECT token_id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .token_id;
+    app.post_logout().await;
+    let editor = app.add_user(Role::Editor).await;
+    app.login_as(&editor).await;
+
+    // Act
+    app.post_admin_api_tokens(&format!("/{}/revoke", token_id), &[("", "")]).await;
+
+    // Assert
+    let response = app.post_newsletters_with_token(&token, newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn basic_auth_can_be_switched_off() {
+    // Arrange
+    let app = spawn_app_with(|c| c.api.basic_auth_enabled = false).await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[test]
+fn basic_auth_is_disabled_by_default() {
+    let configuration = get_configuration().expect("Failed to read configuration.");
+    assert!(!configuration.api.basic_auth_enabled);
+}
+
+#[tokio::test]
+async fn basic_auth_is_refused_for_users_with_a_second_factor() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query!(
+        "UPDATE users SET totp_secret = 'JBSWY3DPEHPK3PXP' WHERE user_id = $1",
+        app.test_user.user_id,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn repeated_basic_auth_failures_end_in_a_lockout() {
+    // Arrange
+    let app = spawn_app_with(|c| c.login_protection.failures_before_lockout_per_username = 2).await;
+    for _ in 0..2 {
+        let response = app
+            .api_client
+            .post(format!("{}/newsletter", &app.address))
+            .basic_auth(&app.test_user.username, Some("wrong-password"))
+            .json(&newsletter_request_body())
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    // The login form is locked out too
+    let response = app.login_as(&app.test_user).await;
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,254 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::authentication::Role;
+
+struct Entry {
+    actor: Option<String>,
+    target: Option<String>,
+    user_agent: String,
+    diff: serde_json::Value,
+}
+
+// The entries recorded for `action`, oldest first
+async fn entries_for(app: &TestApp, action: &str) -> Vec<Entry> {
+    sqlx::query!(
+        r#"
+        SELECT u.username AS "actor?", a.target, a.user_agent, a.diff
+        FROM audit_log a
+        LEFT JOIN users u ON u.user_id = a.actor_id
+        WHERE a.action = $1
+        ORDER BY a.occurred_at
+        "#,
+        action,
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| Entry {
+        actor: r.actor,
+        target: r.target,
+        user_agent: r.user_agent,
+        diff: r.diff,
+    })
+    .collect()
+}
+
+async fn mock_emails(app: &TestApp) {
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn logins_are_audited() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "wrong password",
+    }))
+    .await;
+    app.login_as(&app.test_user).await;
+
+    // Assert
+    let failed = entries_for(&app, "login_failed").await;
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].actor, None);
+    assert_eq!(failed[0].target.as_deref(), Some(app.test_user.username.as_str()));
+    let succeeded = entries_for(&app, "login_succeeded").await;
+    assert_eq!(succeeded.len(), 1);
+    assert_eq!(succeeded[0].actor.as_deref(), Some(app.test_user.username.as_str()));
+    assert!(!succeeded[0].user_agent.is_empty());
+}
+
+#[tokio::test]
+async fn password_changes_are_audited() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    // Assert
+    let entries = entries_for(&app, "password_changed").await;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].actor.as_deref(), Some(app.test_user.username.as_str()));
+    // Nothing about the password itself
+    assert_eq!(entries[0].diff, serde_json::json!({}));
+}
+
+#[tokio::test]
+async fn publishing_is_audited() {
+    // Arrange
+    let app = spawn_app().await;
+    mock_emails(&app).await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Issue #1",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Assert
+    let entries = entries_for(&app, "newsletter_published").await;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].actor.as_deref(), Some(app.test_user.username.as_str()));
+    assert_eq!(entries[0].target.as_deref(), Some("Issue #1"));
+    assert_eq!(entries[0].diff, serde_json::json!({ "recipients": { "from": null, "to": 0 } }));
+}
+
+#[tokio::test]
+async fn subscribing_and_confirming_are_audited() {
+    // Arrange
+    let app = spawn_app().await;
+    mock_emails(&app).await;
+
+    // Act - Part 1 - Subscribe
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // Act - Part 2 - Confirm, twice
+    for _ in 0..2 {
+        reqwest::get(confirmation_links.html.clone())
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    // Assert
+    let added = entries_for(&app, "subscriber_added").await;
+    assert_eq!(added.len(), 1);
+    assert_eq!(added[0].actor, None);
+    assert_eq!(
+        added[0].diff,
+        serde_json::json!({
+            "email": { "from": null, "to": "ursula_le_guin@gmail.com" },
+            "name": { "from": null, "to": "le guin" },
+            "status": { "from": null, "to": "pending" },
+        })
+    );
+    let confirmed = entries_for(&app, "subscriber_confirmed").await;
+    assert_eq!(confirmed.len(), 1, "Confirming again changed nothing and must not be audited");
+    assert_eq!(confirmed[0].target, added[0].target);
+    assert_eq!(confirmed[0].diff, serde_json::json!({ "status": { "from": "pending", "to": "confirmed" } }));
+}
+
+#[tokio::test]
+async fn the_audit_log_is_append_only() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    // Act
+    let update = sqlx::query!("UPDATE audit_log SET action = 'nothing'")
+        .execute(&app.db_pool)
+        .await;
+    let delete = sqlx::query!("DELETE FROM audit_log").execute(&app.db_pool).await;
+
+    // Assert
+    assert!(update.is_err());
+    assert!(delete.is_err());
+    assert_eq!(entries_for(&app, "login_succeeded").await.len(), 1);
+}
+
+#[tokio::test]
+async fn only_owners_can_see_the_audit_log() {
+    // Arrange
+    let app = spawn_app().await;
+    for role in [Role::Editor, Role::Analyst, Role::Support] {
+        let user = app.add_user(role).await;
+        app.login_as(&user).await;
+
+        // Act
+        let page = app.get_admin_audit_log("").await;
+        let export = app.get_admin_audit_log("/export").await;
+
+        // Assert
+        assert_eq!(page.status().as_u16(), 403, "{} could see the audit log.", role);
+        assert_eq!(export.status().as_u16(), 403, "{} could export the audit log.", role);
+        app.post_logout().await;
+    }
+}
+
+#[tokio::test]
+async fn the_audit_log_can_be_filtered() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_user = app.add_user(Role::Editor).await;
+    app.login_as(&other_user).await;
+    app.post_logout().await;
+    app.login_as(&app.test_user).await;
+
+    // Act
+    let html_page = app
+        .get_admin_audit_log_html(&format!("?action=logged_out&actor={}", other_user.username))
+        .await;
+
+    // Assert
+    assert_eq!(html_page.matches("<td>logged_out</td>").count(), 1);
+    assert!(!html_page.contains("<td>login_succeeded</td>"));
+}
+
+#[tokio::test]
+async fn invalid_filters_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    for query in ["?action=dropped_tables", "?since=yesterday"] {
+        // Act
+        let response = app.get_admin_audit_log(query).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400, "{} was accepted", query);
+    }
+}
+
+#[tokio::test]
+async fn the_audit_log_can_be_exported_as_csv() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    // Act
+    let response = app.get_admin_audit_log("/export?action=login_succeeded").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "text/csv; charset=utf-8");
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let csv = response.text().await.unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "occurred_at,actor,action,target,ip,user_agent,request_id,diff");
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].contains(&format!(r#""{}","login_succeeded""#, app.test_user.username)));
+}
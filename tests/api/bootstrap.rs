@@ -0,0 +1,203 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+use secrecy::Secret;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use zero2prod::authentication::Role;
+
+// What the application would have logged, stored the way it stores it
+async fn store_setup_token(app: &TestApp) -> String {
+    let token = Uuid::new_v4().to_string();
+    sqlx::query!(
+        "INSERT INTO setup_tokens (token_hash, created_at, expires_at) VALUES ($1, now(), now() + interval '1 hour')",
+        format!("{:x}", Sha256::digest(token.as_bytes())),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    token
+}
+
+async fn role_of(app: &TestApp, username: &str) -> Option<Role> {
+    sqlx::query!(r#"SELECT role AS "role: Role" FROM users WHERE username = $1"#, username)
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap()
+        .map(|r| r.role)
+}
+
+#[tokio::test]
+async fn the_default_admin_is_not_seeded_anymore() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let role = role_of(&app, "admin").await;
+
+    // Assert
+    assert!(role.is_none());
+}
+
+#[tokio::test]
+async fn the_first_owner_can_be_created_from_the_settings() {
+    // Arrange
+    let password = Uuid::new_v4().to_string();
+    let app = spawn_app_with(|c| {
+        c.bootstrap.admin_username = Some("first-owner".into());
+        c.bootstrap.admin_password = Some(Secret::new(password.clone()));
+    })
+    .await;
+
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "first-owner",
+            "password": &password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    assert_eq!(role_of(&app, "first-owner").await, Some(Role::Owner));
+}
+
+#[tokio::test]
+async fn a_setup_link_creates_the_first_owner_once() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query!("DELETE FROM users").execute(&app.db_pool).await.unwrap();
+    let token = store_setup_token(&app).await;
+    let password = Uuid::new_v4().to_string();
+    let form = serde_json::json!({
+        "setup_token": &token,
+        "username": "first-owner",
+        "password": &password,
+        "password_check": &password,
+    });
+
+    // Act - Part 1 - Follow the link
+    let page = app
+        .api_client
+        .get(format!("{}/setup?setup_token={}", &app.address, token))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(page.status().as_u16(), 200);
+
+    // Act - Part 2 - Create the owner
+    let response = app.api_client.post(format!("{}/setup", &app.address)).form(&form).send().await.unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 3 - Try again
+    let again = app.api_client.post(format!("{}/setup", &app.address)).form(&form).send().await.unwrap();
+
+    // Assert
+    assert_eq!(again.status().as_u16(), 401);
+    assert_eq!(role_of(&app, "first-owner").await, Some(Role::Owner));
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "first-owner",
+            "password": &password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn setup_links_do_not_work_once_there_are_users() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = store_setup_token(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/setup?setup_token={}", &app.address, token))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn unknown_setup_links_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query!("DELETE FROM users").execute(&app.db_pool).await.unwrap();
+    let password = Uuid::new_v4().to_string();
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/setup", &app.address))
+        .form(&serde_json::json!({
+            "setup_token": Uuid::new_v4().to_string(),
+            "username": "first-owner",
+            "password": &password,
+            "password_check": &password,
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(role_of(&app, "first-owner").await.is_none());
+}
+
+#[tokio::test]
+async fn users_with_the_default_password_must_change_it_first() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query!("UPDATE users SET password_change_required = true WHERE user_id = $1", app.test_user.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.login_as(&app.test_user).await;
+
+    // Act - Part 1 - Anything but the password page is off limits
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/admin/password");
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("You must change it before continuing."));
+
+    // Act - Part 2 - Change the password
+    let new_password = Uuid::new_v4().to_string();
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn users_with_the_default_password_cannot_publish_with_it() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query!("UPDATE users SET password_change_required = true WHERE user_id = $1", app.test_user.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
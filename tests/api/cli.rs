@@ -0,0 +1,163 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use chrono::Utc;
+use secrecy::Secret;
+use uuid::Uuid;
+use zero2prod::authentication::Role;
+use zero2prod::cli::{create_user, list_subscribers, reset_password};
+use zero2prod::configuration::{get_configuration, PasswordHashingSettings};
+use zero2prod::domain::SubscriptionStatus;
+use zero2prod::worker::prune_expired_records;
+
+fn hashing() -> PasswordHashingSettings {
+    get_configuration().expect("Failed to read configuration.").password_hashing
+}
+
+async fn insert_subscriber(app: &TestApp, email: &str, status: SubscriptionStatus) {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, 'Ursula', now(), $3)
+        "#,
+        Uuid::new_v4(),
+        email,
+        status as SubscriptionStatus,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn users_created_from_the_command_line_can_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+    let password = Uuid::new_v4().to_string();
+
+    // Act
+    create_user(&app.db_pool, &hashing(), "cli-editor", None, Role::Editor, Secret::new(password.clone()))
+        .await
+        .unwrap();
+
+    // Assert
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "cli-editor",
+            "password": &password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn users_created_from_the_command_line_are_audited() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    create_user(&app.db_pool, &hashing(), "cli-audited", None, Role::Editor, Secret::new(Uuid::new_v4().to_string()))
+        .await
+        .unwrap();
+
+    // Assert
+    let entry = sqlx::query!(
+        "SELECT actor_id, target, ip, diff FROM audit_log WHERE action = 'user_created' AND target = 'cli-audited'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(entry.actor_id, None);
+    assert_eq!(entry.ip, "internal");
+    assert_eq!(entry.diff["role"]["to"], "editor");
+}
+
+#[tokio::test]
+async fn the_command_line_refuses_taken_usernames() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let result = create_user(
+        &app.db_pool,
+        &hashing(),
+        &app.test_user.username,
+        None,
+        Role::Owner,
+        Secret::new(Uuid::new_v4().to_string()),
+    )
+    .await;
+
+    // Assert
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn resetting_a_password_from_the_command_line_logs_the_user_out() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act
+    reset_password(&app.db_pool, &hashing(), &app.test_user.username, Secret::new(new_password.clone()))
+        .await
+        .unwrap();
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn subscribers_can_be_listed_by_status() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_subscriber(&app, "pending@example.com", SubscriptionStatus::Pending).await;
+    insert_subscriber(&app, "confirmed@example.com", SubscriptionStatus::Confirmed).await;
+
+    // Act
+    let all = list_subscribers(&app.db_pool, None).await.unwrap();
+    let confirmed = list_subscribers(&app.db_pool, Some(SubscriptionStatus::Confirmed)).await.unwrap();
+
+    // Assert
+    assert_eq!(all.len(), 2);
+    assert_eq!(confirmed.len(), 1);
+    assert_eq!(confirmed[0].email, "confirmed@example.com");
+}
+
+#[tokio::test]
+async fn the_worker_prunes_dead_sessions_and_expired_links() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let other_browser = app.login_from_other_browser(&app.test_user, "Other browser/1.0").await;
+    sqlx::query!("UPDATE user_sessions SET revoked_at = now() WHERE user_agent = 'Other browser/1.0'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!(
+        "INSERT INTO setup_tokens (token_hash, created_at, expires_at) VALUES ('expired', $1, $1)",
+        Utc::now() - chrono::Duration::hours(1),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let settings = get_configuration().unwrap().session;
+
+    // Act
+    let pruned = prune_expired_records(&app.db_pool, &settings).await.unwrap();
+
+    // Assert
+    assert_eq!(pruned.sessions, 1);
+    assert_eq!(pruned.setup_tokens, 1);
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = other_browser.get(format!("{}/admin/dashboard", &app.address)).send().await.unwrap();
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,152 @@
+use crate::helpers::{assert_is_redirect_to, csrf_token_of, spawn_app, spawn_app_with, TestApp};
+use zero2prod::csrf::CSRF_HEADER;
+
+const REJECTED: &str = "This form has expired or was submitted from another site.";
+
+async fn post_login_with(app: &TestApp, form: &[(&str, &str)], headers: &[(&str, &str)]) -> reqwest::Response {
+    let mut request = app.api_client.post(format!("{}/login", &app.address)).form(form);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn every_admin_form_carries_a_csrf_token() {
+    // Arrange
+    let app = spawn_app_with(|c| c.magic_link.enabled = true).await;
+    app.login_as(&app.test_user).await;
+
+    for page in ["/login", "/login/magic_link", "/admin/dashboard", "/admin/password", "/admin/users", "/admin/invites", "/admin/api_tokens", "/admin/sessions", "/admin/two_factor", "/admin/passkeys"] {
+        // Act
+        let html_page = app
+            .api_client
+            .get(format!("{}{}", &app.address, page))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap();
+
+        // Assert
+        let forms = html_page.matches("<form").count();
+        assert!(forms > 0, "{} has no form", page);
+        assert_eq!(forms, html_page.matches(r#"name="csrf_token""#).count(), "{} has a form without a token", page);
+    }
+}
+
+#[tokio::test]
+async fn logging_in_with_the_token_in_the_form_works() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = app.csrf_token().await;
+
+    // Act
+    let response = post_login_with(
+        &app,
+        &[("username", &app.test_user.username), ("password", &app.test_user.password), ("csrf_token", &token)],
+        &[],
+    )
+    .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn logging_in_without_a_token_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.csrf_token().await;
+
+    // Act
+    let response = post_login_with(
+        &app,
+        &[("username", &app.test_user.username), ("password", &app.test_user.password)],
+        &[],
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(response.text().await.unwrap().contains(REJECTED));
+}
+
+#[tokio::test]
+async fn logging_in_with_another_sessions_token_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.csrf_token().await;
+    let other_browser = reqwest::Client::builder().cookie_store(true).build().unwrap();
+    let other_token = csrf_token_of(&other_browser, &app.address).await;
+
+    // Act
+    let response = post_login_with(
+        &app,
+        &[("username", &app.test_user.username), ("password", &app.test_user.password), ("csrf_token", &other_token)],
+        &[],
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn admin_forms_without_a_token_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn requests_from_another_origin_are_rejected_even_with_a_token() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = app.csrf_token().await;
+
+    for (header, value) in [("Origin", "https://evil.example.com"), ("Referer", "https://evil.example.com/form"), ("Origin", "null")] {
+        // Act
+        let response = post_login_with(
+            &app,
+            &[("username", &app.test_user.username), ("password", &app.test_user.password)],
+            &[(CSRF_HEADER, &token), (header, value)],
+        )
+        .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 403, "{}: {} was let through", header, value);
+    }
+}
+
+#[tokio::test]
+async fn requests_from_our_own_origin_are_accepted() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = app.csrf_token().await;
+
+    // Act
+    let response = post_login_with(
+        &app,
+        &[("username", &app.test_user.username), ("password", &app.test_user.password)],
+        &[(CSRF_HEADER, &token), ("Origin", &app.address)],
+    )
+    .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
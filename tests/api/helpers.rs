@@ -1,18 +1,26 @@
 use sqlx::{PgPool, PgConnection, Connection, Executor};
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use zero2prod::configuration::{get_configuration, DatabaseSettings, Settings};
 use zero2prod::startup::{get_connection_pool};
 use zero2prod::startup::Application;
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::authentication::Role;
+use zero2prod::csrf::CSRF_HEADER;
+use zero2prod::migrations::migrate;
 use uuid::Uuid;
 use once_cell::sync::Lazy;
-use wiremock::MockServer;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
 use argon2::password_hash::SaltString;
-use argon2::{Argon2, PasswordHasher};
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+
+// An origin allowed to call the subscriptions API from a browser
+pub const MARKETING_ORIGIN: &str = "https://marketing.example.com";
 
 pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
-    pub password: String
+    pub password: String,
+    pub role: Role,
 }
 
 pub struct ConfirmationLinks {
@@ -33,28 +41,39 @@ pub struct TestApp {
 
 impl TestUser {
     pub fn generate() -> Self {
-        return Self {
+        Self::generate_with_role(Role::Owner)
+    }
+
+    pub fn generate_with_role(role: Role) -> Self {
+        Self {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
-        };
+            role,
+        }
     }
-    
+
     async fn insert(&self, pool: &PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
 
-        let password_hash = Argon2::default()
+        // Match the parameters used in production
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(15000, 2, 1, None).unwrap(),
+        )
             .hash_password(self.password.as_bytes(), &salt)
             .unwrap()
             .to_string();
 
         sqlx::query!("
-            INSERT INTO users (user_id, username, password_hash)
-            VALUES ($1, $2, $3)
+            INSERT INTO users (user_id, username, password_hash, role)
+            VALUES ($1, $2, $3, $4)
             ",
             self.user_id,
             self.username,
-            password_hash
+            password_hash,
+            self.role as Role,
         )
         .execute(pool)
         .await
@@ -64,6 +83,248 @@ impl TestUser {
 }
 
 impl TestApp {
+    // Store another admin user next to `test_user`
+    pub async fn add_user(&self, role: Role) -> TestUser {
+        let user = TestUser::generate_with_role(role);
+        user.insert(&self.db_pool).await;
+        user
+    }
+
+    pub async fn login_as(&self, user: &TestUser) -> reqwest::Response {
+        self.post_login(&serde_json::json!({
+            "username": &user.username,
+            "password": &user.password
+        }))
+        .await
+    }
+
+    // The CSRF token of our session, to send along with anything we post to `/admin` or `/login`
+    pub async fn csrf_token(&self) -> String {
+        csrf_token_of(&self.api_client, &self.address).await
+    }
+
+    pub async fn get_admin_users(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/users", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_users_html(&self) -> String {
+        self.get_admin_users().await.text().await.unwrap()
+    }
+
+    pub async fn get_admin_invites_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/invites", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_admin_invites<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/invites{}", &self.address, path))
+            .header(CSRF_HEADER, self.csrf_token().await)
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_two_factor_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/two_factor", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_two_factor<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/two_factor{}", &self.address, path))
+            .header(CSRF_HEADER, self.csrf_token().await)
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login_two_factor(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/two_factor", &self.address))
+            .header(CSRF_HEADER, self.csrf_token().await)
+            .form(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_lockouts(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/lockouts", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_lockouts_html(&self) -> String {
+        self.get_admin_lockouts().await.text().await.unwrap()
+    }
+
+    pub async fn post_admin_lockouts_unlock<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/lockouts/unlock", &self.address))
+            .header(CSRF_HEADER, self.csrf_token().await)
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_sessions_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/sessions", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_admin_sessions(&self, path: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/sessions{}", &self.address, path))
+            .header(CSRF_HEADER, self.csrf_token().await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Log `user` in from a browser of its own, e.g. another device
+    pub async fn login_from_other_browser(&self, user: &TestUser, user_agent: &str) -> reqwest::Client {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_store(true)
+            .user_agent(user_agent)
+            .build()
+            .unwrap();
+        let response = client
+            .post(format!("{}/login", &self.address))
+            .header(CSRF_HEADER, csrf_token_of(&client, &self.address).await)
+            .form(&serde_json::json!({
+                "username": &user.username,
+                "password": &user.password
+            }))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_is_redirect_to(&response, "/admin/dashboard");
+        client
+    }
+
+    pub async fn get_admin_audit_log(&self, path_and_query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/audit_log{}", &self.address, path_and_query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_audit_log_html(&self, path_and_query: &str) -> String {
+        self.get_admin_audit_log(path_and_query).await.text().await.unwrap()
+    }
+
+    pub async fn get_admin_reports(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/reports", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_subscribers(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_admin_subscriber_status(&self, subscriber_id: Uuid, status: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/subscribers/{}/status", &self.address, subscriber_id))
+            .header(CSRF_HEADER, self.csrf_token().await)
+            .form(&serde_json::json!({ "status": status }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_api_tokens_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/api_tokens", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_admin_api_tokens<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize + ?Sized,
+    {
+        self.api_client
+            .post(format!("{}/admin/api_tokens{}", &self.address, path))
+            .header(CSRF_HEADER, self.csrf_token().await)
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters_with_token(&self, token: &str, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletter", &self.address))
+            .bearer_auth(token)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_admin_users<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/users{}", &self.address, path))
+            .header(CSRF_HEADER, self.csrf_token().await)
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         return self.api_client
             .post(&format!("{}/subscriptions", &self.address))
@@ -74,6 +335,33 @@ impl TestApp {
             .expect("Failed to execute request.");
     }
 
+    pub async fn post_subscriptions_json(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_subscription_challenge(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/subscriptions/challenge", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_change_email(&self, body: String) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions/change_email", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         return self.api_client
             .post(&format!("{}/newsletter", &self.address))
@@ -116,12 +404,9 @@ impl TestApp {
     }
 
     pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response where Body: serde::Serialize {
-        return reqwest::Client::builder()
-            // disabling redirect follow 
-            .redirect(reqwest::redirect::Policy::none())
-            .build()
-            .unwrap()
+        return self.api_client
             .post(&format!("{}/login", &self.address))
+            .header(CSRF_HEADER, self.csrf_token().await)
             // This reqwest method makes sure that the body is URL-encoded
             // and the Content-Type header is set accordingly.
             .form(body)
@@ -141,6 +426,61 @@ impl TestApp {
             .unwrap();
     }
 
+    pub async fn post_password_reset(&self, username: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/password_reset", &self.address))
+            .form(&serde_json::json!({ "username": username }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_password_reset_confirm<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/password_reset/confirm", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_magic_link(&self, username_or_email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/magic_link", &self.address))
+            .header(CSRF_HEADER, self.csrf_token().await)
+            .form(&serde_json::json!({ "username_or_email": username_or_email }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // As the scripts of our pages do, e.g. to use a passkey
+    pub async fn post_json<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}{}", &self.address, path))
+            .header(CSRF_HEADER, self.csrf_token().await)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_magic_link_confirm(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/magic_link/confirm", &self.address))
+            .header(CSRF_HEADER, self.csrf_token().await)
+            .form(&serde_json::json!({ "token": token }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.api_client
             .get(&format!("{}/admin/dashboard", &self.address))
@@ -167,6 +507,7 @@ impl TestApp {
     {
         self.api_client
             .post(&format!("{}/admin/password", &self.address))
+            .header(CSRF_HEADER, self.csrf_token().await)
             .form(body)
             .send()
             .await
@@ -177,6 +518,15 @@ impl TestApp {
         self.get_change_password().await.text().await.unwrap()
     }
 
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/admin/logout", &self.address))
+            .header(CSRF_HEADER, self.csrf_token().await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
 }
 
 // Ensure that the `tracing` stack is only initialised once using `once_cell`
@@ -199,6 +549,11 @@ static TRACING: Lazy<()> = Lazy::new(|| {
 
 // Launch our application in the background
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+// Launch our application with some settings tweaked on top of the test defaults
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     // The first time `initialize` is invoked the code in `TRACING` is executed.
     // All other invocations will instead skip execution.
     Lazy::force(&TRACING);
@@ -215,6 +570,22 @@ pub async fn spawn_app() -> TestApp {
         c.application.port = 0;
         // Use the mock server as email API
         c.email_client.base_url = email_server.uri();
+        c.application.cors_allowed_origins = vec![MARKETING_ORIGIN.into()];
+        // Act as the proxy in front of the application, so that tests can pick
+        // their client IP address through `X-Forwarded-For`
+        c.application.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+        // Most tests publish newsletters with the test user's password
+        c.api.basic_auth_enabled = true;
+        // Redis is shared by all test cases, which all connect from 127.0.0.1:
+        // keep rate limits out of the way unless a test asks for them
+        c.sign_up_protection.max_attempts_per_ip = u64::MAX;
+        c.sign_up_protection.max_attempts_per_email = u64::MAX;
+        c.login_protection.failures_before_delay = u64::MAX;
+        c.login_protection.failures_before_lockout_per_username = u64::MAX;
+        c.login_protection.failures_before_lockout_per_ip = u64::MAX;
+        c.login_protection.max_link_requests_per_account = u64::MAX;
+        c.login_protection.max_link_requests_per_ip = u64::MAX;
+        configure(&mut c);
         c
     };
 
@@ -250,8 +621,25 @@ pub async fn spawn_app() -> TestApp {
     return test_app;
 }
 
+// A made-up client address, for `X-Forwarded-For`, so that tests don't share
+// the counters of rate limits and lockouts
+pub fn unique_ip() -> String {
+    let bytes = Uuid::new_v4();
+    let bytes = bytes.as_bytes();
+    format!("10.{}.{}.{}", bytes[0], bytes[1], bytes[2])
+}
+
 pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
-    // Create database
+    let connection_pool = create_database(config).await;
+    migrate(&connection_pool)
+        .await
+        .expect("Failed to migrate the database.");
+
+    return connection_pool;
+}
+
+// An empty database, without any migrations applied
+pub async fn create_database(config: &DatabaseSettings) -> PgPool {
     let mut connection = PgConnection::connect_with(&config.without_db())
         .await
         .expect("Failed to connect to Postgres.");
@@ -261,16 +649,27 @@ pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
         .await
         .expect("Failed to create database.");
     
-    // Migrate database
-    let connection_pool = PgPool::connect_with(config.with_db())
-        .await
-        .expect("Failed to connect to Postgres.");
-    sqlx::migrate!("./migrations")
-        .run(&connection_pool)
+    PgPool::connect_with(config.with_db())
         .await
-        .expect("Failed to migrate the database.");
+        .expect("Failed to connect to Postgres.")
+}
 
-    return connection_pool;
+// Read the token from the login form, as a browser would have it
+pub async fn csrf_token_of(client: &reqwest::Client, address: &str) -> String {
+    let html_page = client
+        .get(format!("{}/login", address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap();
+    html_page
+        .split(r#"name="csrf_token" value=""#)
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .expect("The login form has no CSRF token")
+        .to_string()
 }
 
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str){
@@ -278,3 +677,23 @@ pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str){
     assert_eq!(response.headers().get("Location").unwrap(), location);
 }
 
+// Accept every email sent through the mock email API
+pub async fn mock_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+// Some emails are sent in the background, give them a moment
+pub async fn wait_for_emails(app: &TestApp, count: usize) -> Vec<wiremock::Request> {
+    for _ in 0..50 {
+        let requests = app.email_server.received_requests().await.unwrap();
+        if requests.len() >= count {
+            return requests;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    panic!("Expected {} emails to be sent", count);
+}
@@ -1,4 +1,5 @@
-use crate::helpers::{spawn_app, assert_is_redirect_to};
+use crate::helpers::{spawn_app, spawn_app_with, assert_is_redirect_to, TestApp};
+use std::time::{Duration, Instant};
 
 
 #[tokio::test]
@@ -40,4 +41,71 @@ async fn redirect_to_admin_dashboard_after_login_success() {
     // Act - Part 2 - Follow the redirect
     let html_page = app.get_admin_dashboard_html().await;
     assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
-}
\ No newline at end of file
+}
+// Median time to get a response to a login attempt with `username`.
+async fn median_login_time(app: &TestApp, username: &str) -> Duration {
+    let mut timings = Vec::new();
+    for _ in 0..5 {
+        let login_body = serde_json::json!({
+            "username": username,
+            "password": "wrong-password"
+        });
+        let start = Instant::now();
+        app.post_login(&login_body).await;
+        timings.push(start.elapsed());
+    }
+    timings.sort();
+    timings[timings.len() / 2]
+}
+
+#[tokio::test]
+async fn login_failures_take_as_long_for_unknown_and_known_usernames() {
+    // Arrange
+    let app = spawn_app().await;
+    // Warm up connections and the like
+    median_login_time(&app, &app.test_user.username).await;
+
+    // Act
+    let known = median_login_time(&app, &app.test_user.username).await;
+    let unknown = median_login_time(&app, "random-username").await;
+
+    // Assert
+    // Without the dummy hash, unknown usernames are answered orders of magnitude faster
+    let (slowest, fastest) = if known > unknown { (known, unknown) } else { (unknown, known) };
+    assert!(
+        slowest < fastest * 2,
+        "Known username: {:?}, unknown username: {:?}",
+        known,
+        unknown
+    );
+}
+
+#[tokio::test]
+async fn password_hashes_are_upgraded_to_the_configured_parameters_on_login() {
+    // Arrange
+    // The test user's hash uses m=15000,t=2,p=1
+    let app = spawn_app_with(|c| c.password_hashing.memory_cost_kib = 19456).await;
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+
+    // Act
+    let response = app.post_login(&login_body).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let saved = sqlx::query!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(saved.password_hash.starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+
+    // The upgraded hash still matches the password
+    app.post_logout().await;
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
@@ -0,0 +1,154 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app_with, unique_ip, TestApp};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use zero2prod::authentication::Role;
+use zero2prod::csrf::CSRF_HEADER;
+
+const LOCKED_OUT: &str = "<p><i>Too many failed login attempts, please try again later.</i></p>";
+
+// Log in as if from `ip`, so that tests don't share IP counters.
+async fn post_login_from(app: &TestApp, ip: &str, username: &str, password: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/login", &app.address))
+        .header(CSRF_HEADER, app.csrf_token().await)
+        .header("X-Forwarded-For", ip)
+        .form(&serde_json::json!({ "username": username, "password": password }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn fail_logins(app: &TestApp, ip: &str, username: &str, times: usize) {
+    for _ in 0..times {
+        post_login_from(app, ip, username, "wrong-password").await;
+    }
+}
+
+#[tokio::test]
+async fn a_username_is_locked_out_after_too_many_failures() {
+    // Arrange
+    let app = spawn_app_with(|c| c.login_protection.failures_before_lockout_per_username = 3).await;
+    fail_logins(&app, &unique_ip(), &app.test_user.username, 3).await;
+
+    // Act - The right password, from another IP
+    let response = post_login_from(&app, &unique_ip(), &app.test_user.username, &app.test_user.password).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(LOCKED_OUT));
+}
+
+#[tokio::test]
+async fn unknown_usernames_get_the_same_lockout() {
+    // Arrange
+    let app = spawn_app_with(|c| c.login_protection.failures_before_lockout_per_username = 3).await;
+    let username = Uuid::new_v4().to_string();
+    fail_logins(&app, &unique_ip(), &username, 3).await;
+
+    // Act
+    let response = post_login_from(&app, &unique_ip(), &username, "wrong-password").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(LOCKED_OUT));
+}
+
+#[tokio::test]
+async fn an_ip_is_locked_out_after_too_many_failures() {
+    // Arrange
+    let app = spawn_app_with(|c| c.login_protection.failures_before_lockout_per_ip = 3).await;
+    let ip = unique_ip();
+    for _ in 0..3 {
+        fail_logins(&app, &ip, &Uuid::new_v4().to_string(), 1).await;
+    }
+
+    // Act
+    let response = post_login_from(&app, &ip, &app.test_user.username, &app.test_user.password).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(LOCKED_OUT));
+}
+
+#[tokio::test]
+async fn repeated_failures_slow_logins_down() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.login_protection.failures_before_delay = 1;
+        c.login_protection.delay_step_milliseconds = 400;
+    })
+    .await;
+    let ip = unique_ip();
+    fail_logins(&app, &ip, &app.test_user.username, 3).await;
+
+    // Act
+    let start = Instant::now();
+    let response = post_login_from(&app, &ip, &app.test_user.username, &app.test_user.password).await;
+
+    // Assert - Two failures past the threshold
+    assert!(start.elapsed() >= Duration::from_millis(800));
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn owners_can_unlock_a_locked_out_user() {
+    // Arrange
+    let app = spawn_app_with(|c| c.login_protection.failures_before_lockout_per_username = 3).await;
+    let editor = app.add_user(Role::Editor).await;
+    fail_logins(&app, &unique_ip(), &editor.username, 3).await;
+    app.login_as(&app.test_user).await;
+
+    // Act - Part 1 - The owner sees the lockout
+    let html_page = app.get_admin_lockouts_html().await;
+    assert!(html_page.contains(&format!("<td>{}</td>", editor.username.to_lowercase())));
+
+    // Act - Part 2 - And lifts it
+    let response = app
+        .post_admin_lockouts_unlock(&serde_json::json!({ "kind": "username", "value": &editor.username }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/lockouts");
+    let html_page = app.get_admin_lockouts_html().await;
+    assert!(html_page.contains("<p><i>Logins have been unlocked.</i></p>"));
+
+    // Assert
+    app.post_logout().await;
+    let response = post_login_from(&app, &unique_ip(), &editor.username, &editor.password).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn locked_out_usernames_are_escaped_on_the_lockouts_page() {
+    // Arrange
+    let app = spawn_app_with(|c| c.login_protection.failures_before_lockout_per_username = 1).await;
+    let username = format!("<script>{}</script>", Uuid::new_v4());
+    fail_logins(&app, &unique_ip(), &username, 1).await;
+    app.login_as(&app.test_user).await;
+
+    // Act
+    let html_page = app.get_admin_lockouts_html().await;
+
+    // Assert
+    assert!(!html_page.contains(&username));
+    assert!(html_page.contains("&lt;script&gt;"));
+}
+
+#[tokio::test]
+async fn only_owners_can_see_lockouts() {
+    // Arrange
+    let app = spawn_app_with(|_| {}).await;
+    let editor = app.add_user(Role::Editor).await;
+    app.login_as(&editor).await;
+
+    // Act
+    let page = app.get_admin_lockouts().await;
+    let unlock = app
+        .post_admin_lockouts_unlock(&serde_json::json!({ "kind": "ip", "value": "10.0.0.1" }))
+        .await;
+
+    // Assert
+    assert_eq!(page.status().as_u16(), 403);
+    assert_eq!(unlock.status().as_u16(), 403);
+}
@@ -0,0 +1,268 @@
+use crate::helpers::{assert_is_redirect_to, mock_email_server, spawn_app, spawn_app_with, wait_for_emails, TestApp, TestUser};
+use std::time::Duration;
+use uuid::Uuid;
+use zero2prod::authentication::Role;
+
+async fn give_email(app: &TestApp, user: &TestUser, email: &str) {
+    sqlx::query!("UPDATE users SET email = $1 WHERE user_id = $2", email, user.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+async fn spawn_app_with_magic_links() -> TestApp {
+    spawn_app_with(|c| c.magic_link.enabled = true).await
+}
+
+// The token of the link in the `index`-th email sent
+async fn request_magic_link(app: &TestApp, username_or_email: &str, index: usize) -> String {
+    app.post_magic_link(username_or_email).await;
+    let email_request = &wait_for_emails(app, index + 1).await[index];
+    let link = app.get_confirmation_links(email_request).html;
+    assert_eq!(link.path(), "/login/magic_link/confirm");
+    link.query_pairs()
+        .find(|(name, _)| name == "token")
+        .map(|(_, token)| token.into_owned())
+        .expect("The link has no token")
+}
+
+#[tokio::test]
+async fn login_form_links_to_magic_links() {
+    // Arrange
+    let app = spawn_app_with_magic_links().await;
+
+    // Act
+    let html_page = app.get_login_html().await;
+
+    // Assert
+    assert!(html_page.contains(r#"<a href="/login/magic_link">Email me a sign-in link</a>"#));
+}
+
+#[tokio::test]
+async fn magic_links_are_off_unless_enabled() {
+    // Arrange
+    let app = spawn_app().await;
+    give_email(&app, &app.test_user, "admin@example.com").await;
+    mock_email_server(&app).await;
+
+    // Act
+    let html_page = app.get_login_html().await;
+    let form = app
+        .api_client
+        .get(format!("{}/login/magic_link", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let request = app.post_magic_link(&app.test_user.username).await;
+
+    // Assert
+    assert!(!html_page.contains("/login/magic_link"));
+    assert_eq!(form.status().as_u16(), 404);
+    assert_eq!(request.status().as_u16(), 404);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(app.email_server.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn the_response_is_the_same_whether_or_not_the_account_exists() {
+    // Arrange
+    let app = spawn_app_with_magic_links().await;
+    give_email(&app, &app.test_user, "admin@example.com").await;
+    mock_email_server(&app).await;
+
+    // Act
+    let known = app.post_magic_link(&app.test_user.username).await;
+    let known_html = app.get_login_html().await;
+    let unknown = app.post_magic_link(&Uuid::new_v4().to_string()).await;
+    let unknown_html = app.get_login_html().await;
+
+    // Assert
+    assert_is_redirect_to(&known, "/login");
+    assert_is_redirect_to(&unknown, "/login");
+    assert_eq!(known_html, unknown_html);
+    wait_for_emails(&app, 1).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn repeated_requests_send_a_single_email() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.magic_link.enabled = true;
+        c.login_protection.max_link_requests_per_account = 1;
+    })
+    .await;
+    // Limits are kept in Redis, which all test cases share
+    let email = format!("{}@example.com", Uuid::new_v4());
+    give_email(&app, &app.test_user, &email).await;
+    mock_email_server(&app).await;
+    let first = app.post_magic_link(&email).await;
+    let first_html = app.get_login_html().await;
+
+    // Act
+    let mut later = Vec::new();
+    for _ in 0..3 {
+        let response = app.post_magic_link(&email.to_uppercase()).await;
+        later.push((response, app.get_login_html().await));
+    }
+
+    // Assert
+    assert_is_redirect_to(&first, "/login");
+    for (response, html_page) in later {
+        assert_is_redirect_to(&response, "/login");
+        assert_eq!(html_page, first_html);
+    }
+    wait_for_emails(&app, 1).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
+    let requests = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM audit_log WHERE action = 'magic_link_requested'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(requests.count, 1);
+}
+
+#[tokio::test]
+async fn a_magic_link_logs_the_user_in_once() {
+    // Arrange
+    let app = spawn_app_with_magic_links().await;
+    give_email(&app, &app.test_user, "admin@example.com").await;
+    mock_email_server(&app).await;
+    let token = request_magic_link(&app, "Admin@Example.com", 0).await;
+
+    // Act - Part 1 - Opening the link only asks for confirmation
+    let page = app
+        .api_client
+        .get(format!("{}/login/magic_link/confirm?token={}", &app.address, token))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(page.status().as_u16(), 200);
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+
+    // Act - Part 2 - Confirm
+    let response = app.post_magic_link_confirm(&token).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+
+    // Act - Part 3 - The link has been used up
+    app.post_logout().await;
+    let response = app.post_magic_link_confirm(&token).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn only_the_latest_magic_link_works() {
+    // Arrange
+    let app = spawn_app_with_magic_links().await;
+    give_email(&app, &app.test_user, "admin@example.com").await;
+    mock_email_server(&app).await;
+    let first = request_magic_link(&app, &app.test_user.username, 0).await;
+    let second = request_magic_link(&app, &app.test_user.username, 1).await;
+
+    // Act
+    let first_response = app.post_magic_link_confirm(&first).await;
+    let second_response = app.post_magic_link_confirm(&second).await;
+
+    // Assert
+    assert_eq!(first_response.status().as_u16(), 401);
+    assert_is_redirect_to(&second_response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn expired_magic_links_are_rejected() {
+    // Arrange
+    let app = spawn_app_with_magic_links().await;
+    give_email(&app, &app.test_user, "admin@example.com").await;
+    mock_email_server(&app).await;
+    let token = request_magic_link(&app, &app.test_user.username, 0).await;
+    sqlx::query!("UPDATE magic_links SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.post_magic_link_confirm(&token).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(response.text().await.unwrap().contains("invalid, expired or has already been used"));
+}
+
+#[tokio::test]
+async fn users_with_two_factor_still_need_their_code() {
+    // Arrange
+    let app = spawn_app_with_magic_links().await;
+    give_email(&app, &app.test_user, "admin@example.com").await;
+    sqlx::query!(
+        "UPDATE users SET totp_secret = 'JBSWY3DPEHPK3PXP' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    mock_email_server(&app).await;
+    let token = request_magic_link(&app, &app.test_user.username, 0).await;
+
+    // Act
+    let response = app.post_magic_link_confirm(&token).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/two_factor");
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn owners_can_disable_password_login() {
+    // Arrange
+    let app = spawn_app_with_magic_links().await;
+    let editor = app.add_user(Role::Editor).await;
+    give_email(&app, &editor, "editor@example.com").await;
+    mock_email_server(&app).await;
+    app.login_as(&app.test_user).await;
+
+    // Act - Part 1 - Disable it
+    let response = app
+        .post_admin_users(&format!("/{}/password_login/disable", editor.user_id), &serde_json::json!({}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    app.post_logout().await;
+
+    // Act - Part 2 - The password no longer logs the editor in
+    let response = app.login_as(&editor).await;
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains("Password login is disabled for your account"));
+
+    // Act - Part 3 - A sign-in link still does
+    let token = request_magic_link(&app, &editor.username, 0).await;
+    let response = app.post_magic_link_confirm(&token).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn password_login_cannot_be_disabled_without_an_email_address() {
+    // Arrange
+    let app = spawn_app_with_magic_links().await;
+    let editor = app.add_user(Role::Editor).await;
+    app.login_as(&app.test_user).await;
+
+    // Act
+    app.post_admin_users(&format!("/{}/password_login/disable", editor.user_id), &serde_json::json!({}))
+        .await;
+
+    // Assert
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("Password login can only be disabled for users with an email address."));
+    app.post_logout().await;
+    let response = app.login_as(&editor).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
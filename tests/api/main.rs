@@ -1,7 +1,27 @@
+mod admin_dashboard;
+mod admin_invites;
+mod admin_subscribers;
+mod admin_users;
+mod api_tokens;
+mod audit_log;
+mod bootstrap;
 mod change_password;
+mod cli;
+mod csrf;
 mod helpers;
 mod health_check;
 mod login;
+mod login_protection;
+mod magic_link;
+mod migrations;
+mod pages;
+mod password_reset;
+mod sessions;
+mod sign_up_protection;
 mod subscriptions;
+mod subscriptions_change_email;
 mod subscriptions_confirm;
+mod two_factor;
 mod newsletter;
+mod oidc;
+mod passkeys;
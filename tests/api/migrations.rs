@@ -0,0 +1,217 @@
+use crate::helpers::create_database;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::migrate::Migrator;
+use sqlx::PgPool;
+use std::borrow::Cow;
+use uuid::Uuid;
+use zero2prod::configuration::{get_configuration, Settings};
+use zero2prod::migrations::{migrate, pending_migrations, MIGRATOR};
+use zero2prod::startup::Application;
+
+// Settings for an application on a new database that has not been migrated
+fn configuration(migrate_on_startup: bool) -> Settings {
+    let mut c = get_configuration().expect("Failed to read configuration.");
+    c.database.database_name = Uuid::new_v4().to_string();
+    c.database.migrate_on_startup = migrate_on_startup;
+    c.application.port = 0;
+    c
+}
+
+#[tokio::test]
+async fn startup_fails_when_the_schema_is_behind() {
+    // Arrange
+    let configuration = configuration(false);
+    create_database(&configuration.database).await;
+
+    // Act
+    let result = Application::build(configuration).await;
+
+    // Assert
+    let error = result.err().expect("The application started on an empty database.");
+    assert!(error.to_string().contains("The database schema is behind"));
+}
+
+#[tokio::test]
+async fn startup_applies_pending_migrations_when_allowed() {
+    // Arrange
+    let configuration = configuration(true);
+    let pool = create_database(&configuration.database).await;
+    assert_eq!(pending_migrations(&pool).await.unwrap().len(), MIGRATOR.iter().count());
+
+    // Act
+    Application::build(configuration).await.expect("Failed to build application.");
+
+    // Assert
+    assert!(pending_migrations(&pool).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn instances_starting_together_migrate_once() {
+    // Arrange
+    let configuration = configuration(true);
+    let pool = create_database(&configuration.database).await;
+
+    // Act
+    let (first, second) = tokio::join!(
+        Application::build(configuration.clone()),
+        Application::build(configuration),
+    );
+
+    // Assert
+    first.expect("Failed to build the first instance.");
+    second.expect("Failed to build the second instance.");
+    assert!(pending_migrations(&pool).await.unwrap().is_empty());
+}
+
+// Insert a subscriber as the schema before `make_subscriber_emails_case_insensitive` had it
+async fn insert_old_subscriber(pool: &PgPool, email: &str, status: &str, days_ago: i64) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query("INSERT INTO subscriptions (id, email, name, subscribed_at, status) VALUES ($1, $2, 'le guin', $3, $4)")
+        .bind(id)
+        .bind(email)
+        .bind(Utc::now() - Duration::days(days_ago))
+        .bind(status)
+        .execute(pool)
+        .await
+        .expect("Failed to insert a subscriber.");
+    sqlx::query("INSERT INTO subscription_tokens (subscription_token, subscriber_id) VALUES ($1, $2)")
+        .bind(Uuid::new_v4().to_string())
+        .bind(id)
+        .execute(pool)
+        .await
+        .expect("Failed to insert a subscription token.");
+    id
+}
+
+#[tokio::test]
+async fn existing_subscriber_emails_are_normalized_and_deduplicated() {
+    // Arrange
+    let configuration = configuration(false);
+    let pool = create_database(&configuration.database).await;
+    let before = Migrator {
+        migrations: Cow::Owned(MIGRATOR.iter().filter(|m| m.version < 20230122101530).cloned().collect()),
+        ignore_missing: false,
+    };
+    before.run(&pool).await.expect("Failed to migrate the database.");
+    let pending = insert_old_subscriber(&pool, "ursula@example.com", "pending_confirmation", 3).await;
+    let confirmed = insert_old_subscriber(&pool, "Ursula@EXAMPLE.com", "confirmed", 2).await;
+    let newer = insert_old_subscriber(&pool, "URSULA@example.com", "pending_confirmation", 1).await;
+    let idn = insert_old_subscriber(&pool, "ursula@Bücher.example", "confirmed", 1).await;
+    // Full-width letters and dots are mapped to ASCII, as for new subscribers
+    let full_width = insert_old_subscriber(&pool, "le.guin@ｅｘａｍｐｌｅ。com", "confirmed", 5).await;
+    let ascii = insert_old_subscriber(&pool, "le.guin@example.com", "pending_confirmation", 4).await;
+
+    // Act
+    migrate(&pool).await.expect("Failed to migrate the database.");
+
+    // Assert
+    let mut saved: Vec<(Uuid, String)> = sqlx::query_as("SELECT id, email FROM subscriptions")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    saved.sort_by(|a, b| a.1.cmp(&b.1));
+    // A confirmed subscription is kept over older or newer pending ones
+    assert_eq!(
+        saved,
+        vec![
+            (confirmed, "Ursula@example.com".to_string()),
+            (full_width, "le.guin@example.com".to_string()),
+            (idn, "ursula@xn--bcher-kva.example".to_string()),
+        ]
+    );
+    for removed in [pending, newer, ascii] {
+        let (tokens,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM subscription_tokens WHERE subscriber_id = $1")
+            .bind(removed)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(tokens, 0);
+    }
+    let mut set_aside: Vec<(Uuid, String, Uuid)> =
+        sqlx::query_as("SELECT id, email, kept_id FROM removed_duplicate_subscriptions")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    set_aside.sort_by_key(|(_, email, _)| email.clone());
+    assert_eq!(
+        set_aside,
+        vec![
+            (newer, "URSULA@example.com".to_string(), confirmed),
+            (ascii, "le.guin@example.com".to_string(), full_width),
+            (pending, "ursula@example.com".to_string(), confirmed),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn existing_subscribers_get_a_confirmation_date_and_an_initial_status() {
+    // Arrange
+    let configuration = configuration(false);
+    let pool = create_database(&configuration.database).await;
+    let before = Migrator {
+        migrations: Cow::Owned(MIGRATOR.iter().filter(|m| m.version < 20230129120000).cloned().collect()),
+        ignore_missing: false,
+    };
+    before.run(&pool).await.expect("Failed to migrate the database.");
+    let pending = insert_old_subscriber(&pool, "ursula@example.com", "pending_confirmation", 2).await;
+    let confirmed = insert_old_subscriber(&pool, "le.guin@example.com", "confirmed", 1).await;
+
+    // Act
+    migrate(&pool).await.expect("Failed to migrate the database.");
+
+    // Assert
+    let (subscribed_at, confirmed_at): (DateTime<Utc>, Option<DateTime<Utc>>) =
+        sqlx::query_as("SELECT subscribed_at, confirmed_at FROM subscriptions WHERE id = $1")
+            .bind(confirmed)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(confirmed_at, Some(subscribed_at));
+    for (id, status) in [(pending, "pending"), (confirmed, "confirmed")] {
+        let history: Vec<(Option<String>, String)> = sqlx::query_as(
+            "SELECT from_status::text, to_status::text FROM subscription_status_history WHERE subscriber_id = $1",
+        )
+        .bind(id)
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(history, vec![(None, status.to_string())]);
+    }
+}
+
+#[tokio::test]
+async fn the_seed_admin_is_kept_when_it_has_api_tokens() {
+    // Arrange
+    let configuration = configuration(false);
+    let pool = create_database(&configuration.database).await;
+    let before = Migrator {
+        migrations: Cow::Owned(MIGRATOR.iter().filter(|m| m.version < 20230416100000).cloned().collect()),
+        ignore_missing: false,
+    };
+    before.run(&pool).await.expect("Failed to migrate the database.");
+    sqlx::query(
+        r#"
+        INSERT INTO api_tokens (token_id, user_id, name, token_hash, scopes, created_at)
+        SELECT $1, user_id, 'publishing script', 'hash', '{newsletter:publish}', now()
+        FROM users WHERE username = 'admin'
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .execute(&pool)
+    .await
+    .expect("Failed to insert an API token.");
+
+    // Act
+    migrate(&pool).await.expect("Failed to migrate the database.");
+
+    // Assert
+    let (password_change_required, tokens): (bool, i64) = sqlx::query_as(
+        "SELECT password_change_required, (SELECT COUNT(*) FROM api_tokens t WHERE t.user_id = u.user_id) \
+        FROM users u WHERE username = 'admin'",
+    )
+    .fetch_one(&pool)
+    .await
+    .expect("The seed admin was removed.");
+    assert!(password_change_required);
+    assert_eq!(tokens, 1);
+}
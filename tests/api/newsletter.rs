@@ -1,4 +1,5 @@
 use crate::helpers::{spawn_app, TestApp, ConfirmationLinks};
+use zero2prod::authentication::Role;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
@@ -157,3 +158,33 @@ async fn requests_missing_authorization_are_rejected() {
     assert_eq!(401, response.status().as_u16());
     // assert_eq!(r#"Basic realm="publish""#, response.headers()["WWW-Authenticate"]);
 }
+
+#[tokio::test]
+async fn users_without_the_publish_permission_are_rejected_with_a_403() {
+    // Arrange
+    let app = spawn_app().await;
+    let analyst = app.add_user(Role::Analyst).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletter", &app.address))
+        .basic_auth(&analyst.username, Some(&analyst.password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Nesletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>"
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(403, response.status().as_u16());
+}
@@ -0,0 +1,502 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::authentication::Role;
+use zero2prod::configuration::{OidcRoleMapping, OidcSettings};
+
+// The signing key of the mock provider, along with the modulus of its public half
+const PROVIDER_KEY: &str = include_str!("fixtures/oidc_provider_key.pem");
+const PROVIDER_KEY_MODULUS: &str = "3xV5PufAiswG01wWVChudardlVxE8j3gaEoqenZnVr4ddXcuF3KHAPKRwwnAFEPAmzT5OUw3eaDoymqE1G8E_2X-JCLPN78F2dv_M2dV5G6pbcMUavPn49cMbxcQBL_PsO5r_gBESu1WKoUqQ-5Orfu3sfHpnP3u-df2_2-_94eJWx0hS7HOSk83eJBdB-F42AC5Q8ThJ56mkzLmssIVT3CbetmqyJR4xT9OufQaIk1p8PVEn_UQZr53ugoNI_MGULeP4k3G8FM0yaApexEeJDj4EElIkiHCaUFLnzVQbBIxx6VR6w5iUHrRbOeCiMoFeqR0GrWiAz7p0x3-MzlYCQ";
+const CLIENT_ID: &str = "newsletter-admin";
+
+// Stands in for the identity provider: discovery, keys and the token endpoint
+struct MockProvider {
+    server: MockServer,
+}
+
+// What the provider got with the user when we sent them there
+struct AuthorizationRequest {
+    state: String,
+    nonce: String,
+    code_challenge: String,
+}
+
+impl MockProvider {
+    async fn start() -> Self {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/.well-known/openid-configuration"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "issuer": server.uri(),
+                "authorization_endpoint": format!("{}/authorize", server.uri()),
+                "token_endpoint": format!("{}/token", server.uri()),
+                "jwks_uri": format!("{}/jwks", server.uri()),
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/jwks"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "keys": [{ "kty": "RSA", "kid": "test-key", "use": "sig", "alg": "RS256", "n": PROVIDER_KEY_MODULUS, "e": "AQAB" }]
+            })))
+            .mount(&server)
+            .await;
+        Self { server }
+    }
+
+    fn settings(&self) -> OidcSettings {
+        OidcSettings {
+            issuer_url: self.server.uri(),
+            client_id: CLIENT_ID.into(),
+            client_secret: secrecy::Secret::new("client-secret".into()),
+            display_name: "Example SSO".into(),
+            scopes: vec!["openid".into(), "email".into()],
+            groups_claim: "groups".into(),
+            role_mappings: vec![
+                OidcRoleMapping { group: "newsletter-owners".into(), role: Role::Owner },
+                OidcRoleMapping { group: "newsletter-editors".into(), role: Role::Editor },
+            ],
+            default_role: None,
+            create_users: true,
+            link_by_email: false,
+            sync_roles: false,
+        }
+    }
+
+    // A signed ID token with `claims` on top of valid ones for this provider and client
+    fn id_token(&self, claims: &Value, nonce: &str) -> String {
+        let mut all_claims = json!({
+            "iss": self.server.uri(),
+            "aud": CLIENT_ID,
+            "exp": chrono::Utc::now().timestamp() + 300,
+            "iat": chrono::Utc::now().timestamp(),
+            "nonce": nonce,
+        });
+        for (name, value) in claims.as_object().unwrap() {
+            all_claims[name] = value.clone();
+        }
+        let mut header = Header::new(jsonwebtoken::Algorithm::RS256);
+        header.kid = Some("test-key".into());
+        encode(&header, &all_claims, &EncodingKey::from_rsa_pem(PROVIDER_KEY.as_bytes()).unwrap()).unwrap()
+    }
+
+    async fn answer_next_token_request_with(&self, id_token: String) {
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "access-token",
+                "token_type": "Bearer",
+                "id_token": id_token,
+            })))
+            .up_to_n_times(1)
+            .mount(&self.server)
+            .await;
+    }
+
+    // The form the app posted to the token endpoint last
+    async fn last_token_request(&self) -> HashMap<String, String> {
+        let requests = self.server.received_requests().await.unwrap();
+        let request = requests.iter().rev().find(|r| r.url.path() == "/token").expect("No token request");
+        url::form_urlencoded::parse(&request.body).into_owned().collect()
+    }
+}
+
+// Follow our redirect to the provider, as a browser would
+async fn start_login(app: &TestApp) -> AuthorizationRequest {
+    let response = app
+        .api_client
+        .get(format!("{}/login/oidc", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 303);
+    let location = reqwest::Url::parse(response.headers().get("Location").unwrap().to_str().unwrap()).unwrap();
+    assert_eq!(location.path(), "/authorize");
+    let query: HashMap<String, String> = location.query_pairs().into_owned().collect();
+    assert_eq!(query["client_id"], CLIENT_ID);
+    assert_eq!(query["code_challenge_method"], "S256");
+    AuthorizationRequest {
+        state: query["state"].clone(),
+        nonce: query["nonce"].clone(),
+        code_challenge: query["code_challenge"].clone(),
+    }
+}
+
+// Come back from the provider, as a browser would after logging in there
+async fn finish_login(app: &TestApp, state: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/login/oidc/callback?code=the-code&state={}", &app.address, state))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn log_in_through_provider(app: &TestApp, provider: &MockProvider, claims: Value) -> reqwest::Response {
+    let authorization = start_login(app).await;
+    provider
+        .answer_next_token_request_with(provider.id_token(&claims, &authorization.nonce))
+        .await;
+    finish_login(app, &authorization.state).await
+}
+
+async fn spawn_app_with_provider(configure: impl FnOnce(&mut OidcSettings)) -> (TestApp, MockProvider) {
+    let provider = MockProvider::start().await;
+    let mut settings = provider.settings();
+    configure(&mut settings);
+    let app = spawn_app_with(|c| c.oidc = Some(settings)).await;
+    (app, provider)
+}
+
+#[tokio::test]
+async fn the_login_form_offers_single_sign_on_only_when_configured() {
+    // Arrange
+    let (app, _provider) = spawn_app_with_provider(|_| {}).await;
+    let app_without_sso = spawn_app().await;
+
+    // Act
+    let html_page = app.get_login_html().await;
+    let html_page_without_sso = app_without_sso.get_login_html().await;
+    let start_without_sso = app_without_sso
+        .api_client
+        .get(format!("{}/login/oidc", &app_without_sso.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains(r#"<a href="/login/oidc">Log in with Example SSO</a>"#));
+    assert!(!html_page_without_sso.contains("/login/oidc"));
+    assert_eq!(start_without_sso.status().as_u16(), 404);
+}
+
+// A user who only ever logs in through the provider (or with magic links)
+async fn give_user_email_without_credentials(app: &TestApp, email: &str) {
+    sqlx::query!(
+        "UPDATE users SET email = $1, password_login_disabled = true WHERE user_id = $2",
+        email,
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn oidc_subject_of(app: &TestApp, user_id: Uuid) -> Option<String> {
+    sqlx::query!("SELECT oidc_subject FROM users WHERE user_id = $1", user_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .oidc_subject
+}
+
+async fn role_of(app: &TestApp, user_id: Uuid) -> Role {
+    sqlx::query!(r#"SELECT role AS "role: Role" FROM users WHERE user_id = $1"#, user_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .role
+}
+
+#[tokio::test]
+async fn an_existing_user_is_matched_by_verified_email_and_gets_the_mapped_role() {
+    // Arrange
+    let (app, provider) = spawn_app_with_provider(|settings| {
+        settings.link_by_email = true;
+        settings.sync_roles = true;
+    })
+    .await;
+    app.add_user(Role::Owner).await;
+    give_user_email_without_credentials(&app, "owner@example.com").await;
+
+    // Act
+    let response = log_in_through_provider(
+        &app,
+        &provider,
+        json!({ "sub": "idp-42", "email": "Owner@Example.com", "email_verified": true, "groups": ["newsletter-editors"] }),
+    )
+    .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+    assert_eq!(role_of(&app, app.test_user.user_id).await, Role::Editor);
+    assert_eq!(oidc_subject_of(&app, app.test_user.user_id).await.as_deref(), Some("idp-42"));
+}
+
+#[tokio::test]
+async fn existing_users_are_not_matched_by_email_unless_enabled() {
+    // Arrange
+    let (app, provider) = spawn_app_with_provider(|settings| settings.create_users = false).await;
+    give_user_email_without_credentials(&app, "owner@example.com").await;
+
+    // Act
+    let response = log_in_through_provider(
+        &app,
+        &provider,
+        json!({ "sub": "idp-42", "email": "owner@example.com", "email_verified": true, "groups": ["newsletter-owners"] }),
+    )
+    .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+    assert_eq!(oidc_subject_of(&app, app.test_user.user_id).await, None);
+}
+
+#[tokio::test]
+async fn users_with_a_password_or_a_second_factor_are_never_matched_by_email() {
+    // Arrange
+    let (app, provider) = spawn_app_with_provider(|settings| {
+        settings.create_users = false;
+        settings.link_by_email = true;
+    })
+    .await;
+    sqlx::query!("UPDATE users SET email = 'owner@example.com' WHERE user_id = $1", app.test_user.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let claims = json!({ "sub": "idp-42", "email": "owner@example.com", "email_verified": true, "groups": ["newsletter-owners"] });
+
+    // Act - Part 1 - The user logs in with a password
+    let response = log_in_through_provider(&app, &provider, claims.clone()).await;
+
+    // Assert - Part 1
+    assert_is_redirect_to(&response, "/login");
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+
+    // Act - Part 2 - The user logs in with a magic link and a second factor
+    sqlx::query!(
+        "UPDATE users SET password_login_disabled = true, totp_secret = 'JBSWY3DPEHPK3PXP' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let response = log_in_through_provider(&app, &provider, claims).await;
+
+    // Assert - Part 2
+    assert_is_redirect_to(&response, "/login");
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+    assert_eq!(oidc_subject_of(&app, app.test_user.user_id).await, None);
+}
+
+#[tokio::test]
+async fn roles_are_only_brought_in_line_on_login_when_enabled() {
+    // Arrange
+    let (app, provider) = spawn_app_with_provider(|_| {}).await;
+    log_in_through_provider(&app, &provider, json!({ "sub": "idp-7", "preferred_username": "ursula", "groups": ["newsletter-owners"] }))
+        .await;
+    app.post_logout().await;
+
+    // Act
+    let response =
+        log_in_through_provider(&app, &provider, json!({ "sub": "idp-7", "groups": ["newsletter-editors"] })).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let user = sqlx::query!(r#"SELECT role AS "role: Role" FROM users WHERE oidc_subject = 'idp-7'"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(user.role, Role::Owner);
+}
+
+#[tokio::test]
+async fn the_last_active_owner_is_never_demoted() {
+    // Arrange
+    let (app, provider) = spawn_app_with_provider(|settings| {
+        settings.link_by_email = true;
+        settings.sync_roles = true;
+    })
+    .await;
+    let disabled_owner = app.add_user(Role::Owner).await;
+    sqlx::query!("UPDATE users SET disabled = true WHERE user_id = $1", disabled_owner.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    give_user_email_without_credentials(&app, "owner@example.com").await;
+
+    // Act
+    let response = log_in_through_provider(
+        &app,
+        &provider,
+        json!({ "sub": "idp-42", "email": "owner@example.com", "email_verified": true, "groups": ["newsletter-editors"] }),
+    )
+    .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    assert_eq!(role_of(&app, app.test_user.user_id).await, Role::Owner);
+}
+
+#[tokio::test]
+async fn the_token_request_proves_it_comes_from_whoever_started_the_login() {
+    // Arrange
+    let (app, provider) = spawn_app_with_provider(|_| {}).await;
+    let authorization = start_login(&app).await;
+    let claims = json!({ "sub": "idp-42", "groups": ["newsletter-owners"] });
+    provider
+        .answer_next_token_request_with(provider.id_token(&claims, &authorization.nonce))
+        .await;
+
+    // Act
+    finish_login(&app, &authorization.state).await;
+
+    // Assert
+    let form = provider.last_token_request().await;
+    assert_eq!(form["grant_type"], "authorization_code");
+    assert_eq!(form["code"], "the-code");
+    let challenge = base64::encode_config(Sha256::digest(form["code_verifier"].as_bytes()), base64::URL_SAFE_NO_PAD);
+    assert_eq!(challenge, authorization.code_challenge);
+}
+
+#[tokio::test]
+async fn unknown_users_are_created_just_in_time() {
+    // Arrange
+    let (app, provider) = spawn_app_with_provider(|_| {}).await;
+
+    // Act
+    let response = log_in_through_provider(
+        &app,
+        &provider,
+        json!({ "sub": "idp-7", "preferred_username": "ursula", "email": "ursula@example.com", "email_verified": true, "groups": ["newsletter-owners", "newsletter-editors"] }),
+    )
+    .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let user = sqlx::query!(
+        r#"SELECT username, email, role AS "role: Role" FROM users WHERE oidc_subject = 'idp-7'"#,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(user.username, "ursula");
+    assert_eq!(user.email.as_deref(), Some("ursula@example.com"));
+    assert_eq!(user.role, Role::Owner);
+    let html_page = app.get_admin_audit_log_html("?action=user_created_by_sso").await;
+    assert!(html_page.contains("<td>ursula</td>"));
+}
+
+#[tokio::test]
+async fn unknown_users_are_refused_unless_users_are_created_just_in_time() {
+    // Arrange
+    let (app, provider) = spawn_app_with_provider(|settings| settings.create_users = false).await;
+
+    // Act
+    let response = log_in_through_provider(
+        &app,
+        &provider,
+        json!({ "sub": "idp-7", "preferred_username": "ursula", "groups": ["newsletter-owners"] }),
+    )
+    .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains("There is no account for you"));
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn users_in_no_mapped_group_are_refused_without_a_default_role() {
+    // Arrange
+    let (app, provider) = spawn_app_with_provider(|_| {}).await;
+
+    // Act
+    let response = log_in_through_provider(&app, &provider, json!({ "sub": "idp-7", "groups": ["sales"] })).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains("does not give you access"));
+    let user = sqlx::query!("SELECT user_id FROM users WHERE oidc_subject = 'idp-7'")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(user.is_none());
+}
+
+#[tokio::test]
+async fn unverified_emails_do_not_match_existing_users() {
+    // Arrange
+    let (app, provider) = spawn_app_with_provider(|settings| {
+        settings.create_users = false;
+        settings.link_by_email = true;
+    })
+    .await;
+    give_user_email_without_credentials(&app, "owner@example.com").await;
+
+    // Act
+    let response = log_in_through_provider(
+        &app,
+        &provider,
+        json!({ "sub": "idp-666", "email": "owner@example.com", "email_verified": false, "groups": ["newsletter-owners"] }),
+    )
+    .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn answers_for_another_login_are_rejected() {
+    // Arrange
+    let (app, provider) = spawn_app_with_provider(|_| {}).await;
+    let claims = json!({ "sub": "idp-7", "groups": ["newsletter-owners"] });
+
+    // Act - Part 1 - Another state
+    let authorization = start_login(&app).await;
+    provider
+        .answer_next_token_request_with(provider.id_token(&claims, &authorization.nonce))
+        .await;
+    let wrong_state = finish_login(&app, "forged-state").await;
+
+    // Act - Part 2 - Another nonce
+    let authorization = start_login(&app).await;
+    provider
+        .answer_next_token_request_with(provider.id_token(&claims, "another-nonce"))
+        .await;
+    let wrong_nonce = finish_login(&app, &authorization.state).await;
+
+    // Assert
+    assert_is_redirect_to(&wrong_state, "/login");
+    assert_is_redirect_to(&wrong_nonce, "/login");
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn id_tokens_not_signed_by_the_provider_are_rejected() {
+    // Arrange
+    let (app, provider) = spawn_app_with_provider(|_| {}).await;
+    let authorization = start_login(&app).await;
+    let claims = json!({
+        "iss": provider.server.uri(),
+        "aud": CLIENT_ID,
+        "exp": chrono::Utc::now().timestamp() + 300,
+        "nonce": authorization.nonce,
+        "sub": "idp-7",
+        "groups": ["newsletter-owners"],
+    });
+    // Signed with the public key, as if it were a shared secret
+    let forged = encode(
+        &Header::new(jsonwebtoken::Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(PROVIDER_KEY_MODULUS.as_bytes()),
+    )
+    .unwrap();
+    provider.answer_next_token_request_with(forged).await;
+
+    // Act
+    let response = finish_login(&app, &authorization.state).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains("Single sign-on failed"));
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
@@ -0,0 +1,119 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+const SCRIPT: &str = "<script>alert('pwned')</script>";
+const ESCAPED_SCRIPT: &str = "&lt;script&gt;alert(&#x27;pwned&#x27;)&lt;/script&gt;";
+
+#[tokio::test]
+async fn every_page_shares_the_layout() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    for page in [
+        "/",
+        "/login",
+        "/password_reset",
+        "/admin/dashboard",
+        "/admin/password",
+        "/admin/users",
+        "/admin/invites",
+        "/admin/lockouts",
+        "/admin/api_tokens",
+        "/admin/sessions",
+        "/admin/two_factor",
+        "/admin/passkeys",
+    ] {
+        // Act
+        let response = app
+            .api_client
+            .get(format!("{}{}", &app.address, page))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200, "{}", page);
+        assert_eq!(
+            response.headers()["Content-Type"].to_str().unwrap(),
+            "text/html; charset=utf-8",
+            "{}",
+            page
+        );
+        let html_page = response.text().await.unwrap();
+        assert!(html_page.starts_with("<!DOCTYPE html>"), "{} does not use the layout", page);
+        assert!(html_page.contains(r#"<meta http-equiv="content-type" content="text/html; charset=utf-8">"#));
+    }
+}
+
+#[tokio::test]
+async fn flash_messages_are_escaped() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    // Act - Part 1 - The unknown scope ends up in a flash message
+    let response = app
+        .post_admin_api_tokens("", &[("name", "CI"), ("scope", SCRIPT)])
+        .await;
+    assert_is_redirect_to(&response, "/admin/api_tokens");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_api_tokens_html().await;
+
+    // Assert
+    assert!(html_page.contains(&format!("<p><i>Unknown scope: {}.</i></p>", ESCAPED_SCRIPT)));
+    assert!(!html_page.contains(SCRIPT));
+}
+
+#[tokio::test]
+async fn user_data_is_escaped() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    // Act - Part 1 - The page showing the new token
+    let response = app
+        .post_admin_api_tokens("", &[("name", SCRIPT), ("scope", "newsletter:publish")])
+        .await;
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(ESCAPED_SCRIPT));
+    assert!(!html_page.contains(SCRIPT));
+
+    // Act - Part 2 - The list of tokens
+    let html_page = app.get_admin_api_tokens_html().await;
+
+    // Assert
+    assert!(html_page.contains(&format!("<td>{}</td>", ESCAPED_SCRIPT)));
+    assert!(!html_page.contains(SCRIPT));
+}
+
+#[tokio::test]
+async fn dead_links_get_an_error_page() {
+    // Arrange
+    let app = spawn_app().await;
+
+    for (page, message) in [
+        (
+            "/invites/accept?invite_token=%3Cscript%3E",
+            "This invite is invalid, expired or has already been used.",
+        ),
+        (
+            "/password_reset/confirm?reset_token=%3Cscript%3E",
+            "This password reset link is invalid, expired or has already been used.",
+        ),
+    ] {
+        // Act
+        let response = app
+            .api_client
+            .get(format!("{}{}", &app.address, page))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401, "{}", page);
+        let html_page = response.text().await.unwrap();
+        assert!(html_page.starts_with("<!DOCTYPE html>"), "{} does not use the layout", page);
+        assert!(html_page.contains(&format!("<p>{}</p>", message)));
+    }
+}
@@ -0,0 +1,427 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+use serde_cbor::Value;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use zero2prod::authentication::Role;
+use zero2prod::csrf::CSRF_HEADER;
+
+// What the browser runs on for the test app, see `configuration/local.yaml`
+const ORIGIN: &str = "http://127.0.0.1";
+const RP_ID: &str = "127.0.0.1";
+
+const USER_PRESENT: u8 = 0x01;
+const USER_VERIFIED: u8 = 0x04;
+const ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+/// A security key in software: answers our challenges the way a browser
+/// relays an authenticator's answers.
+struct SoftAuthenticator {
+    key_pair: EcdsaKeyPair,
+    credential_id: Vec<u8>,
+    sign_count: u32,
+}
+
+impl SoftAuthenticator {
+    fn new() -> Self {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        Self {
+            key_pair: EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref()).unwrap(),
+            credential_id: uuid::Uuid::new_v4().as_bytes().to_vec(),
+            sign_count: 0,
+        }
+    }
+
+    fn credential_id(&self) -> String {
+        encode(&self.credential_id)
+    }
+
+    fn cose_key(&self) -> Vec<u8> {
+        // Uncompressed point: 0x04 || x || y
+        let point = self.key_pair.public_key().as_ref();
+        let mut key = BTreeMap::new();
+        key.insert(Value::Integer(1), Value::Integer(2));
+        key.insert(Value::Integer(3), Value::Integer(-7));
+        key.insert(Value::Integer(-1), Value::Integer(1));
+        key.insert(Value::Integer(-2), Value::Bytes(point[1..33].to_vec()));
+        key.insert(Value::Integer(-3), Value::Bytes(point[33..].to_vec()));
+        serde_cbor::to_vec(&Value::Map(key)).unwrap()
+    }
+
+    fn authenticator_data(&self, flags: u8, with_credential: bool) -> Vec<u8> {
+        let mut data = Sha256::digest(RP_ID.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend_from_slice(&self.sign_count.to_be_bytes());
+        if with_credential {
+            data.extend_from_slice(&[0; 16]);
+            data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            data.extend_from_slice(&self.credential_id);
+            data.extend_from_slice(&self.cose_key());
+        }
+        data
+    }
+
+    /// The body the admin page's script posts to add this passkey.
+    fn register(&self, options: &serde_json::Value, name: &str) -> serde_json::Value {
+        let client_data = client_data("webauthn.create", options);
+        let mut attestation = BTreeMap::new();
+        attestation.insert(Value::Text("fmt".into()), Value::Text("none".into()));
+        attestation.insert(Value::Text("attStmt".into()), Value::Map(BTreeMap::new()));
+        attestation.insert(
+            Value::Text("authData".into()),
+            Value::Bytes(self.authenticator_data(USER_PRESENT | USER_VERIFIED | ATTESTED_CREDENTIAL_DATA, true)),
+        );
+        json!({
+            "name": name,
+            "client_data_json": encode(&client_data),
+            "attestation_object": encode(&serde_cbor::to_vec(&Value::Map(attestation)).unwrap()),
+        })
+    }
+
+    /// The body the login pages' script posts to log in with this passkey.
+    fn sign(&mut self, options: &serde_json::Value, flags: u8) -> serde_json::Value {
+        self.sign_count += 1;
+        let client_data = client_data("webauthn.get", options);
+        let authenticator_data = self.authenticator_data(flags, false);
+        let mut signed = authenticator_data.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data));
+        let signature = self.key_pair.sign(&SystemRandom::new(), &signed).unwrap();
+        json!({
+            "credential_id": self.credential_id(),
+            "client_data_json": encode(&client_data),
+            "authenticator_data": encode(&authenticator_data),
+            "signature": encode(signature.as_ref()),
+        })
+    }
+}
+
+fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn client_data(kind: &str, options: &serde_json::Value) -> Vec<u8> {
+    serde_json::to_vec(&json!({
+        "type": kind,
+        "challenge": options["challenge"],
+        "origin": ORIGIN,
+    }))
+    .unwrap()
+}
+
+// What the page's script passes on to the browser
+async fn ceremony_options(app: &TestApp, path: &str) -> serde_json::Value {
+    let response = app.post_json(path, &json!({})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.json().await.unwrap()
+}
+
+// Where the script is told to send the browser next
+async fn assert_script_redirect(response: reqwest::Response, status: u16, location: &str) {
+    assert_eq!(response.status().as_u16(), status);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["redirect"], location);
+}
+
+async fn add_passkey(app: &TestApp, authenticator: &SoftAuthenticator) {
+    let options = ceremony_options(app, "/admin/passkeys/options").await;
+    let response = app.post_json("/admin/passkeys", &authenticator.register(&options, "Laptop")).await;
+    assert_script_redirect(response, 200, "/admin/passkeys").await;
+}
+
+async fn get_passkeys_html(app: &TestApp) -> String {
+    app.api_client
+        .get(format!("{}/admin/passkeys", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn users_can_add_passkeys() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let authenticator = SoftAuthenticator::new();
+
+    // Act
+    add_passkey(&app, &authenticator).await;
+
+    // Assert
+    let html_page = get_passkeys_html(&app).await;
+    assert!(html_page.contains("The passkey has been added."));
+    assert!(html_page.contains("<td>Laptop</td>"));
+    let options = ceremony_options(&app, "/admin/passkeys/options").await;
+    assert_eq!(options["excludeCredentials"][0]["id"], authenticator.credential_id());
+}
+
+#[tokio::test]
+async fn passkeys_created_for_another_challenge_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    ceremony_options(&app, "/admin/passkeys/options").await;
+    let forged = json!({ "challenge": encode(b"not the challenge") });
+
+    // Act
+    let response = app
+        .post_json("/admin/passkeys", &SoftAuthenticator::new().register(&forged, "Laptop"))
+        .await;
+
+    // Assert
+    assert_script_redirect(response, 400, "/admin/passkeys").await;
+    assert!(get_passkeys_html(&app).await.contains("The passkey could not be verified."));
+}
+
+#[tokio::test]
+async fn a_passkey_logs_the_user_in_without_a_password() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut authenticator = SoftAuthenticator::new();
+    app.login_as(&app.test_user).await;
+    add_passkey(&app, &authenticator).await;
+    app.post_logout().await;
+
+    // Act
+    let options = ceremony_options(&app, "/login/passkey/options").await;
+    assert_eq!(options["userVerification"], "required");
+    let response = app
+        .post_json("/login/passkey", &authenticator.sign(&options, USER_PRESENT | USER_VERIFIED))
+        .await;
+
+    // Assert
+    assert_script_redirect(response, 200, "/admin/dashboard").await;
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn passwordless_logins_need_the_user_to_be_verified() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut authenticator = SoftAuthenticator::new();
+    app.login_as(&app.test_user).await;
+    add_passkey(&app, &authenticator).await;
+    app.post_logout().await;
+
+    // Act
+    let options = ceremony_options(&app, "/login/passkey/options").await;
+    let response = app.post_json("/login/passkey", &authenticator.sign(&options, USER_PRESENT)).await;
+
+    // Assert
+    assert_script_redirect(response, 401, "/login").await;
+    assert!(app.get_login_html().await.contains("The passkey could not be verified."));
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn a_passkey_is_a_second_factor_after_the_password() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut authenticator = SoftAuthenticator::new();
+    app.login_as(&app.test_user).await;
+    add_passkey(&app, &authenticator).await;
+    app.post_logout().await;
+
+    // Act - Part 1 - The password alone is not enough
+    let response = app.login_as(&app.test_user).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+
+    // Act - Part 2 - Use the passkey
+    let options = ceremony_options(&app, "/login/passkey/options").await;
+    assert_eq!(options["allowCredentials"][0]["id"], authenticator.credential_id());
+    let response = app.post_json("/login/passkey", &authenticator.sign(&options, USER_PRESENT)).await;
+
+    // Assert
+    assert_script_redirect(response, 200, "/admin/dashboard").await;
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn the_second_factor_must_be_a_passkey_of_the_same_user() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = app.add_user(Role::Editor).await;
+    let mut authenticator = SoftAuthenticator::new();
+    app.login_as(&app.test_user).await;
+    add_passkey(&app, &SoftAuthenticator::new()).await;
+    app.post_logout().await;
+    app.login_as(&editor).await;
+    add_passkey(&app, &authenticator).await;
+    app.post_logout().await;
+
+    // Act
+    let response = app.login_as(&app.test_user).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    let options = ceremony_options(&app, "/login/passkey/options").await;
+    let response = app.post_json("/login/passkey", &authenticator.sign(&options, USER_PRESENT)).await;
+
+    // Assert
+    assert_script_redirect(response, 401, "/login/two_factor").await;
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn answers_cannot_be_replayed() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut authenticator = SoftAuthenticator::new();
+    app.login_as(&app.test_user).await;
+    add_passkey(&app, &authenticator).await;
+    app.post_logout().await;
+    let options = ceremony_options(&app, "/login/passkey/options").await;
+    let answer = authenticator.sign(&options, USER_PRESENT | USER_VERIFIED);
+    let response = app.post_json("/login/passkey", &answer).await;
+    assert_script_redirect(response, 200, "/admin/dashboard").await;
+    app.post_logout().await;
+
+    // Act
+    let response = app.post_json("/login/passkey", &answer).await;
+
+    // Assert
+    assert_script_redirect(response, 401, "/login").await;
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn cloned_passkeys_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut authenticator = SoftAuthenticator::new();
+    app.login_as(&app.test_user).await;
+    add_passkey(&app, &authenticator).await;
+    app.post_logout().await;
+    authenticator.sign_count = 10;
+    let options = ceremony_options(&app, "/login/passkey/options").await;
+    let response = app
+        .post_json("/login/passkey", &authenticator.sign(&options, USER_PRESENT | USER_VERIFIED))
+        .await;
+    assert_script_redirect(response, 200, "/admin/dashboard").await;
+    app.post_logout().await;
+
+    // Act - A copy of the key, with a counter of its own
+    authenticator.sign_count = 3;
+    let options = ceremony_options(&app, "/login/passkey/options").await;
+    let response = app
+        .post_json("/login/passkey", &authenticator.sign(&options, USER_PRESENT | USER_VERIFIED))
+        .await;
+
+    // Assert
+    assert_script_redirect(response, 401, "/login").await;
+}
+
+#[tokio::test]
+async fn deleted_passkeys_no_longer_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut authenticator = SoftAuthenticator::new();
+    app.login_as(&app.test_user).await;
+    add_passkey(&app, &authenticator).await;
+    let passkey_id = sqlx::query!("SELECT passkey_id FROM passkeys")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .passkey_id;
+
+    // Act - Part 1 - Delete it
+    let response = app
+        .api_client
+        .post(format!("{}/admin/passkeys/{}/delete", &app.address, passkey_id))
+        .header(CSRF_HEADER, app.csrf_token().await)
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/passkeys");
+    assert!(get_passkeys_html(&app).await.contains("The passkey has been deleted."));
+    app.post_logout().await;
+
+    // Act - Part 2 - Try to log in with it
+    let options = ceremony_options(&app, "/login/passkey/options").await;
+    let response = app
+        .post_json("/login/passkey", &authenticator.sign(&options, USER_PRESENT | USER_VERIFIED))
+        .await;
+
+    // Assert
+    assert_script_redirect(response, 401, "/login").await;
+}
+
+#[tokio::test]
+async fn users_cannot_delete_the_passkeys_of_others() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = app.add_user(Role::Editor).await;
+    app.login_as(&app.test_user).await;
+    add_passkey(&app, &SoftAuthenticator::new()).await;
+    app.post_logout().await;
+    let passkey_id = sqlx::query!("SELECT passkey_id FROM passkeys")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .passkey_id;
+    app.login_as(&editor).await;
+
+    // Act
+    app.api_client
+        .post(format!("{}/admin/passkeys/{}/delete", &app.address, passkey_id))
+        .header(CSRF_HEADER, app.csrf_token().await)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(get_passkeys_html(&app).await.contains("There is no such passkey."));
+    let count = sqlx::query!(r#"SELECT count(*) AS "count!" FROM passkeys"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(count, 1);
+}
+
+#[tokio::test]
+async fn disabled_users_cannot_log_in_with_a_passkey() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut authenticator = SoftAuthenticator::new();
+    app.login_as(&app.test_user).await;
+    add_passkey(&app, &authenticator).await;
+    app.post_logout().await;
+    sqlx::query!("UPDATE users SET disabled = true WHERE user_id = $1", app.test_user.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let options = ceremony_options(&app, "/login/passkey/options").await;
+    let response = app
+        .post_json("/login/passkey", &authenticator.sign(&options, USER_PRESENT | USER_VERIFIED))
+        .await;
+
+    // Assert
+    assert_script_redirect(response, 401, "/login").await;
+}
+
+#[tokio::test]
+async fn a_passkey_meets_a_two_factor_requirement() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query!("UPDATE security_settings SET require_two_factor = true")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.login_as(&app.test_user).await;
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/admin/two_factor");
+
+    // Act
+    add_passkey(&app, &SoftAuthenticator::new()).await;
+
+    // Assert
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+}
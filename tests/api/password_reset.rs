@@ -0,0 +1,232 @@
+use crate::helpers::{assert_is_redirect_to, mock_email_server, spawn_app, spawn_app_with, wait_for_emails, TestApp};
+use std::time::Duration;
+use uuid::Uuid;
+
+async fn give_test_user_an_email(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE users SET email = 'admin@example.com' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn request_reset_link(app: &TestApp) -> reqwest::Url {
+    app.post_password_reset(&app.test_user.username).await;
+    let email_request = &wait_for_emails(app, 1).await[0];
+    app.get_confirmation_links(email_request).html
+}
+
+#[tokio::test]
+async fn login_form_links_to_password_reset() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let html_page = app.get_login_html().await;
+
+    // Assert
+    assert!(html_page.contains(r#"<a href="/password_reset">Forgot password?</a>"#));
+}
+
+#[tokio::test]
+async fn the_response_is_the_same_whether_or_not_the_username_exists() {
+    // Arrange
+    let app = spawn_app().await;
+    give_test_user_an_email(&app).await;
+    mock_email_server(&app).await;
+
+    // Act
+    let known = app.post_password_reset(&app.test_user.username).await;
+    let known_html = app.get_login_html().await;
+    let unknown = app.post_password_reset(&Uuid::new_v4().to_string()).await;
+    let unknown_html = app.get_login_html().await;
+
+    // Assert
+    assert_is_redirect_to(&known, "/login");
+    assert_is_redirect_to(&unknown, "/login");
+    assert_eq!(known_html, unknown_html);
+    wait_for_emails(&app, 1).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn repeated_reset_requests_send_a_single_email() {
+    // Arrange
+    let app = spawn_app_with(|c| c.login_protection.max_link_requests_per_account = 1).await;
+    give_test_user_an_email(&app).await;
+    mock_email_server(&app).await;
+    let first = app.post_password_reset(&app.test_user.username).await;
+    let first_html = app.get_login_html().await;
+
+    // Act
+    let mut later = Vec::new();
+    for _ in 0..3 {
+        let response = app.post_password_reset(&app.test_user.username).await;
+        later.push((response, app.get_login_html().await));
+    }
+
+    // Assert
+    assert_is_redirect_to(&first, "/login");
+    for (response, html_page) in later {
+        assert_is_redirect_to(&response, "/login");
+        assert_eq!(html_page, first_html);
+    }
+    wait_for_emails(&app, 1).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
+    let requests = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM audit_log WHERE action = 'password_reset_requested'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(requests.count, 1);
+}
+
+#[tokio::test]
+async fn a_reset_link_lets_the_user_choose_a_new_password() {
+    // Arrange
+    let app = spawn_app().await;
+    give_test_user_an_email(&app).await;
+    mock_email_server(&app).await;
+    let reset_link = request_reset_link(&app).await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Open the link
+    let response = reqwest::get(reset_link.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 2 - Choose a new password
+    let reset_token = reset_link.query_pairs().find(|(k, _)| k == "reset_token").unwrap().1;
+    let response = app
+        .post_password_reset_confirm(&serde_json::json!({
+            "reset_token": reset_token,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Your password has been reset, you can now log in.</i></p>"));
+
+    // Assert
+    let response = app.login_as(&app.test_user).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_reset_link_can_only_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    give_test_user_an_email(&app).await;
+    mock_email_server(&app).await;
+    let reset_link = request_reset_link(&app).await;
+    let reset_token = reset_link.query_pairs().find(|(k, _)| k == "reset_token").unwrap().1.to_string();
+    let new_password = Uuid::new_v4().to_string();
+    let body = serde_json::json!({
+        "reset_token": reset_token,
+        "new_password": &new_password,
+        "new_password_check": &new_password,
+    });
+    app.post_password_reset_confirm(&body).await;
+
+    // Act
+    let page = reqwest::get(reset_link).await.unwrap();
+    let second_use = app.post_password_reset_confirm(&body).await;
+
+    // Assert
+    assert_eq!(page.status().as_u16(), 401);
+    assert_eq!(second_use.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn an_expired_reset_link_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    give_test_user_an_email(&app).await;
+    mock_email_server(&app).await;
+    let reset_link = request_reset_link(&app).await;
+    sqlx::query!("UPDATE password_resets SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(reset_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_forged_reset_token_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!(
+        "{}/password_reset/confirm?reset_token={}",
+        app.address,
+        Uuid::new_v4()
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_forged_reset_token_is_rejected_before_the_new_password_is_looked_at() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_password_reset_confirm(&serde_json::json!({
+            "reset_token": Uuid::new_v4().to_string(),
+            "new_password": Uuid::new_v4().to_string(),
+            "new_password_check": Uuid::new_v4().to_string(),
+        }))
+        .await;
+
+    // Assert
+    // Not sent back to the form to fix the mismatched passwords
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn resetting_the_password_logs_the_user_out_everywhere() {
+    // Arrange
+    let app = spawn_app().await;
+    give_test_user_an_email(&app).await;
+    mock_email_server(&app).await;
+    app.login_as(&app.test_user).await;
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let reset_link = request_reset_link(&app).await;
+    let reset_token = reset_link.query_pairs().find(|(k, _)| k == "reset_token").unwrap().1.to_string();
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act
+    app.post_password_reset_confirm(&serde_json::json!({
+        "reset_token": reset_token,
+        "new_password": &new_password,
+        "new_password_check": &new_password,
+    }))
+    .await;
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
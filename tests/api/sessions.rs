@@ -0,0 +1,267 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+use actix_web::cookie::{Cookie, CookieJar, Key};
+use secrecy::Secret;
+use uuid::Uuid;
+use zero2prod::session_keys::SESSION_COOKIE_NAME;
+use zero2prod::authentication::Role;
+
+const OTHER_BROWSER: &str = "Other browser/1.0";
+
+async fn get_dashboard_from(app: &TestApp, client: &reqwest::Client) -> reqwest::Response {
+    client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn session_id_of(app: &TestApp, user_agent: &str) -> Uuid {
+    sqlx::query!("SELECT session_id FROM user_sessions WHERE user_agent = $1", user_agent)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .session_id
+}
+
+#[tokio::test]
+async fn the_sessions_page_lists_active_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    app.login_from_other_browser(&app.test_user, OTHER_BROWSER).await;
+
+    // Act
+    let html_page = app.get_admin_sessions_html().await;
+
+    // Assert
+    assert!(html_page.contains(OTHER_BROWSER));
+    assert!(html_page.contains("127.0.0.1"));
+    assert_eq!(html_page.matches("(this session)").count(), 1);
+}
+
+#[tokio::test]
+async fn the_sessions_page_does_not_list_sessions_of_other_users() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_user = app.add_user(Role::Editor).await;
+    app.login_from_other_browser(&other_user, OTHER_BROWSER).await;
+    app.login_as(&app.test_user).await;
+
+    // Act
+    let html_page = app.get_admin_sessions_html().await;
+
+    // Assert
+    assert!(!html_page.contains(OTHER_BROWSER));
+}
+
+#[tokio::test]
+async fn revoking_a_session_logs_it_out() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let other_browser = app.login_from_other_browser(&app.test_user, OTHER_BROWSER).await;
+    let session_id = session_id_of(&app, OTHER_BROWSER).await;
+
+    // Act - Part 1 - Revoke the other session
+    let response = app.post_admin_sessions(&format!("/{}/revoke", session_id)).await;
+    assert_is_redirect_to(&response, "/admin/sessions");
+
+    // Act - Part 2 - The other browser is logged out
+    let response = get_dashboard_from(&app, &other_browser).await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 3 - This one is not
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn users_cannot_revoke_sessions_of_other_users() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_user = app.add_user(Role::Owner).await;
+    let other_browser = app.login_from_other_browser(&other_user, OTHER_BROWSER).await;
+    let session_id = session_id_of(&app, OTHER_BROWSER).await;
+    app.login_as(&app.test_user).await;
+
+    // Act
+    let response = app.post_admin_sessions(&format!("/{}/revoke", session_id)).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let html_page = app.get_admin_sessions_html().await;
+    assert!(html_page.contains("That session does not exist or has already ended."));
+    let response = get_dashboard_from(&app, &other_browser).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn logging_out_everywhere_ends_all_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let other_browser = app.login_from_other_browser(&app.test_user, OTHER_BROWSER).await;
+
+    // Act
+    let response = app.post_admin_sessions("/revoke_all").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("You have been logged out of all your sessions."));
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    let response = get_dashboard_from(&app, &other_browser).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn changing_password_logs_out_other_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let other_browser = app.login_from_other_browser(&app.test_user, OTHER_BROWSER).await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    // Assert
+    let response = get_dashboard_from(&app, &other_browser).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn idle_sessions_expire() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    sqlx::query!("UPDATE user_sessions SET last_seen_at = now() - interval '2 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.get_admin_dashboard().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Your session has expired, please log in again."));
+}
+
+#[tokio::test]
+async fn sessions_expire_after_the_absolute_timeout_despite_activity() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    sqlx::query!("UPDATE user_sessions SET created_at = now() - interval '13 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.get_admin_dashboard().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn activity_keeps_a_session_alive() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    sqlx::query!("UPDATE user_sessions SET last_seen_at = now() - interval '30 minutes'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.get_admin_dashboard().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let last_seen_at = sqlx::query!("SELECT last_seen_at FROM user_sessions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .last_seen_at;
+    assert!(chrono::Utc::now() - last_seen_at < chrono::Duration::minutes(1));
+}
+
+fn session_key(seed: &str) -> String {
+    seed.repeat(64)
+}
+
+// Spawn an app with rotated keys, log in and return the session cookie re-encrypted with `key`
+async fn log_in_and_reencrypt_session_cookie(key: &str) -> (TestApp, String) {
+    let app = spawn_app_with(|c| {
+        c.session.key = Some(Secret::new(session_key("b")));
+        c.session.previous_key = Some(Secret::new(session_key("a")));
+    })
+    .await;
+    let response = app.login_as(&app.test_user).await;
+    let set_cookie = response
+        .headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .filter_map(|h| Cookie::parse_encoded(h.to_str().unwrap().to_string()).ok())
+        .find(|c| c.name() == SESSION_COOKIE_NAME)
+        .expect("No session cookie after logging in");
+
+    let mut jar = CookieJar::new();
+    let decrypted = jar
+        .private(&Key::from(session_key("b").as_bytes()))
+        .decrypt(set_cookie)
+        .unwrap();
+    jar.private_mut(&Key::from(key.as_bytes())).add(decrypted);
+    // Only `name=value`, as browsers send it
+    let reencrypted = jar.get(SESSION_COOKIE_NAME).unwrap().encoded().stripped().to_string();
+    (app, reencrypted)
+}
+
+async fn get_dashboard_with_session_cookie(app: &TestApp, cookie: &str) -> reqwest::Response {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(format!("{}/admin/dashboard", &app.address))
+        .header("Cookie", cookie)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn sessions_from_the_previous_key_survive_a_rotation() {
+    // Arrange
+    let (app, cookie) = log_in_and_reencrypt_session_cookie(&session_key("a")).await;
+
+    // Act
+    let response = get_dashboard_with_session_cookie(&app, &cookie).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn sessions_from_an_unknown_key_are_rejected() {
+    // Arrange
+    let (app, cookie) = log_in_and_reencrypt_session_cookie(&session_key("c")).await;
+
+    // Act
+    let response = get_dashboard_with_session_cookie(&app, &cookie).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,244 @@
+use crate::helpers::{spawn_app, spawn_app_with, unique_ip, TestApp};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+// Post a sign-up as if it came from `ip`, so that tests don't share IP counters.
+async fn post_subscriptions_from(app: &TestApp, ip: &str, body: &serde_json::Value) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("X-Forwarded-For", ip)
+        .json(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn unique_email() -> String {
+    format!("{}@example.com", Uuid::new_v4())
+}
+
+// Brute force a nonce, the way the sign-up form's JavaScript would.
+fn solve(challenge: &str, difficulty: u32) -> String {
+    (0u64..)
+        .map(|nonce| nonce.to_string())
+        .find(|nonce| {
+            let digest = Sha256::digest(format!("{}:{}", challenge, nonce).as_bytes());
+            let value = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+            value.leading_zeros() >= difficulty
+        })
+        .unwrap()
+}
+
+async fn subscriber_count(app: &TestApp) -> i64 {
+    sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn a_filled_in_honeypot_is_accepted_but_nothing_is_stored_or_sent() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": unique_email(),
+            "website": "https://cheap-pills.example.com",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn addresses_from_blocked_domains_and_their_subdomains_are_rejected() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.sign_up_protection.blocked_email_domains = vec!["Mailinator.com".into()]
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let test_cases = vec![
+        ("ursula@mailinator.com", 400),
+        ("ursula@eu.MAILINATOR.com", 400),
+        ("ursula@notmailinator.com", 200),
+    ];
+
+    for (email, expected_status) in test_cases {
+        // Act
+        let response = app
+            .post_subscriptions_json(&serde_json::json!({"name": "le guin", "email": email}))
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), expected_status, "Unexpected outcome for {}.", email);
+        if expected_status == 400 {
+            let errors: serde_json::Value = response.json().await.unwrap();
+            assert_eq!(errors["errors"][0]["code"], "disposable_domain");
+        }
+    }
+}
+
+#[tokio::test]
+async fn too_many_attempts_from_the_same_ip_are_rejected_with_a_429() {
+    // Arrange
+    let app = spawn_app_with(|c| c.sign_up_protection.max_attempts_per_ip = 2).await;
+    let ip = unique_ip();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let mut statuses = Vec::new();
+    for _ in 0..3 {
+        let body = serde_json::json!({"name": "le guin", "email": unique_email()});
+        statuses.push(post_subscriptions_from(&app, &ip, &body).await.status().as_u16());
+    }
+
+    // Assert
+    assert_eq!(statuses, vec![200, 200, 429]);
+    assert_eq!(subscriber_count(&app).await, 2);
+}
+
+#[tokio::test]
+async fn a_spoofed_forwarded_for_does_not_change_the_rate_limit_bucket() {
+    // Arrange
+    let app = spawn_app_with(|c| c.sign_up_protection.max_attempts_per_ip = 2).await;
+    // The address our proxy saw, appended to whatever the client sent
+    let ip = unique_ip();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let mut statuses = Vec::new();
+    for _ in 0..3 {
+        let forwarded_for = format!("{}, {}", unique_ip(), ip);
+        let body = serde_json::json!({"name": "le guin", "email": unique_email()});
+        statuses.push(post_subscriptions_from(&app, &forwarded_for, &body).await.status().as_u16());
+    }
+
+    // Assert
+    assert_eq!(statuses, vec![200, 200, 429]);
+    assert_eq!(subscriber_count(&app).await, 2);
+}
+
+#[tokio::test]
+async fn too_many_attempts_for_the_same_address_are_rejected_with_a_429() {
+    // Arrange
+    let app = spawn_app_with(|c| c.sign_up_protection.max_attempts_per_email = 1).await;
+    let email = unique_email();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let body = serde_json::json!({"name": "le guin", "email": email});
+    let first = app.post_subscriptions_json(&body).await;
+    // Coming from somewhere else does not help
+    let second = post_subscriptions_from(&app, "192.0.2.1", &body).await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn the_challenge_endpoint_is_not_found_when_proof_of_work_is_disabled() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_subscription_challenge().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn subscribe_requires_a_proof_of_work_when_enabled() {
+    // Arrange
+    let app = spawn_app_with(|c| c.sign_up_protection.proof_of_work_difficulty = Some(8)).await;
+    let challenge: serde_json::Value = app.get_subscription_challenge().await.json().await.unwrap();
+    let test_cases = vec![
+        (serde_json::json!({}), "no solution"),
+        (
+            serde_json::json!({"pow_challenge": challenge["challenge"], "pow_nonce": "not-a-solution"}),
+            "wrong nonce",
+        ),
+        (
+            serde_json::json!({"pow_challenge": "1.forged.signature", "pow_nonce": "0"}),
+            "forged challenge",
+        ),
+    ];
+
+    for (mut body, description) in test_cases {
+        body["name"] = "le guin".into();
+        body["email"] = unique_email().into();
+
+        // Act
+        let response = app.post_subscriptions_json(&body).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400, "Accepted a sign-up with {}.", description);
+        let errors: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(errors["errors"][0]["field"], "proof_of_work");
+    }
+}
+
+#[tokio::test]
+async fn a_solved_challenge_can_only_be_used_once() {
+    // Arrange
+    let app = spawn_app_with(|c| c.sign_up_protection.proof_of_work_difficulty = Some(8)).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let challenge: serde_json::Value = app.get_subscription_challenge().await.json().await.unwrap();
+    assert_eq!(challenge["difficulty"], 8);
+    let challenge = challenge["challenge"].as_str().unwrap();
+    let nonce = solve(challenge, 8);
+
+    // Act
+    let mut statuses = Vec::new();
+    for _ in 0..2 {
+        let body = serde_json::json!({
+            "name": "le guin",
+            "email": unique_email(),
+            "pow_challenge": challenge,
+            "pow_nonce": nonce,
+        });
+        statuses.push(app.post_subscriptions_json(&body).await.status().as_u16());
+    }
+
+    // Assert
+    assert_eq!(statuses, vec![200, 400]);
+}
@@ -1,4 +1,5 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, MARKETING_ORIGIN};
+use zero2prod::domain::SubscriptionStatus;
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
 
@@ -37,7 +38,7 @@ async fn subscribe_persists_the_new_subscriber() {
     // Act
     app.post_subscriptions(body.into()).await;
 
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
+    let saved = sqlx::query!(r#"SELECT email, name, status AS "status: SubscriptionStatus" FROM subscriptions"#,)
         .fetch_one(&app.db_pool)
         .await
         .expect("Failed to fecth saved subscription.");
@@ -45,7 +46,7 @@ async fn subscribe_persists_the_new_subscriber() {
     // Assert
     assert_eq!(saved.name, "Alfredo");
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
-    assert_eq!(saved.status, "pending_confirmation");
+    assert_eq!(saved.status, SubscriptionStatus::Pending);
 }
 
 #[tokio::test]
@@ -161,4 +162,255 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
 
     // Assert
     assert_eq!(response.status().as_u16(), 500);
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn subscribe_accepts_json_bodies() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com"
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT email, name FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.name, "le guin");
+}
+
+#[tokio::test]
+async fn subscribe_reports_each_invalid_field_with_a_reason_code() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "<script>",
+            "email": "definitely-not-an-email"
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let errors = body["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0]["field"], "name");
+    assert_eq!(errors[0]["code"], "forbidden_characters");
+    assert_eq!(errors[1]["field"], "email");
+    assert_eq!(errors[1]["code"], "invalid_format");
+}
+
+#[tokio::test]
+async fn missing_form_fields_are_reported_as_empty() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_subscriptions("name=Alfredo".into()).await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "email");
+    assert_eq!(body["errors"][0]["code"], "empty");
+}
+
+#[tokio::test]
+async fn subscribe_allows_cross_origin_requests_from_configured_origins() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .request(reqwest::Method::OPTIONS, format!("{}/subscriptions", &app.address))
+        .header("Origin", MARKETING_ORIGIN)
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "content-type")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert!(response.status().is_success());
+    assert_eq!(
+        response.headers()["Access-Control-Allow-Origin"],
+        MARKETING_ORIGIN
+    );
+}
+
+#[tokio::test]
+async fn subscribe_does_not_grant_cors_to_unknown_origins() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Origin", "https://evil.example.com")
+        .json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com"
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert!(response.headers().get("Access-Control-Allow-Origin").is_none());
+}
+
+#[tokio::test]
+async fn subscribe_stores_the_domain_in_canonical_form() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions_json(&serde_json::json!({"name": "le guin", "email": "Ursula@Bücher.EXAMPLE"}))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let saved = sqlx::query!("SELECT email FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "Ursula@xn--bcher-kva.example");
+}
+
+#[tokio::test]
+async fn addresses_differing_only_by_case_are_the_same_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let first = app.post_subscriptions("name=le%20guin&email=ursula%40gmail.com".into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.get_confirmation_links(email_request).html).await.unwrap();
+
+    // Act
+    let second = app.post_subscriptions("name=le%20guin&email=URSULA%40gmail.com".into()).await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    // The same answer as for a new subscriber, and no second confirmation email
+    assert_eq!(second.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 1);
+}
+
+#[tokio::test]
+async fn signing_up_again_while_pending_sends_a_new_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula%40gmail.com".into()).await;
+
+    // Act
+    let response = app.post_subscriptions("name=le%20guin&email=Ursula%40gmail.com".into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let confirmation = reqwest::get(app.get_confirmation_links(email_request).html).await.unwrap();
+    assert_eq!(confirmation.status().as_u16(), 200);
+    let saved = sqlx::query!(r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions"#)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].status, SubscriptionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_can_sign_up_again() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula%40gmail.com".into()).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act - Part 1 - Sign up again
+    let response = app.post_subscriptions("name=le%20guin&email=ursula%40gmail.com".into()).await;
+
+    // Assert - Part 1
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!(r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::Pending);
+
+    // Act - Part 2 - Follow the new confirmation link
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    reqwest::get(app.get_confirmation_links(email_request).html).await.unwrap();
+
+    // Assert - Part 2
+    let saved = sqlx::query!(r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::Confirmed);
+    let history = sqlx::query!(
+        r#"SELECT from_status AS "from_status: SubscriptionStatus", to_status AS "to_status: SubscriptionStatus"
+        FROM subscription_status_history ORDER BY changed_at"#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(history.last().unwrap().from_status, Some(SubscriptionStatus::Pending));
+    assert!(history
+        .iter()
+        .any(|h| h.from_status == Some(SubscriptionStatus::Unsubscribed) && h.to_status == SubscriptionStatus::Pending));
+}
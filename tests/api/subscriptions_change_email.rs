@@ -0,0 +1,206 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+// Subscribe `email` and return the subscription token sent in the confirmation link.
+async fn create_subscriber(app: &TestApp, email: &str) -> String {
+    let body = format!("name=le%20guin&email={}", email.replace('@', "%40"));
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(email_request);
+    confirmation_links
+        .html
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap()
+}
+
+async fn stored_email(app: &TestApp) -> String {
+    sqlx::query!("SELECT email FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.")
+        .email
+}
+
+#[tokio::test]
+async fn change_email_sends_a_confirmation_to_the_new_address() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_subscriber(&app, "ursula_le_guin@gmail.com").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let body = format!("subscription_token={}&new_email=ursula%40new-job.com", token);
+    let response = app.post_change_email(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "ursula@new-job.com");
+}
+
+#[tokio::test]
+async fn the_email_is_only_swapped_once_the_change_is_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_subscriber(&app, "ursula_le_guin@gmail.com").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Request the change
+    let body = format!("subscription_token={}&new_email=ursula%40new-job.com", token);
+    app.post_change_email(body).await.error_for_status().unwrap();
+    assert_eq!(stored_email(&app).await, "ursula_le_guin@gmail.com");
+
+    // Act - Part 2 - Follow the link sent to the new address
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(stored_email(&app).await, "ursula@new-job.com");
+
+    // The old address is told about the change
+    let notification = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&notification.body).unwrap();
+    assert_eq!(body["To"], "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn expired_email_change_links_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_subscriber(&app, "ursula_le_guin@gmail.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = format!("subscription_token={}&new_email=ursula%40new-job.com", token);
+    app.post_change_email(body).await.error_for_status().unwrap();
+    sqlx::query!("UPDATE email_change_requests SET requested_at = now() - interval '25 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(stored_email(&app).await, "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn the_change_succeeds_even_if_the_old_address_cannot_be_notified() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_subscriber(&app, "ursula_le_guin@gmail.com").await;
+    let confirmation_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let body = format!("subscription_token={}&new_email=ursula%40new-job.com", token);
+    app.post_change_email(body).await.error_for_status().unwrap();
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    drop(confirmation_guard);
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(stored_email(&app).await, "ursula@new-job.com");
+}
+
+#[tokio::test]
+async fn change_email_rejects_an_address_belonging_to_another_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_subscriber(&app, "ursula_le_guin@gmail.com").await;
+    create_subscriber(&app, "taken@gmail.com").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let body = format!("subscription_token={}&new_email=taken%40gmail.com", token);
+    let response = app.post_change_email(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn change_email_with_an_unknown_token_is_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_change_email("subscription_token=unknown&new_email=ursula%40new-job.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn change_email_returns_a_400_for_an_invalid_address() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_subscriber(&app, "ursula_le_guin@gmail.com").await;
+
+    // Act
+    let body = format!("subscription_token={}&new_email=definitely-not-an-email", token);
+    let response = app.post_change_email(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
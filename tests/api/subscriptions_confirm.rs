@@ -1,4 +1,5 @@
 use crate::helpers::spawn_app;
+use zero2prod::domain::SubscriptionStatus;
 use wiremock::{ResponseTemplate, Mock};
 use wiremock::matchers::{path, method};
 
@@ -65,14 +66,64 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber(){
         .unwrap();
 
     // Assert
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
+    let saved = sqlx::query!(
+        r#"SELECT email, name, status AS "status: SubscriptionStatus", confirmed_at FROM subscriptions"#,
+    )
         .fetch_one(&app.db_pool)
         .await
         .expect("Failed to fetch saved subscription.");
 
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
     assert_eq!(saved.name, "Alfredo");
-    assert_eq!(saved.status, "confirmed");
+    assert_eq!(saved.status, SubscriptionStatus::Confirmed);
+    assert!(saved.confirmed_at.is_some());
+
+    // Both the sign up and the confirmation are part of the history
+    let history = sqlx::query!(
+        r#"
+        SELECT from_status AS "from_status: SubscriptionStatus", to_status AS "to_status: SubscriptionStatus"
+        FROM subscription_status_history
+        ORDER BY changed_at
+        "#,
+    )
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let history: Vec<_> = history.into_iter().map(|r| (r.from_status, r.to_status)).collect();
+    assert_eq!(
+        history,
+        vec![
+            (None, SubscriptionStatus::Pending),
+            (Some(SubscriptionStatus::Pending), SubscriptionStatus::Confirmed),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn confirmation_links_of_deleted_subscribers_are_rejected_with_a_409() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=Alfredo&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    sqlx::query!("UPDATE subscriptions SET status = 'deleted'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
 }
 
 #[tokio::test]
@@ -0,0 +1,193 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp, TestUser};
+use chrono::Utc;
+use zero2prod::authentication::{totp_code, Role};
+
+// Enroll `user` in two-factor authentication, returning their secret and recovery codes.
+// The enrollment code is the current one, so log in with `code_for_next_step`.
+async fn enroll(app: &TestApp, user: &TestUser) -> (String, Vec<String>) {
+    app.login_as(user).await;
+    let html_page = app.get_two_factor_html().await;
+    let secret = html_page
+        .split(r#"<code id="totp-secret">"#)
+        .nth(1)
+        .and_then(|rest| rest.split("</code>").next())
+        .expect("The enrollment page does not show the secret")
+        .to_string();
+    let code = totp_code(&secret, Utc::now().timestamp() as u64).unwrap();
+    let response = app.post_two_factor("", &serde_json::json!({ "code": code })).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    let recovery_codes = html_page
+        .split("<li><code>")
+        .skip(1)
+        .map(|rest| rest.split("</code>").next().unwrap().to_string())
+        .collect();
+    app.post_logout().await;
+    (secret, recovery_codes)
+}
+
+fn code_for_next_step(secret: &str) -> String {
+    totp_code(secret, Utc::now().timestamp() as u64 + 30).unwrap()
+}
+
+#[tokio::test]
+async fn users_with_two_factor_must_enter_a_code_after_their_password() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, recovery_codes) = enroll(&app, &app.test_user).await;
+    assert_eq!(recovery_codes.len(), 10);
+
+    // Act - Part 1 - The password alone is not enough
+    let response = app.login_as(&app.test_user).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Enter the code
+    let response = app.post_login_two_factor(&code_for_next_step(&secret)).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Assert
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn an_invalid_code_does_not_log_the_user_in() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, _) = enroll(&app, &app.test_user).await;
+    app.login_as(&app.test_user).await;
+    let stale_code = totp_code(&secret, Utc::now().timestamp() as u64 - 3600).unwrap();
+
+    // Act
+    let response = app.post_login_two_factor(&stale_code).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/two_factor");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn codes_cannot_be_replayed() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, _) = enroll(&app, &app.test_user).await;
+    let code = code_for_next_step(&secret);
+    app.login_as(&app.test_user).await;
+    let response = app.post_login_two_factor(&code).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    // Act
+    app.login_as(&app.test_user).await;
+    let response = app.post_login_two_factor(&code).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/two_factor");
+}
+
+#[tokio::test]
+async fn recovery_codes_can_only_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let (_, recovery_codes) = enroll(&app, &app.test_user).await;
+
+    // Act - Part 1 - Log in with a recovery code
+    app.login_as(&app.test_user).await;
+    let response = app.post_login_two_factor(&recovery_codes[0]).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    // Act - Part 2 - Try it again
+    app.login_as(&app.test_user).await;
+    let response = app.post_login_two_factor(&recovery_codes[0]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/two_factor");
+}
+
+#[tokio::test]
+async fn too_many_invalid_codes_require_logging_in_again() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, _) = enroll(&app, &app.test_user).await;
+    app.login_as(&app.test_user).await;
+    for _ in 0..5 {
+        app.post_login_two_factor("not-a-code").await;
+    }
+
+    // Act
+    let response = app.post_login_two_factor(&code_for_next_step(&secret)).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn invalid_codes_across_new_logins_end_in_a_lockout() {
+    // Arrange
+    let app = spawn_app_with(|c| c.login_protection.failures_before_lockout_per_username = 3).await;
+    let (secret, _) = enroll(&app, &app.test_user).await;
+    for _ in 0..3 {
+        let response = app.login_as(&app.test_user).await;
+        assert_is_redirect_to(&response, "/login/two_factor");
+        app.post_login_two_factor("not-a-code").await;
+    }
+
+    // Act - Part 1 - The right password no longer leads to the second step
+    let response = app.login_as(&app.test_user).await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Too many failed login attempts, please try again later.</i></p>"));
+
+    // Act - Part 2 - Nor does the right code help
+    let response = app.post_login_two_factor(&code_for_next_step(&secret)).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn owners_can_require_two_factor_for_everyone() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = app.add_user(Role::Editor).await;
+    app.login_as(&app.test_user).await;
+    let response = app
+        .post_admin_users("/two_factor", &serde_json::json!({ "required": true }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    app.post_logout().await;
+
+    // Act
+    app.login_as(&editor).await;
+    let response = app.get_admin_dashboard().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/two_factor");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("Two-factor authentication is required, please set it up to continue."));
+    assert!(html_page.contains(r#"<code id="totp-secret">"#));
+}
+
+#[tokio::test]
+async fn non_owners_cannot_change_the_two_factor_requirement() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = app.add_user(Role::Editor).await;
+    app.login_as(&editor).await;
+
+    // Act
+    let response = app
+        .post_admin_users("/two_factor", &serde_json::json!({ "required": true }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}